@@ -0,0 +1,69 @@
+//! Measures the fallback path: several failing providers attempted one by
+//! one before a working one.
+//!
+//! Run with `cargo bench --features mock`.
+
+extern crate urlshortener;
+
+use std::time::{Duration, Instant};
+use urlshortener::client::UrlShortener;
+use urlshortener::mock::{MockResponse, MockServer};
+use urlshortener::providers::Provider;
+
+const ITERATIONS: u32 = 200;
+
+fn kutt(server: &MockServer) -> Provider {
+    Provider::Kutt {
+        api_key: "key".to_owned(),
+        host: Some(server.address().to_owned()),
+    }
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    for _ in 0..10 {
+        f();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<24} {:>10.1?}/iter",
+        name,
+        Duration::from_nanos((elapsed.as_nanos() / u128::from(ITERATIONS)) as u64)
+    );
+}
+
+fn main() {
+    let servers: Vec<MockServer> = vec![
+        MockResponse::new(503, "Service Unavailable"),
+        MockResponse::new(200, "<html>Please enter a valid URL</html>"),
+        MockResponse::new(200, vec![b'x'; 512 * 1024]),
+        MockResponse::json("shortUrl", "https://kutt.it/abc"),
+    ]
+    .into_iter()
+    .map(|res| MockServer::with_response(res).unwrap())
+    .collect();
+    let providers: Vec<Provider> = servers.iter().map(kutt).collect();
+
+    let us = UrlShortener::builder()
+        .max_response_size(64 * 1024)
+        .build()
+        .unwrap();
+
+    bench("fallback", || {
+        let res = providers
+            .iter()
+            .find_map(|provider| us.generate("https://example.com", provider).ok());
+        assert_eq!(res.as_deref(), Some("https://kutt.it/abc"));
+    });
+
+    for (name, provider) in ["status", "rejection", "too large"].iter().zip(&providers) {
+        bench(name, || {
+            assert!(us.generate("https://example.com", provider).is_err());
+        });
+    }
+}
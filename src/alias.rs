@@ -0,0 +1,143 @@
+//! Generators of custom aliases, for the providers supporting them (see
+//! [`crate::providers::Provider::alias_rules`]).
+
+use crate::rng::{Rng, XorShift};
+
+/// The characters of the base62 aliases.
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Produces a custom alias for each URL being shortened.
+pub trait AliasGenerator {
+    /// Returns the alias for the URL.
+    fn next(&mut self, url: &str) -> String;
+}
+
+impl<F: FnMut(&str) -> String> AliasGenerator for F {
+    fn next(&mut self, url: &str) -> String {
+        self(url)
+    }
+}
+
+/// Generates random aliases of ASCII letters and digits.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::alias::{AliasGenerator, RandomBase62};
+///
+/// let mut aliases = RandomBase62::new(8);
+/// let alias = aliases.next("https://rust-lang.org");
+/// assert_eq!(alias.len(), 8);
+/// assert!(alias.chars().all(|c| c.is_ascii_alphanumeric()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RandomBase62 {
+    length: usize,
+    rng: XorShift,
+}
+
+impl RandomBase62 {
+    /// Creates a generator of random aliases of the given length.
+    pub fn new(length: usize) -> RandomBase62 {
+        RandomBase62 {
+            length,
+            rng: XorShift::new(),
+        }
+    }
+
+    /// Creates a generator of random aliases of the given length, from the
+    /// given seed. The same seed always gives the same aliases.
+    pub fn with_seed(length: usize, seed: u64) -> RandomBase62 {
+        RandomBase62 {
+            length,
+            rng: XorShift::with_seed(seed),
+        }
+    }
+}
+
+impl AliasGenerator for RandomBase62 {
+    fn next(&mut self, _url: &str) -> String {
+        (0..self.length)
+            .map(|_| BASE62[(self.rng.next_u64() % BASE62.len() as u64) as usize] as char)
+            .collect()
+    }
+}
+
+/// Generates sequential aliases made of a prefix and an incrementing number.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::alias::{AliasGenerator, Counter};
+///
+/// let mut aliases = Counter::new("link_", 1);
+/// assert_eq!(aliases.next("https://rust-lang.org"), "link_1");
+/// assert_eq!(aliases.next("https://crates.io"), "link_2");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Counter {
+    prefix: String,
+    next: u64,
+}
+
+impl Counter {
+    /// Creates a generator of aliases starting with the prefix, counting
+    /// from `start`.
+    pub fn new(prefix: &str, start: u64) -> Counter {
+        Counter {
+            prefix: prefix.to_owned(),
+            next: start,
+        }
+    }
+}
+
+impl AliasGenerator for Counter {
+    fn next(&mut self, _url: &str) -> String {
+        let alias = format!("{}{}", self.prefix, self.next);
+        self.next += 1;
+        alias
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::Provider;
+    use std::collections::HashSet;
+
+    #[test]
+    fn random_base62() {
+        let mut generator = RandomBase62::new(10);
+        let rules = Provider::IsGd.alias_rules().unwrap();
+
+        let aliases: Vec<String> = (0..1000)
+            .map(|i| generator.next(&format!("http://example.com/{}", i)))
+            .collect();
+
+        let unique: HashSet<_> = aliases.iter().collect();
+        assert_eq!(unique.len(), aliases.len());
+        for alias in &aliases {
+            assert_eq!(rules.check(alias), Ok(()));
+        }
+    }
+
+    #[test]
+    fn seeded_random_base62() {
+        let aliases = |seed| {
+            let mut generator = RandomBase62::with_seed(6, seed);
+            (0..10).map(|_| generator.next("")).collect::<Vec<_>>()
+        };
+        assert_eq!(aliases(1), aliases(1));
+        assert_ne!(aliases(1), aliases(2));
+    }
+
+    #[test]
+    fn counter() {
+        let mut generator = Counter::new("x", 9);
+        assert_eq!(generator.next(""), "x9");
+        assert_eq!(generator.next(""), "x10");
+
+        let mut slug = |url: &str| url.rsplit('/').next().unwrap_or_default().to_owned();
+        assert_eq!(slug.next("http://example.com/my_page"), "my_page");
+    }
+}
@@ -0,0 +1,263 @@
+//! Strategies of the delays between the retries of a provider (see
+//! [`crate::client::UrlShortener::generate_with_backoff`]).
+
+use crate::rng::{Rng, XorShift};
+use std::time::Duration;
+
+/// Computes the delay before each retry.
+pub trait Backoff {
+    /// Returns the delay before the retry following the given attempt, the
+    /// first attempt being `0`.
+    fn delay(&self, attempt: u32) -> Duration;
+
+    /// Returns the delay before the retry following the given attempt, like
+    /// [`Backoff::delay`], knowing the delay returned for the previous
+    /// attempt, if any. A strategy deriving each delay from the previous one
+    /// computes it in constant time this way, so that a retry loop carrying
+    /// the previous delay does not recompute the whole chain.
+    fn next_delay(&self, attempt: u32, _previous: Option<Duration>) -> Duration {
+        self.delay(attempt)
+    }
+}
+
+impl<F: Fn(u32) -> Duration> Backoff for F {
+    fn delay(&self, attempt: u32) -> Duration {
+        self(attempt)
+    }
+}
+
+/// Waits the same delay before every retry.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use urlshortener::backoff::{Backoff, FixedBackoff};
+///
+/// let backoff = FixedBackoff::new(Duration::from_millis(100));
+/// assert_eq!(backoff.delay(0), Duration::from_millis(100));
+/// assert_eq!(backoff.delay(5), Duration::from_millis(100));
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FixedBackoff {
+    delay: Duration,
+}
+
+impl FixedBackoff {
+    /// Creates a strategy waiting the given delay before every retry.
+    pub fn new(delay: Duration) -> FixedBackoff {
+        FixedBackoff { delay }
+    }
+}
+
+impl Backoff for FixedBackoff {
+    fn delay(&self, _attempt: u32) -> Duration {
+        self.delay
+    }
+}
+
+/// Doubles the delay before each next retry, up to an optional maximum.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use urlshortener::backoff::{Backoff, ExponentialBackoff};
+///
+/// let backoff = ExponentialBackoff::new(Duration::from_millis(100)).max(Duration::from_secs(1));
+/// assert_eq!(backoff.delay(0), Duration::from_millis(100));
+/// assert_eq!(backoff.delay(2), Duration::from_millis(400));
+/// assert_eq!(backoff.delay(10), Duration::from_secs(1));
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ExponentialBackoff {
+    base: Duration,
+    max: Option<Duration>,
+}
+
+impl ExponentialBackoff {
+    /// Creates a strategy waiting `base` before the first retry, and twice
+    /// the previous delay before each next one.
+    pub fn new(base: Duration) -> ExponentialBackoff {
+        ExponentialBackoff { base, max: None }
+    }
+
+    /// Sets the maximum delay.
+    pub fn max(mut self, max: Duration) -> ExponentialBackoff {
+        self.max = Some(max);
+        self
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = 2u32
+            .checked_pow(attempt)
+            .and_then(|factor| self.base.checked_mul(factor))
+            .unwrap_or(Duration::MAX);
+        match self.max {
+            Some(max) => delay.min(max),
+            None => delay,
+        }
+    }
+}
+
+/// Picks each delay at random between the base delay and three times the
+/// previous delay, up to a maximum, as the "decorrelated jitter" strategy
+/// does. This spreads the retries of many clients failing at once.
+///
+/// The delays are derived from a seed, so the same seed always gives the
+/// same delays.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use urlshortener::backoff::{Backoff, DecorrelatedJitter};
+///
+/// let (base, max) = (Duration::from_millis(100), Duration::from_secs(5));
+/// let backoff = DecorrelatedJitter::new(base, max);
+/// let delay = backoff.delay(3);
+/// assert!(base <= delay && delay <= max);
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DecorrelatedJitter {
+    base: Duration,
+    max: Duration,
+    seed: u64,
+}
+
+impl DecorrelatedJitter {
+    /// Creates a strategy with delays between `base` and `max`, from a
+    /// random seed.
+    pub fn new(base: Duration, max: Duration) -> DecorrelatedJitter {
+        DecorrelatedJitter::with_rng(base, max, &mut XorShift::new())
+    }
+
+    /// Creates a strategy with delays between `base` and `max`, from a seed
+    /// drawn from `rng`.
+    pub fn with_rng<R: Rng>(base: Duration, max: Duration, rng: &mut R) -> DecorrelatedJitter {
+        DecorrelatedJitter::with_seed(base, max, rng.next_u64())
+    }
+
+    /// Creates a strategy with delays between `base` and `max`, from the
+    /// given seed.
+    pub fn with_seed(base: Duration, max: Duration, seed: u64) -> DecorrelatedJitter {
+        DecorrelatedJitter { base, max, seed }
+    }
+}
+
+impl DecorrelatedJitter {
+    /// Picks the delay of the given attempt from the previous one, in
+    /// nanoseconds.
+    fn step(&self, attempt: u64, previous: u64) -> u64 {
+        let (base, max) = (nanos(self.base), nanos(self.max));
+        let upper = previous.saturating_mul(3).min(max).max(base);
+        let span = (upper - base).saturating_add(1);
+        base + splitmix64(self.seed.wrapping_add(attempt)) % span
+    }
+}
+
+impl Backoff for DecorrelatedJitter {
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = (0..=u64::from(attempt)).fold(nanos(self.base), |delay, i| self.step(i, delay));
+        Duration::from_nanos(delay.min(nanos(self.max)))
+    }
+
+    fn next_delay(&self, attempt: u32, previous: Option<Duration>) -> Duration {
+        match previous {
+            Some(previous) if attempt > 0 => {
+                let delay = self.step(u64::from(attempt), nanos(previous));
+                Duration::from_nanos(delay.min(nanos(self.max)))
+            }
+            _ => self.delay(attempt),
+        }
+    }
+}
+
+/// Returns the duration in nanoseconds, saturating at `u64::MAX`.
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().min(u128::from(u64::MAX)) as u64
+}
+
+/// Scrambles the number, using the splitmix64 finalizer.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delays<B: Backoff>(backoff: &B) -> Vec<Duration> {
+        (0..6).map(|attempt| backoff.delay(attempt)).collect()
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn fixed() {
+        assert_eq!(delays(&FixedBackoff::new(ms(250))), vec![ms(250); 6]);
+    }
+
+    #[test]
+    fn exponential() {
+        assert_eq!(
+            delays(&ExponentialBackoff::new(ms(100))),
+            vec![ms(100), ms(200), ms(400), ms(800), ms(1600), ms(3200)]
+        );
+        assert_eq!(
+            delays(&ExponentialBackoff::new(ms(100)).max(ms(500))),
+            vec![ms(100), ms(200), ms(400), ms(500), ms(500), ms(500)]
+        );
+        assert_eq!(ExponentialBackoff::new(ms(100)).delay(64), Duration::MAX);
+    }
+
+    #[test]
+    fn decorrelated_jitter() {
+        let backoff = DecorrelatedJitter::with_seed(ms(100), ms(2000), 42);
+        let sequence = delays(&backoff);
+
+        assert_eq!(
+            sequence,
+            delays(&DecorrelatedJitter::with_seed(ms(100), ms(2000), 42))
+        );
+        assert_ne!(
+            sequence,
+            delays(&DecorrelatedJitter::with_seed(ms(100), ms(2000), 43))
+        );
+
+        let mut previous = ms(100);
+        for &delay in &sequence {
+            assert!(ms(100) <= delay && delay <= ms(2000));
+            assert!(delay <= previous * 3);
+            previous = delay;
+        }
+
+        // Carrying the previous delay gives the same sequence.
+        for (base, max) in [(ms(100), ms(2000)), (ms(100), ms(50))] {
+            let backoff = DecorrelatedJitter::with_seed(base, max, 7);
+            let mut previous = None;
+            for attempt in 0..40 {
+                let delay = backoff.next_delay(attempt, previous);
+                assert_eq!(delay, backoff.delay(attempt), "{}", attempt);
+                previous = Some(delay);
+            }
+        }
+        assert_eq!(
+            FixedBackoff::new(ms(250)).next_delay(3, Some(ms(1))),
+            ms(250)
+        );
+    }
+
+    #[test]
+    fn closure() {
+        let linear = |attempt: u32| ms(100) * (attempt + 1);
+        assert_eq!(linear.delay(2), ms(300));
+    }
+}
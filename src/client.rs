@@ -1,160 +1,7282 @@
-use crate::providers::{self, parse, request, ProviderError};
-use reqwest::blocking::{Client, ClientBuilder};
-use std::time::Duration;
+use crate::alias::AliasGenerator;
+use crate::backoff::{Backoff, DecorrelatedJitter, ExponentialBackoff};
+use crate::providers::{self, parse, request, ProviderError, ProviderKind, ProviderTier};
+use crate::request::Request;
+use crate::rng::{Rng, XorShift};
+use crate::target;
+use reqwest::blocking::{Client, ClientBuilder, RequestBuilder, Response};
+use reqwest::{header, redirect};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::mem::{self, Discriminant};
+use std::net::{IpAddr, SocketAddr};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-/// Url shortener: the way to retrieve a short url.
+/// The default timeout of the requests which are not sent to a provider.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// The default limit of a provider response body size, in bytes.
+const DEFAULT_MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+/// The maximum number of redirects followed when verifying a short URL.
+const MAX_VERIFY_REDIRECTS: usize = 10;
+/// The default service rendering the QR codes of the short URLs.
+const DEFAULT_QR_SERVICE: &str = "https://api.qrserver.com/v1/create-qr-code/";
+/// The most short URLs kept by [`UrlShortener::generate_prefetching`].
+const MAX_PREFETCHED: usize = 1024;
+/// How long the short URLs prefetched by
+/// [`UrlShortener::generate_prefetching`] are kept.
+const PREFETCH_TTL: Duration = Duration::from_secs(60 * 60);
+/// The signature starting every PNG image.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A shared user callback.
+struct Callback<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Callback(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+/// A callback observing the provider attempts.
+type AttemptHook = dyn Fn(&AttemptEvent<'_>) + Send + Sync;
+/// A callback receiving the audit records.
+type AuditHook = dyn Fn(&AuditRecord) + Send + Sync;
+/// A parser replacing the built-in one of a provider.
+type Parser = dyn Fn(&str) -> Option<String> + Send + Sync;
+/// A resolver of the hosts of the requests.
+type Resolver = dyn Fn(&str) -> Vec<IpAddr> + Send + Sync;
+/// Builds the HTTP clients, with the host resolved to the addresses if any.
+type ClientsBuilder =
+    dyn Fn(Option<(&str, &[SocketAddr])>) -> Result<Clients, reqwest::Error> + Send + Sync;
+/// A callback modifying every provider request before it is sent.
+type Interceptor = dyn Fn(&mut reqwest::blocking::Request) + Send + Sync;
+/// A builder of the batch requests of a provider.
+type BatchRequest = dyn Fn(&[String]) -> Request + Send + Sync;
+/// A parser of the batch responses of a provider.
+type BatchParser = dyn Fn(&str) -> Option<Vec<String>> + Send + Sync;
+/// The source of the random numbers of a client and its clones.
+type SharedRng = Mutex<dyn Rng + Send>;
+
+/// A callback told about the links a [`LinkMonitor`] has found broken.
+type BreakageHook = dyn Fn(&ShortUrl) + Send + Sync;
+/// The source of the current time of a [`LinkMonitor`].
+type Clock = dyn Fn() -> Instant + Send + Sync;
+
+/// Extracts the token from the page fetched by a [`TokenStep`].
+type TokenExtractor = dyn Fn(&str) -> Option<String> + Send + Sync;
+/// Adds the token of a [`TokenStep`] to the request of a provider.
+type TokenApplier = dyn Fn(&mut Request, &str) + Send + Sync;
+
+/// The errors of the providers which have failed, in the order they have
+/// been attempted.
+pub type ProviderErrors = Vec<(providers::Provider, ProviderError)>;
+
+/// Describes the phase of a provider attempt.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AttemptPhase {
+    /// The request to the provider is about to be sent.
+    Started,
+    /// The provider has answered or failed.
+    Finished,
+}
+
+/// An event passed to the attempt hook before and after each provider request.
+#[derive(Debug, Clone)]
+pub struct AttemptEvent<'a> {
+    /// The provider being attempted.
+    pub provider: &'a providers::Provider,
+    /// The phase of the attempt.
+    pub phase: AttemptPhase,
+    /// The time elapsed since the attempt has started.
+    pub elapsed: Duration,
+    /// The result of the attempt, only available when it has finished: the
+    /// short URL, the short URLs separated by spaces for a request to a batch
+    /// endpoint (see [`UrlShortenerBuilder::batch_api`]), or the long URL for
+    /// a request about a short URL, like [`UrlShortener::expand`].
+    pub outcome: Option<Result<&'a str, ProviderError>>,
+}
+
+/// A request which is currently in flight, shared by all the identical
+/// requests made meanwhile.
+#[derive(Debug, Default)]
+struct Flight {
+    result: Mutex<Option<Result<String, ProviderError>>>,
+    done: Condvar,
+}
+
+/// The in-flight requests keyed by the provider and the long URL.
+type Flights = Mutex<HashMap<(providers::Provider, String), Arc<Flight>>>;
+
+/// Completes the flight of its leader when dropped: the identical requests
+/// waiting for it are given its result, or `ProviderError::Connection` if
+/// the leader has panicked, and the flight is removed.
+struct FlightGuard<'a> {
+    flights: &'a Flights,
+    key: (providers::Provider, String),
+    flight: Arc<Flight>,
+    result: Option<Result<String, ProviderError>>,
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        let result = self.result.take().unwrap_or(Err(ProviderError::Connection));
+        // The locks are not held by the panicking code, but a poisoned lock
+        // must not panic again while unwinding.
+        *self
+            .flight
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.flight.done.notify_all();
+        self.flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+/// The short URLs generated in the background by
+/// [`UrlShortener::generate_prefetching`], keyed by the provider and the long
+/// URL, along with when they have been generated.
+///
+/// Up to `MAX_PREFETCHED` short URLs are kept, for `PREFETCH_TTL` at most,
+/// the oldest one being evicted first.
+#[derive(Debug, Default)]
+struct Prefetched {
+    links: Mutex<HashMap<(providers::Provider, String), (String, Instant)>>,
+}
+
+impl Prefetched {
+    /// Keeps the short URL, evicting the expired ones, and the oldest one if
+    /// there are too many.
+    fn insert(&self, key: (providers::Provider, String), short_url: String) {
+        let mut links = self.links.lock().unwrap();
+        let now = Instant::now();
+        links.retain(|_, (_, at)| now.duration_since(*at) < PREFETCH_TTL);
+        if links.len() >= MAX_PREFETCHED && !links.contains_key(&key) {
+            let oldest = links
+                .iter()
+                .min_by_key(|(_, (_, at))| *at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                links.remove(&oldest);
+            }
+        }
+        links.insert(key, (short_url, now));
+    }
+
+    /// Returns the short URL if it has not expired, leaving it in place.
+    fn get(&self, key: &(providers::Provider, String)) -> Option<String> {
+        let links = self.links.lock().unwrap();
+        links
+            .get(key)
+            .filter(|(_, at)| at.elapsed() < PREFETCH_TTL)
+            .map(|(short_url, _)| short_url.clone())
+    }
+
+    /// Removes the short URL, returning it if it has not expired.
+    fn take(&self, key: &(providers::Provider, String)) -> Option<String> {
+        let mut links = self.links.lock().unwrap();
+        links
+            .remove(key)
+            .filter(|(_, at)| at.elapsed() < PREFETCH_TTL)
+            .map(|(short_url, _)| short_url)
+    }
+}
+
+/// The number of requests in flight to each provider, keyed by its name,
+/// for [`UrlShortenerBuilder::max_concurrent_per_provider`].
+#[derive(Debug, Default)]
+struct Slots {
+    in_flight: Mutex<HashMap<String, usize>>,
+    freed: Condvar,
+}
+
+impl Slots {
+    /// Waits until fewer than `max` requests are in flight to the provider,
+    /// then takes a slot, which is given back when the guard is dropped.
+    fn acquire(&self, provider: &providers::Provider, max: usize) -> SlotGuard<'_> {
+        let name = provider.to_name().to_owned();
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while in_flight.get(&name).copied().unwrap_or(0) >= max {
+            in_flight = self.freed.wait(in_flight).unwrap();
+        }
+        *in_flight.entry(name.clone()).or_insert(0) += 1;
+
+        SlotGuard { slots: self, name }
+    }
+}
+
+/// A request slot taken from [`Slots`].
+struct SlotGuard<'a> {
+    slots: &'a Slots,
+    name: String,
+}
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.slots.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.name) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.name);
+            }
+        }
+        self.slots.freed.notify_all();
+    }
+}
+
+/// The most hosts whose HTTP clients are kept by [`Resolving`].
+const MAX_RESOLVED_HOSTS: usize = 64;
+
+/// The HTTP clients of a `UrlShortener`, all configured alike.
+#[derive(Debug, Clone)]
+struct Clients {
+    /// Sends the requests to the providers.
+    provider: Client,
+    /// Sends the requests to the providers giving the short URL as their
+    /// location, without following the redirects.
+    location: Option<Client>,
+    /// Sends the requests which are not sent to a provider, like the
+    /// verifications, without following the redirects.
+    verify: Client,
+}
+
+/// The HTTP clients built for the hosts resolved by the resolver of the
+/// `UrlShortener` (see [`UrlShortenerBuilder::resolver`]), along with the
+/// addresses they connect to.
 #[derive(Debug)]
-pub struct UrlShortener {
-    client: Client,
+struct Resolving {
+    resolver: Callback<Resolver>,
+    build: Callback<ClientsBuilder>,
+    clients: Mutex<HashMap<String, (Vec<IpAddr>, Clients)>>,
 }
 
-impl UrlShortener {
-    /// Creates new `UrlShortener` with default (3 seconds) timeout.
-    pub fn new() -> Result<UrlShortener, reqwest::Error> {
-        UrlShortener::with_timeout(3)
+/// The hosts reached without going through the proxy.
+#[derive(Debug, Clone, Default)]
+struct NoProxy {
+    /// The lowercased domains, each also covering its subdomains, and the IP
+    /// addresses. `*` covers every host.
+    hosts: Vec<String>,
+}
+
+impl NoProxy {
+    /// Adds an entry, like `example.com`, `.example.com` or `*.example.com`
+    /// for a domain and its subdomains, an IP address, or `*` for every host.
+    fn add(&mut self, entry: &str) {
+        let entry = entry.trim().to_lowercase();
+        let entry = match entry.as_str() {
+            "*" => "*",
+            e => e.trim_start_matches("*.").trim_start_matches('.'),
+        };
+        if !entry.is_empty() {
+            self.hosts.push(entry.to_owned());
+        }
     }
 
-    /// Creates new `UrlShortener` with custom read timeout.
-    pub fn with_timeout(seconds: u64) -> Result<UrlShortener, reqwest::Error> {
-        let client = ClientBuilder::new()
-            .timeout(Duration::from_secs(seconds))
-            .build()?;
+    /// Returns whether the host bypasses the proxy.
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        self.hosts
+            .iter()
+            .any(|entry| entry == "*" || target::host_matches(host, entry))
+    }
+}
 
-        Ok(UrlShortener { client })
+/// Describes a request exactly as it would be sent to a provider.
+#[derive(Debug, Clone)]
+pub struct RequestPreview {
+    /// The HTTP method.
+    pub method: reqwest::Method,
+    /// The full URL, including the query.
+    pub url: String,
+    /// All the request headers.
+    pub headers: header::HeaderMap,
+    /// The request body.
+    pub body: Option<String>,
+}
+
+/// A short URL along with the details of its generation, returned by
+/// [`UrlShortener::generate_detailed`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ShortUrl {
+    /// The short URL.
+    pub url: String,
+    /// The provider which has generated the short URL.
+    pub provider: providers::Provider,
+    /// The code of the short URL, if it can be cleanly isolated (see
+    /// [`providers::short_code`]).
+    pub code: Option<String>,
+    /// Whether the provider has created a new short link, or returned the
+    /// existing one of the same URL, if it says so.
+    pub created: Option<bool>,
+    /// When the provider has created the short link, if it says so.
+    pub created_at: Option<SystemTime>,
+    /// The long URL, exactly as given, so that the target of the short URL
+    /// can be shown without any request.
+    pub original: String,
+    /// The SHA-256 digest of the normalized long URL, in hexadecimal (see
+    /// `target::url_hash`), if enabled with `UrlShortenerBuilder::hash_urls`
+    /// under the `hash` feature.
+    pub url_hash: Option<String>,
+}
+
+impl ShortUrl {
+    /// Returns the length of the long URL, in characters.
+    pub fn original_len(&self) -> usize {
+        self.original.chars().count()
     }
 
-    /// Try to generate a short URL from each provider, iterating over each
-    /// provider until a short URL is successfully generated.
-    /// If you wish to override the list or providers or their priority,
-    /// provide your own list of providers as second argument.
-    ///
-    /// # Examples
-    ///
-    /// ```rust,no_run
-    /// use urlshortener::client::UrlShortener;
+    /// Returns the length of the short URL, in characters.
+    pub fn short_len(&self) -> usize {
+        self.url.chars().count()
+    }
+
+    /// Returns the share of the long URL length saved by the short URL: `0.75`
+    /// when the short URL is four times shorter. It is negative when the
+    /// short URL is longer, and `0` for an empty long URL.
+    pub fn savings_ratio(&self) -> f32 {
+        let original_len = self.original_len();
+        if original_len == 0 {
+            return 0.0;
+        }
+        1.0 - self.short_len() as f32 / original_len as f32
+    }
+}
+
+impl fmt::Display for ShortUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.url)
+    }
+}
+
+impl AsRef<str> for ShortUrl {
+    fn as_ref(&self) -> &str {
+        &self.url
+    }
+}
+
+/// A short URL along with its related addresses, returned by
+/// [`shorten_full`]. Each related address is `None` if the provider does not
+/// support it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct LinkBundle {
+    /// The short URL.
+    pub short: String,
+    /// The preview page of the short URL (see
+    /// [`providers::Provider::preview_url`]).
+    pub preview: Option<String>,
+    /// The QR code image of the short URL, rendered by the default QR
+    /// service (see [`qr_code_url`]).
+    pub qr: Option<String>,
+    /// The statistics page of the short URL (see
+    /// [`providers::Provider::stats_url`]).
+    pub stats: Option<String>,
+}
+
+impl LinkBundle {
+    /// Bundles the short URL generated by the provider with its related
+    /// addresses.
     ///
-    /// let us = UrlShortener::new().unwrap();
-    /// let long_url = "https://rust-lang.org";
-    /// let _short_url = us.try_generate(long_url, None);
-    /// ```
+    /// # Example
     ///
-    /// ```rust,no_run
-    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    /// ```rust
+    /// use urlshortener::{client::LinkBundle, providers::Provider};
     ///
-    /// let us = UrlShortener::new().unwrap();
-    /// let providers = [
-    ///     Provider::GooGl { api_key: "MY_API_KEY".to_owned() },
-    ///     Provider::IsGd,
-    /// ];
-    /// let long_url = "https://rust-lang.org";
-    /// let _short_url = us.try_generate(long_url, Some(&providers));
+    /// let bundle = LinkBundle::new("https://is.gd/abc".to_owned(), &Provider::IsGd);
+    /// assert_eq!(bundle.preview.as_deref(), Some("https://is.gd/abc-"));
     /// ```
+    pub fn new(short: String, provider: &providers::Provider) -> LinkBundle {
+        LinkBundle {
+            preview: provider.preview_url(&short),
+            qr: Some(qr_code_url(&short)),
+            stats: provider.stats_url(&short),
+            short,
+        }
+    }
+}
+
+/// The reliability record of a provider: the outcomes of the requests sent
+/// to it.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProviderStats {
+    /// The number of requests which have returned a short URL.
+    pub successes: u64,
+    /// The number of requests which have failed.
+    pub failures: u64,
+}
+
+impl ProviderStats {
+    /// Returns the estimated probability of the next request succeeding,
+    /// between `0` and `1`. A provider without any record is given `0.5`.
+    pub fn reliability(&self) -> f64 {
+        (self.successes as f64 + 1.0) / ((self.successes + self.failures) as f64 + 2.0)
+    }
+
+    /// Compares the reliability of the two records exactly.
+    fn cmp_reliability(&self, other: &ProviderStats) -> std::cmp::Ordering {
+        let total = |s: &ProviderStats| u128::from(s.successes) + u128::from(s.failures) + 2;
+        let successes = |s: &ProviderStats| u128::from(s.successes) + 1;
+        (successes(self) * total(other)).cmp(&(successes(other) * total(self)))
+    }
+}
+
+/// The response time percentiles of a provider, returned by
+/// [`UrlShortener::latency_stats`]. They are accurate to about 7%.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LatencyStats {
+    /// The number of requests measured.
+    pub count: u64,
+    /// The median response time.
+    pub p50: Duration,
+    /// The response time of the 95th percentile.
+    pub p95: Duration,
+    /// The response time of the 99th percentile.
+    pub p99: Duration,
+}
+
+/// Derives the timeout of a provider from its observed response times, set
+/// with [`UrlShortenerBuilder::adaptive_timeout`]: the 95th percentile plus
+/// a margin, between a minimum and a maximum. Until enough requests have been
+/// measured, the static timeout is used.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use urlshortener::client::{AdaptiveTimeout, LatencyStats};
+///
+/// let adaptive = AdaptiveTimeout::new(Duration::from_millis(500));
+/// let stats = LatencyStats {
+///     count: 100,
+///     p95: Duration::from_millis(800),
+///     ..LatencyStats::default()
+/// };
+/// let fallback = Duration::from_secs(3);
+/// assert_eq!(adaptive.timeout(&stats, fallback), Duration::from_millis(1300));
+/// assert_eq!(adaptive.timeout(&LatencyStats::default(), fallback), fallback);
+/// ```
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AdaptiveTimeout {
+    margin: Duration,
+    min_samples: u64,
+    min: Duration,
+    max: Option<Duration>,
+}
+
+impl AdaptiveTimeout {
+    /// Creates an adaptive timeout adding `margin` to the 95th percentile,
+    /// once 20 requests have been measured.
+    pub fn new(margin: Duration) -> AdaptiveTimeout {
+        AdaptiveTimeout {
+            margin,
+            min_samples: 20,
+            min: Duration::from_secs(0),
+            max: None,
+        }
+    }
+
+    /// Sets the number of measured requests needed before adapting.
+    pub fn min_samples(mut self, samples: u64) -> Self {
+        self.min_samples = samples.max(1);
+        self
+    }
+
+    /// Sets the minimum timeout.
+    pub fn min(mut self, min: Duration) -> Self {
+        self.min = min;
+        self
+    }
+
+    /// Sets the maximum timeout. Defaults to twice the static timeout, as
+    /// the requests timing out are measured too and would otherwise push
+    /// the timeout up without end.
+    pub fn max(mut self, max: Duration) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    /// Returns the timeout for a provider with the given response times,
+    /// `fallback` being its static timeout.
+    pub fn timeout(&self, stats: &LatencyStats, fallback: Duration) -> Duration {
+        if stats.count < self.min_samples {
+            return fallback;
+        }
+        let max = self.max.unwrap_or_else(|| fallback.saturating_mul(2));
+
+        stats
+            .p95
+            .saturating_add(self.margin)
+            .min(max)
+            .max(self.min.min(max))
+    }
+}
+
+/// The number of buckets of a `Histogram` per power of two.
+const HISTOGRAM_SUB_BUCKETS: u64 = 8;
+
+/// A streaming histogram of durations, with buckets spaced logarithmically
+/// so that its size stays fixed, however many values are recorded.
+#[derive(Debug, Clone)]
+struct Histogram {
+    /// The number of durations by bucket, in microseconds.
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            counts: vec![0; Histogram::bucket(u64::MAX) + 1],
+            total: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// Returns the bucket of the value: the values under
+    /// `HISTOGRAM_SUB_BUCKETS` have their own buckets, and every next power
+    /// of two is split into `HISTOGRAM_SUB_BUCKETS` buckets.
+    fn bucket(value: u64) -> usize {
+        if value < HISTOGRAM_SUB_BUCKETS {
+            return value as usize;
+        }
+        let shift = 63 - value.leading_zeros() - HISTOGRAM_SUB_BUCKETS.trailing_zeros();
+        ((u64::from(shift) + 1) * HISTOGRAM_SUB_BUCKETS
+            + ((value >> shift) - HISTOGRAM_SUB_BUCKETS)) as usize
+    }
+
+    /// Returns the middle value of the bucket.
+    fn value(bucket: usize) -> u64 {
+        let bucket = bucket as u64;
+        if bucket < HISTOGRAM_SUB_BUCKETS {
+            return bucket;
+        }
+        let shift = bucket / HISTOGRAM_SUB_BUCKETS - 1;
+        let low = (HISTOGRAM_SUB_BUCKETS + bucket % HISTOGRAM_SUB_BUCKETS) << shift;
+        low + ((1 << shift) >> 1)
+    }
+
+    fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.counts[Histogram::bucket(micros)] += 1;
+        self.total += 1;
+    }
+
+    /// Returns the duration under which the `quantile` share of the
+    /// durations fall.
+    fn percentile(&self, quantile: f64) -> Duration {
+        let rank = ((quantile * self.total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(Histogram::value(bucket));
+            }
+        }
+        Duration::from_secs(0)
+    }
+
+    fn stats(&self) -> LatencyStats {
+        LatencyStats {
+            count: self.total,
+            p50: self.percentile(0.50),
+            p95: self.percentile(0.95),
+            p99: self.percentile(0.99),
+        }
+    }
+}
+
+/// The state learned by a `UrlShortener` about the providers, exported by
+/// [`UrlShortener::export_state`] and restored by
+/// [`UrlShortenerBuilder::with_state`]. With the `serde` feature, it can be
+/// serialized, to survive restarts.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientState {
+    /// The reliability records, by provider name (see
+    /// [`providers::Provider::to_name`]).
+    pub providers: HashMap<String, ProviderStats>,
+}
+
+/// Details of a provider response, returned alongside the short URL by
+/// [`UrlShortener::generate_with_meta`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ResponseMeta {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The response headers selected by
+    /// [`UrlShortenerBuilder::capture_headers`], with lowercased names.
+    pub headers: Vec<(String, String)>,
+    /// Whether the provider has created a new short link, or returned the
+    /// existing one of the same URL, if it says so (see
+    /// [`providers::link_created`]).
+    pub created: Option<bool>,
+    /// The format the short URL has been read from, which tells which
+    /// parser has succeeded when a provider changes its responses.
+    pub format: Option<providers::ResponseFormat>,
+    /// When the provider has created the short link, if it says so (see
+    /// [`providers::created_at`]).
+    pub created_at: Option<SystemTime>,
+}
+
+impl ResponseMeta {
+    /// Returns the value of the captured header with the given name, if any.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl RequestPreview {
+    fn new(req: &reqwest::blocking::Request) -> RequestPreview {
+        RequestPreview {
+            method: req.method().clone(),
+            url: req.url().to_string(),
+            headers: req.headers().clone(),
+            body: req
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|body| String::from_utf8_lossy(body).into_owned()),
+        }
+    }
+}
+
+/// A structured record of a single request to a provider, passed to the
+/// audit hook (see [`UrlShortenerBuilder::audit_hook`]).
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    /// The provider the request was sent to.
+    pub provider: providers::Provider,
+    /// The request exactly as it was sent.
+    pub request: RequestPreview,
+    /// The HTTP status of the response, if the provider has answered.
+    pub status: Option<u16>,
+    /// The time from sending the request to reading the whole response.
+    pub duration: Duration,
+    /// The short URL, or the error. For a request to a batch endpoint (see
+    /// [`UrlShortenerBuilder::batch_api`]), the short URLs separated by
+    /// spaces, and for a request about a short URL, like
+    /// [`UrlShortener::expand`], the long URL.
+    pub outcome: Result<String, ProviderError>,
+}
+
+/// Describes how the transient failures of a provider are retried.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt.
+    pub retries: u32,
+    /// The delay before the first retry, doubled before each next one.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// The options of [`UrlShortener::generate_batch`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct BatchOptions {
+    /// How the transient failures of each URL are retried.
+    pub retry: RetryPolicy,
+    /// The total number of retries allowed across the whole batch, or `None`
+    /// for no limit. Once it is spent, the failures are returned without any
+    /// retry, which protects the rate limit of the provider.
+    pub retry_budget: Option<u32>,
+}
+
+/// The native batch endpoint of a provider, creating the short URLs of
+/// several URLs in one request, which spares the rate limit of the provider
+/// (see [`UrlShortenerBuilder::batch_api`]).
+///
+/// # Examples
+///
+/// ```rust
+/// use urlshortener::{client::BatchApi, request::{ContentType, Method, Request}};
+///
+/// let api = BatchApi::new(
+///     50,
+///     |urls| Request {
+///         url: "https://short.example.com/api/bulk".to_owned(),
+///         body: Some(urls.join("\n")),
+///         content_type: None,
+///         user_agent: None,
+///         headers: None,
+///         method: Method::Post,
+///     },
+///     |res| Some(res.lines().map(str::to_owned).collect()),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchApi {
+    max_urls: usize,
+    request: Callback<BatchRequest>,
+    parser: Callback<BatchParser>,
+}
+
+impl BatchApi {
+    /// Describes a batch endpoint taking up to `max_urls` URLs per request.
+    /// `request` builds the request of the given URLs, and `parser` returns
+    /// the short URLs found in its response body, in the order of the URLs,
+    /// or `None` if the response cannot be parsed. A response holding an
+    /// array of link objects can be read with [`providers::parse_links`].
+    pub fn new<R, P>(max_urls: usize, request: R, parser: P) -> BatchApi
+    where
+        R: Fn(&[String]) -> Request + Send + Sync + 'static,
+        P: Fn(&str) -> Option<Vec<String>> + Send + Sync + 'static,
+    {
+        BatchApi {
+            max_urls: max_urls.max(1),
+            request: Callback(Arc::new(request)),
+            parser: Callback(Arc::new(parser)),
+        }
+    }
+}
+
+/// A page fetched before each request to a provider whose create form is
+/// protected by a CSRF token or a nonce, set with
+/// [`UrlShortenerBuilder::token_step`]. The token is extracted from the page
+/// and added to the request, which also sends back the cookies set by the
+/// page.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::client::TokenStep;
+///
+/// // A hidden `csrf` field, echoed in the form body.
+/// let step = TokenStep::form_field("https://short.example.com/", "csrf");
+///
+/// // A token found elsewhere, sent in a header.
+/// let step = TokenStep::new(
+///     "https://short.example.com/",
+///     |page| Some(page.split("nonce: '").nth(1)?.split('\'').next()?.to_owned()),
+///     |req, token| {
+///         let value = token.parse().unwrap();
+///         req.headers.get_or_insert_with(Default::default).insert("X-Nonce", value);
+///     },
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenStep {
+    page: String,
+    extract: Callback<TokenExtractor>,
+    apply: Callback<TokenApplier>,
+}
+
+impl TokenStep {
+    /// Fetches `page` with a `GET` request, passes its body to `extract`,
+    /// which returns the token or `None` if there is none, and adds the
+    /// token to the provider request with `apply`.
+    pub fn new<E, A>(page: &str, extract: E, apply: A) -> TokenStep
+    where
+        E: Fn(&str) -> Option<String> + Send + Sync + 'static,
+        A: Fn(&mut Request, &str) + Send + Sync + 'static,
+    {
+        TokenStep {
+            page: page.to_owned(),
+            extract: Callback(Arc::new(extract)),
+            apply: Callback(Arc::new(apply)),
+        }
+    }
+
+    /// Fetches `page` and sends the value of its form field `name` (see
+    /// [`providers::form_input`]) as the field of the same name of the form
+    /// body, or of the query for a request without body.
+    pub fn form_field(page: &str, name: &str) -> TokenStep {
+        let (field, param) = (name.to_owned(), name.to_owned());
+        TokenStep::new(
+            page,
+            move |page| providers::form_input(page, &field),
+            move |req, token| match req.body {
+                Some(ref mut body) => {
+                    if !body.is_empty() {
+                        body.push('&');
+                    }
+                    let pair = format!(
+                        "{}={}",
+                        target::form_encode(&param),
+                        target::form_encode(token)
+                    );
+                    body.push_str(&pair);
+                }
+                None => {
+                    let separator = if req.url.contains('?') { '&' } else { '?' };
+                    req.url.push(separator);
+                    req.url.push_str(&format!(
+                        "{}={}",
+                        target::query_encode(&param),
+                        target::query_encode(token)
+                    ));
+                }
+            },
+        )
+    }
+}
+
+/// A builder for a `UrlShortener` with custom settings.
+#[derive(Debug, Clone)]
+pub struct UrlShortenerBuilder {
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    provider_timeouts: HashMap<providers::Provider, Duration>,
+    adaptive_timeout: Option<AdaptiveTimeout>,
+    endpoints: HashMap<providers::Provider, String>,
+    parsers: HashMap<providers::Provider, Callback<Parser>>,
+    location_providers: HashSet<providers::Provider>,
+    max_response_size: u64,
+    max_concurrent_per_provider: Option<usize>,
+    allow_previews: bool,
+    normalize_urls: bool,
+    default_scheme: Option<String>,
+    #[cfg(feature = "hash")]
+    hash_urls: bool,
+    capture_headers: Vec<String>,
+    qr_service: String,
+    domain_filter: target::DomainFilter,
+    block_private_addresses: bool,
+    disabled: HashSet<Discriminant<providers::Provider>>,
+    allow_insecure_fallback: bool,
+    attempt_hook: Option<Callback<AttemptHook>>,
+    audit_hook: Option<Callback<AuditHook>>,
+    interceptor: Option<Callback<Interceptor>>,
+    correlation_header: Option<header::HeaderName>,
+    batch_apis: HashMap<providers::Provider, BatchApi>,
+    token_steps: HashMap<providers::Provider, TokenStep>,
+    query_stripping: HashSet<providers::Provider>,
+    state: ClientState,
+    proxy: Option<String>,
+    no_proxy: NoProxy,
+    resolver: Option<Callback<Resolver>>,
+    rng: Option<Callback<SharedRng>>,
+}
+
+impl Default for UrlShortenerBuilder {
+    fn default() -> Self {
+        UrlShortenerBuilder {
+            timeout: None,
+            connect_timeout: None,
+            provider_timeouts: HashMap::new(),
+            adaptive_timeout: None,
+            endpoints: HashMap::new(),
+            parsers: HashMap::new(),
+            location_providers: HashSet::new(),
+            max_response_size: DEFAULT_MAX_RESPONSE_SIZE,
+            max_concurrent_per_provider: None,
+            allow_previews: false,
+            normalize_urls: false,
+            default_scheme: None,
+            #[cfg(feature = "hash")]
+            hash_urls: false,
+            capture_headers: Vec::new(),
+            qr_service: DEFAULT_QR_SERVICE.to_owned(),
+            domain_filter: target::DomainFilter::new(),
+            block_private_addresses: false,
+            disabled: HashSet::new(),
+            allow_insecure_fallback: false,
+            attempt_hook: None,
+            audit_hook: None,
+            interceptor: None,
+            correlation_header: None,
+            batch_apis: HashMap::new(),
+            token_steps: HashMap::new(),
+            query_stripping: HashSet::new(),
+            state: ClientState::default(),
+            proxy: None,
+            no_proxy: NoProxy::default(),
+            resolver: None,
+            rng: None,
+        }
+    }
+}
+
+impl UrlShortenerBuilder {
+    /// Sets the timeout of the requests to all the providers, overriding
+    /// their default timeouts (see [`providers::Provider::default_timeout`]).
     ///
-    /// # Errors
-    ///
-    /// Returns an `Error<ProviderError>` if there is an error generating a
-    /// short URL from all providers.
-    ///
-    /// # Notes
-    ///
-    /// This function has been deprecated since it does not bring any UX improvements.
-    /// The body could be easily re-written as:
+    /// The timeout covers the whole request. When it elapses after the
+    /// connection has been established, the request fails with
+    /// `ProviderError::ReadTimeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout of establishing the connection to a provider, which
+    /// fails with `ProviderError::ConnectTimeout`. A short connect timeout
+    /// lets the fallback move on quickly from the providers which are down,
+    /// while still giving the slow ones the time to answer.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sends the requests through the proxy, like `http://proxy:8080`,
+    /// instead of the proxies of the environment variables (`HTTP_PROXY` and
+    /// the like). An invalid address makes [`UrlShortenerBuilder::build`]
+    /// fail.
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_owned());
+        self
+    }
+
+    /// Lets the requests to the hosts reach them directly instead of going
+    /// through the proxy (see [`UrlShortenerBuilder::proxy`]). Each host is a
+    /// domain, also covering its subdomains, like `example.com`,
+    /// `.example.com` or `*.example.com`, an IP address, or `*` for every
+    /// host.
     ///
-    /// ```rust,no_run
-    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    /// # Examples
     ///
-    /// let us = UrlShortener::new().unwrap();
-    /// let providers = [
-    ///     Provider::GooGl { api_key: "MY_API_KEY".to_owned() },
-    ///     Provider::IsGd,
-    /// ];
-    /// let long_url = "https://rust-lang.org";
-    /// let mut short_url = None;
-    /// for provider in &providers {
-    ///     if let Ok(short_url_res) = us.generate(long_url, provider) {
-    ///         short_url = Some(short_url_res);
-    ///         break;
-    ///     }
-    /// }
+    /// ```rust
+    /// use urlshortener::client::UrlShortener;
     ///
+    /// let us = UrlShortener::builder()
+    ///     .proxy("http://proxy.internal:8080")
+    ///     .no_proxy(&["is.gd", "*.corp.example"])
+    ///     .build()
+    ///     .unwrap();
     /// ```
-    #[deprecated(since = "1.0.0", note = "Please use `generate` directly instead.")]
-    pub fn try_generate(
-        &self,
-        url: &str,
-        use_providers: Option<&[providers::Provider]>,
-    ) -> Result<String, ProviderError> {
-        let providers = use_providers.unwrap_or(providers::PROVIDERS);
-        for provider in providers {
-            let res = self.generate(url, provider);
+    pub fn no_proxy<S: AsRef<str>>(mut self, hosts: &[S]) -> Self {
+        for host in hosts {
+            self.no_proxy.add(host.as_ref());
+        }
+        self
+    }
 
-            if res.is_ok() {
-                return res;
-            }
+    /// Adds the comma-separated hosts of the `NO_PROXY` environment variable,
+    /// or `no_proxy` if it is unset, to the hosts bypassing the proxy (see
+    /// [`UrlShortenerBuilder::no_proxy`]).
+    pub fn no_proxy_from_env(mut self) -> Self {
+        let hosts = std::env::var("NO_PROXY")
+            .or_else(|_| std::env::var("no_proxy"))
+            .unwrap_or_default();
+        for host in hosts.split(',') {
+            self.no_proxy.add(host);
         }
+        self
+    }
 
-        Err(ProviderError::Connection)
+    /// Sets the timeout of the requests to the specified provider,
+    /// overriding both its default timeout and the one set by
+    /// [`UrlShortenerBuilder::timeout`].
+    pub fn provider_timeout(mut self, provider: providers::Provider, timeout: Duration) -> Self {
+        self.provider_timeouts.insert(provider, timeout);
+        self
     }
 
-    /// Attempts to get a short URL using the specified provider.
+    /// Derives the timeout of each provider from its measured response
+    /// times (see [`UrlShortener::latency_stats`]), so that the fast
+    /// providers fail fast and the slow ones get more time. The timeouts set
+    /// by [`UrlShortenerBuilder::timeout`] or the default ones apply until
+    /// enough requests have been measured, and those set by
+    /// [`UrlShortenerBuilder::provider_timeout`] always apply.
     ///
     /// # Examples
     ///
-    /// ```rust, no_run
-    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    /// ```rust
+    /// use std::time::Duration;
+    /// use urlshortener::client::{AdaptiveTimeout, UrlShortener};
     ///
-    /// let us = UrlShortener::new().unwrap();
-    /// let long_url = "http://rust-lang.org";
-    /// let _short_url = us.generate(long_url, &Provider::IsGd);
+    /// let us = UrlShortener::builder()
+    ///     .adaptive_timeout(AdaptiveTimeout::new(Duration::from_millis(500)))
+    ///     .build()
+    ///     .unwrap();
     /// ```
+    pub fn adaptive_timeout(mut self, adaptive: AdaptiveTimeout) -> Self {
+        self.adaptive_timeout = Some(adaptive);
+        self
+    }
+
+    /// Sends the requests of the provider to another host, keeping the path
+    /// and the query of the provider requests. This is mostly useful for
+    /// testing and for going through a mirror of the provider.
     ///
-    /// ```rust,no_run
-    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    /// # Examples
     ///
-    /// let us = UrlShortener::new().unwrap();
-    /// let api_key = "MY_API_KEY".to_owned();
-    /// let long_url = "http://rust-lang.org";
-    /// let _short_url = us.generate(long_url, &Provider::GooGl { api_key: api_key });
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .endpoint(Provider::IsGd, "http://localhost:8080")
+    ///     .build()
+    ///     .unwrap();
+    /// let preview = us.preview("https://rust-lang.org", &Provider::IsGd).unwrap();
+    /// assert!(preview.url.starts_with("http://localhost:8080/create.php?"));
     /// ```
-    pub fn generate<S: AsRef<str>>(
+    pub fn endpoint(mut self, provider: providers::Provider, base: &str) -> Self {
+        self.endpoints.insert(provider, base.to_owned());
+        self
+    }
+
+    /// Replaces the built-in parser of the provider responses (see
+    /// [`providers::parse`]) with `parser`, which returns the short URL found
+    /// in the response body, or `None` to fail with
+    /// `ProviderError::Deserialize`. This lets a provider which has changed
+    /// its response format be used until the crate catches up.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .parser(Provider::TinyUrl, |res| {
+    ///         let link = res.split("data-link=\"").nth(1)?;
+    ///         link.split('"').next().map(str::to_owned)
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn parser<F>(mut self, provider: providers::Provider, parser: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.parsers.insert(provider, Callback(Arc::new(parser)));
+        self
+    }
+
+    /// Reads the short URLs generated by the provider from the `Location`
+    /// header of its responses, for the providers which answer with a
+    /// redirect to the short URL, or with the short URL as the location of
+    /// the created link, and an empty body. The responses without a
+    /// `Location` header are parsed as usual.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .short_url_in_location(Provider::TinyPh)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn short_url_in_location(mut self, provider: providers::Provider) -> Self {
+        self.location_providers.insert(provider);
+        self
+    }
+
+    /// Declares the native batch endpoint of the provider, which
+    /// [`UrlShortener::generate_batch`] then uses instead of one request per
+    /// URL.
+    pub fn batch_api(mut self, provider: providers::Provider, api: BatchApi) -> Self {
+        self.batch_apis.insert(provider, api);
+        self
+    }
+
+    /// Fetches the page of the token step before each request to the
+    /// provider, and adds its token to the request, for the providers whose
+    /// create form is protected by a CSRF token or a nonce. The page also
+    /// goes to the endpoint set with [`UrlShortenerBuilder::endpoint`], if
+    /// any.
+    ///
+    /// The request fails with `ProviderError::Deserialize` if the page has
+    /// no token.
+    pub fn token_step(mut self, provider: providers::Provider, step: TokenStep) -> Self {
+        self.token_steps.insert(provider, step);
+        self
+    }
+
+    /// Sets the maximum size of a provider response body, in bytes. Larger
+    /// responses are rejected with `ProviderError::ResponseTooLarge`.
+    /// Defaults to 1 MiB.
+    pub fn max_response_size(mut self, bytes: u64) -> Self {
+        self.max_response_size = bytes;
+        self
+    }
+
+    /// Keeps at most `max` requests in flight to each provider at any time,
+    /// across all the threads and the clones of the `UrlShortener`; the
+    /// other requests wait for a slot. This caps the bursts of the
+    /// concurrent functions, like [`UrlShortener::generate_stream`], which
+    /// some providers refuse even under their rate limits. A provider is
+    /// identified by its name (see [`providers::Provider::to_name`]), and
+    /// `0` counts as `1`. Unlimited by default.
+    pub fn max_concurrent_per_provider(mut self, max: usize) -> Self {
+        self.max_concurrent_per_provider = Some(max.max(1));
+        self
+    }
+
+    /// Allows the fallback over several providers to return links of
+    /// providers which show a preview or an ad page instead of redirecting
+    /// directly. Such providers are skipped by default.
+    pub fn allow_previews(mut self, allow: bool) -> Self {
+        self.allow_previews = allow;
+        self
+    }
+
+    /// Normalizes the long URLs before shortening them (see
+    /// [`target::normalize_url`]), so that the equivalent URLs share a
+    /// single short URL.
+    pub fn normalize_urls(mut self, normalize: bool) -> Self {
+        self.normalize_urls = normalize;
+        self
+    }
+
+    /// Prepends `scheme://` to the long URLs without a scheme, like
+    /// `example.com`, before validating and shortening them (see
+    /// [`target::default_scheme`]). The URLs are sent as given by default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::builder().default_scheme("https").build().unwrap();
+    /// ```
+    pub fn default_scheme(mut self, scheme: &str) -> Self {
+        self.default_scheme = Some(scheme.to_owned());
+        self
+    }
+
+    /// Fills [`ShortUrl::url_hash`] in the results of
+    /// [`UrlShortener::generate_detailed`] with the SHA-256 digest of the
+    /// normalized long URL, a stable key for storing the links. Disabled by
+    /// default. Requires the `hash` feature.
+    #[cfg(feature = "hash")]
+    pub fn hash_urls(mut self, hash: bool) -> Self {
+        self.hash_urls = hash;
+        self
+    }
+
+    /// Captures the specified response headers, like `X-RateLimit-Remaining`,
+    /// into the [`ResponseMeta`] returned by
+    /// [`UrlShortener::generate_with_meta`]. No header is captured by default.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .capture_headers(&["X-RateLimit-Remaining", "X-RateLimit-Reset"])
+    ///     .build()
+    ///     .unwrap();
+    /// let (_short_url, meta) = us
+    ///     .generate_with_meta("https://rust-lang.org", &Provider::IsGd)
+    ///     .unwrap();
+    /// println!("{:?}", meta.header("X-RateLimit-Remaining"));
+    /// ```
+    pub fn capture_headers<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.capture_headers
+            .extend(names.iter().map(|name| name.as_ref().to_lowercase()));
+        self
+    }
+
+    /// Refuses to shorten the URLs pointing to domains not allowed by the
+    /// filter, with `ProviderError::BlockedDomain`, before any request is
+    /// sent.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::{Provider, ProviderError}, target::DomainFilter};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .domain_filter(DomainFilter::new().deny("malware.test"))
+    ///     .build()
+    ///     .unwrap();
+    /// let res = us.generate("http://www.malware.test/", &Provider::IsGd);
+    /// assert_eq!(res, Err(ProviderError::BlockedDomain));
+    /// ```
+    pub fn domain_filter(mut self, filter: target::DomainFilter) -> Self {
+        self.domain_filter = filter;
+        self
+    }
+
+    /// Refuses to shorten the URLs pointing to private, loopback, link-local
+    /// or reserved addresses, with `ProviderError::PrivateAddressBlocked`,
+    /// before any request is sent. The domains are resolved for the check.
+    ///
+    /// This should be enabled when shortening URLs supplied by untrusted
+    /// users, as the providers may fetch the long URL.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::{Provider, ProviderError}};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .block_private_addresses(true)
+    ///     .build()
+    ///     .unwrap();
+    /// let res = us.generate("http://169.254.169.254/", &Provider::IsGd);
+    /// assert_eq!(res, Err(ProviderError::PrivateAddressBlocked));
+    /// ```
+    pub fn block_private_addresses(mut self, block: bool) -> Self {
+        self.block_private_addresses = block;
+        self
+    }
+
+    /// Disables the providers: they are skipped by the fallback over several
+    /// providers, and the requests made to them directly fail with
+    /// `ProviderError::ProviderDisabled`. The credentials and the host of
+    /// the providers are ignored, so that disabling `Provider::BitLy` with
+    /// any token disables all of them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::{Provider, ProviderError}};
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .disable(&[Provider::TinyUrl, Provider::Rlu])
+    ///     .build()
+    ///     .unwrap();
+    /// let res = us.generate("https://rust-lang.org", &Provider::TinyUrl);
+    /// assert_eq!(res, Err(ProviderError::ProviderDisabled));
+    /// ```
+    pub fn disable(mut self, providers: &[providers::Provider]) -> Self {
+        self.disabled
+            .extend(providers.iter().map(mem::discriminant));
+        self
+    }
+
+    /// Flags the provider as stripping or mangling the query strings of the
+    /// long URLs (see [`providers::Provider::preserves_query`]). The
+    /// fallbacks across providers attempt it after the others for the URLs
+    /// with a query string.
+    pub fn strips_query(mut self, provider: providers::Provider) -> Self {
+        self.query_stripping.insert(provider);
+        self
+    }
+
+    /// Allows retrying a request over plain HTTP when the TLS connection to
+    /// the provider fails with `ProviderError::Tls`. A warning is logged for
+    /// every such downgrade.
+    ///
+    /// This exposes the long URL and the credentials of the provider to
+    /// anyone on the network path, so it is disabled by default.
+    pub fn allow_insecure_fallback(mut self, allow: bool) -> Self {
+        self.allow_insecure_fallback = allow;
+        self
+    }
+
+    /// Sets the service rendering the QR codes for
+    /// [`UrlShortener::generate_with_qr`]. The service is sent the
+    /// `data`, `size` and `format` query parameters and must answer with a
+    /// PNG image. Defaults to `https://api.qrserver.com/v1/create-qr-code/`.
+    pub fn qr_service(mut self, url: &str) -> Self {
+        self.qr_service = url.to_owned();
+        self
+    }
+
+    /// Sets a callback invoked before and after each provider request.
+    ///
+    /// A panic inside the callback is caught and does not affect the request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .attempt_hook(|event| println!("{:?}", event))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn attempt_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AttemptEvent<'_>) + Send + Sync + 'static,
+    {
+        self.attempt_hook = Some(Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Sets a callback receiving an [`AuditRecord`] for each request sent to
+    /// a provider, for writing it to an audit store.
+    ///
+    /// A panic inside the callback is caught and does not affect the request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .audit_hook(|record| {
+    ///         println!(
+    ///             "{} {} {:?} {:?}",
+    ///             record.provider.to_name(),
+    ///             record.request.url,
+    ///             record.status,
+    ///             record.duration
+    ///         )
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn audit_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&AuditRecord) + Send + Sync + 'static,
+    {
+        self.audit_hook = Some(Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Sets a callback modifying every provider request right before it is
+    /// sent, for example to add the headers required by an egress gateway.
+    /// It also applies to the requests returned by [`UrlShortener::preview`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    /// use reqwest::header::HeaderValue;
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .request_interceptor(|req| {
+    ///         req.headers_mut()
+    ///             .insert("X-Gateway-Key", HeaderValue::from_static("secret"));
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// let preview = us.preview("https://rust-lang.org", &Provider::IsGd).unwrap();
+    /// assert_eq!(preview.headers["X-Gateway-Key"], "secret");
+    /// ```
+    pub fn request_interceptor<F>(mut self, interceptor: F) -> Self
+    where
+        F: Fn(&mut reqwest::blocking::Request) + Send + Sync + 'static,
+    {
+        self.interceptor = Some(Callback(Arc::new(interceptor)));
+        self
+    }
+
+    /// Sets a correlation ID header, like `X-Request-Id`, on every provider
+    /// request, tying them into the tracing of the caller. The value is the
+    /// one given to [`UrlShortener::with_correlation_id`], or else a random
+    /// one generated for each request. The request interceptor may still
+    /// override it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    /// use reqwest::header::HeaderName;
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .correlation_header(HeaderName::from_static("x-request-id"))
+    ///     .build()
+    ///     .unwrap();
+    /// let traced = us.with_correlation_id("4bf92f3577b34da6").unwrap();
+    /// let preview = traced.preview("https://rust-lang.org", &Provider::IsGd).unwrap();
+    /// assert_eq!(preview.headers["X-Request-Id"], "4bf92f3577b34da6");
+    /// ```
+    pub fn correlation_header(mut self, name: header::HeaderName) -> Self {
+        self.correlation_header = Some(name);
+        self
+    }
+
+    /// Seeds the `UrlShortener` with the state learned by another one, like
+    /// the reliability records ranking the providers (see
+    /// [`UrlShortener::rank_providers`]).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let previous = UrlShortener::new().unwrap();
+    /// let us = UrlShortener::builder()
+    ///     .with_state(previous.export_state())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn with_state(mut self, state: ClientState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Resolves the hosts of the requests with `resolver` instead of the
+    /// system resolver. It returns the addresses of a host, or none of them
+    /// to leave it to the system resolver. This allows split-horizon DNS,
+    /// DNS over HTTPS or pinning a provider to an address for testing.
+    ///
+    /// The resolver is called before every request, with the host of its
+    /// URL: the providers, including the self-hosted ones like
+    /// `Provider::Kutt` with a custom host, the endpoints, the token pages,
+    /// the QR service and the verifications of the short URLs alike.
+    ///
+    /// The blocking HTTP client can't call it for every connection, so the
+    /// connections to a host are made by an HTTP client dedicated to its
+    /// addresses, which is replaced when the resolver returns other ones.
+    /// The hosts the redirects lead to are left to the system resolver.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::builder()
+    ///     .resolver(|host| match host {
+    ///         "is.gd" => vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))],
+    ///         _ => Vec::new(),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&str) -> Vec<IpAddr> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Callback(Arc::new(resolver)));
+        self
+    }
+
+    /// Sets the source of the random numbers behind the random picks of
+    /// providers (see [`UrlShortener::generate_weighted`]) and the jittered
+    /// delays (see [`UrlShortener::jitter`]). Defaults to an [`XorShift`]
+    /// seeded at random.
+    ///
+    /// The source is shared by the clones of the builder, and by the clients
+    /// built from them.
+    pub fn rng<R: Rng + Send + 'static>(mut self, rng: R) -> Self {
+        self.rng = Some(Callback(Arc::new(Mutex::new(rng))));
+        self
+    }
+
+    /// Seeds the source of the random numbers, so that the random picks of
+    /// providers are reproducible, for example in tests. This is the same as
+    /// `rng(XorShift::with_seed(seed))`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::builder().seed(42).build().unwrap();
+    /// ```
+    pub fn seed(self, seed: u64) -> Self {
+        self.rng(XorShift::with_seed(seed))
+    }
+
+    /// Returns the proxy of the requests, skipping the hosts which bypass it.
+    fn build_proxy(&self) -> Result<Option<reqwest::Proxy>, reqwest::Error> {
+        let url = match self.proxy {
+            Some(ref url) => url.clone(),
+            None => return Ok(None),
+        };
+        // Rejects an invalid address now rather than on every request.
+        reqwest::Proxy::all(url.as_str())?;
+
+        let no_proxy = self.no_proxy.clone();
+        Ok(Some(reqwest::Proxy::custom(move |target| {
+            match target.host_str() {
+                Some(host) if no_proxy.bypasses(host) => None,
+                _ => Some(url.clone()),
+            }
+        })))
+    }
+
+    /// Creates the `UrlShortener`.
+    pub fn build(self) -> Result<UrlShortener, reqwest::Error> {
+        let proxy = self.build_proxy()?;
+        let (timeout, connect_timeout) = (self.timeout, self.connect_timeout);
+        let has_location_providers = !self.location_providers.is_empty();
+        let build_clients = move |resolved: Option<(&str, &[SocketAddr])>| {
+            let builder = || {
+                let mut client = ClientBuilder::new();
+                if let Some(ref proxy) = proxy {
+                    client = client.no_proxy().proxy(proxy.clone());
+                }
+                if let Some((host, addrs)) = resolved {
+                    client = client.resolve_to_addrs(host, addrs);
+                }
+                client
+            };
+            let provider_client = || match connect_timeout {
+                Some(timeout) => builder().connect_timeout(timeout),
+                None => builder(),
+            };
+
+            // The redirects of the providers giving the short URL as their
+            // location must not be followed, or they would lead to the long
+            // URL.
+            let location = if has_location_providers {
+                Some(
+                    provider_client()
+                        .redirect(redirect::Policy::none())
+                        .build()?,
+                )
+            } else {
+                None
+            };
+            Ok(Clients {
+                provider: provider_client().build()?,
+                location,
+                verify: builder()
+                    .timeout(timeout.unwrap_or(DEFAULT_TIMEOUT))
+                    .redirect(redirect::Policy::none())
+                    .build()?,
+            })
+        };
+        let clients = build_clients(None)?;
+        let resolving = self.resolver.clone().map(|resolver| {
+            Arc::new(Resolving {
+                resolver,
+                build: Callback(Arc::new(build_clients)),
+                clients: Mutex::default(),
+            })
+        });
+
+        Ok(UrlShortener {
+            clients,
+            resolving,
+            timeout: self.timeout,
+            provider_timeouts: Arc::new(self.provider_timeouts),
+            adaptive_timeout: self.adaptive_timeout,
+            endpoints: Arc::new(self.endpoints),
+            parsers: Arc::new(self.parsers),
+            location_providers: Arc::new(self.location_providers),
+            max_response_size: self.max_response_size,
+            max_concurrent_per_provider: self.max_concurrent_per_provider,
+            allow_previews: self.allow_previews,
+            normalize_urls: self.normalize_urls,
+            default_scheme: self.default_scheme,
+            #[cfg(feature = "hash")]
+            hash_urls: self.hash_urls,
+            capture_headers: Arc::new(self.capture_headers),
+            qr_service: Arc::new(self.qr_service),
+            domain_filter: Arc::new(self.domain_filter),
+            block_private_addresses: self.block_private_addresses,
+            disabled: Arc::new(self.disabled),
+            allow_insecure_fallback: self.allow_insecure_fallback,
+            https_only: false,
+            attempt_hook: self.attempt_hook,
+            audit_hook: self.audit_hook,
+            interceptor: self.interceptor,
+            correlation_header: self.correlation_header,
+            correlation_id: None,
+            batch_apis: Arc::new(self.batch_apis),
+            token_steps: Arc::new(self.token_steps),
+            query_stripping: Arc::new(self.query_stripping),
+            flights: Arc::default(),
+            slots: Arc::default(),
+            stats: Arc::new(Mutex::new(self.state.providers)),
+            prefetched: Arc::default(),
+            background: Arc::default(),
+            shut_down: Arc::default(),
+            latencies: Arc::default(),
+            probed: Arc::default(),
+            rng: self
+                .rng
+                .unwrap_or_else(|| Callback(Arc::new(Mutex::new(XorShift::new())))),
+        })
+    }
+}
+
+/// Url shortener: the way to retrieve a short url.
+///
+/// The `UrlShortener` is `Send + Sync`, and its clones share the same state,
+/// so it can be used from several threads at once, for example behind an
+/// `Arc` in a web server.
+#[derive(Debug, Clone)]
+pub struct UrlShortener {
+    clients: Clients,
+    resolving: Option<Arc<Resolving>>,
+    timeout: Option<Duration>,
+    provider_timeouts: Arc<HashMap<providers::Provider, Duration>>,
+    adaptive_timeout: Option<AdaptiveTimeout>,
+    endpoints: Arc<HashMap<providers::Provider, String>>,
+    parsers: Arc<HashMap<providers::Provider, Callback<Parser>>>,
+    location_providers: Arc<HashSet<providers::Provider>>,
+    max_response_size: u64,
+    max_concurrent_per_provider: Option<usize>,
+    allow_previews: bool,
+    normalize_urls: bool,
+    default_scheme: Option<String>,
+    #[cfg(feature = "hash")]
+    hash_urls: bool,
+    capture_headers: Arc<Vec<String>>,
+    qr_service: Arc<String>,
+    domain_filter: Arc<target::DomainFilter>,
+    block_private_addresses: bool,
+    disabled: Arc<HashSet<Discriminant<providers::Provider>>>,
+    allow_insecure_fallback: bool,
+    https_only: bool,
+    attempt_hook: Option<Callback<AttemptHook>>,
+    audit_hook: Option<Callback<AuditHook>>,
+    interceptor: Option<Callback<Interceptor>>,
+    correlation_header: Option<header::HeaderName>,
+    correlation_id: Option<header::HeaderValue>,
+    batch_apis: Arc<HashMap<providers::Provider, BatchApi>>,
+    token_steps: Arc<HashMap<providers::Provider, TokenStep>>,
+    query_stripping: Arc<HashSet<providers::Provider>>,
+    flights: Arc<Flights>,
+    slots: Arc<Slots>,
+    stats: Arc<Mutex<HashMap<String, ProviderStats>>>,
+    prefetched: Arc<Prefetched>,
+    background: Arc<Mutex<Vec<thread::JoinHandle<()>>>>,
+    shut_down: Arc<AtomicBool>,
+    latencies: Arc<Mutex<HashMap<providers::Provider, Histogram>>>,
+    probed: Arc<Mutex<HashMap<providers::Provider, Option<Duration>>>>,
+    rng: Callback<SharedRng>,
+}
+
+impl UrlShortener {
+    /// Creates new `UrlShortener` with the default timeout of each provider.
+    pub fn new() -> Result<UrlShortener, reqwest::Error> {
+        UrlShortener::builder().build()
+    }
+
+    /// Creates new `UrlShortener` with custom read timeout.
+    pub fn with_timeout(seconds: u64) -> Result<UrlShortener, reqwest::Error> {
+        UrlShortener::builder()
+            .timeout(Duration::from_secs(seconds))
+            .build()
+    }
+
+    /// Creates a builder for a `UrlShortener` with custom settings.
+    pub fn builder() -> UrlShortenerBuilder {
+        UrlShortenerBuilder::default()
+    }
+
+    /// Returns whether the provider has been disabled with
+    /// [`UrlShortenerBuilder::disable`].
+    pub fn is_disabled(&self, provider: &providers::Provider) -> bool {
+        self.disabled.contains(&mem::discriminant(provider))
+    }
+
+    /// Returns whether the fallback over several providers should attempt
+    /// the provider for the URL: it must not be disabled, and take URLs as
+    /// long (see [`providers::Provider::max_input_length`]).
+    fn accepts(&self, provider: &providers::Provider, url: &str) -> bool {
+        !self.is_disabled(provider) && fits(url, provider)
+    }
+
+    /// Returns the timeout used for the requests to the provider.
+    pub fn timeout_for(&self, provider: &providers::Provider) -> Duration {
+        if let Some(&timeout) = self.provider_timeouts.get(provider) {
+            return timeout;
+        }
+        let fallback = self.timeout.unwrap_or_else(|| provider.default_timeout());
+
+        match self.adaptive_timeout {
+            Some(ref adaptive) => {
+                let stats = match self.latencies.lock().unwrap().get(provider) {
+                    Some(histogram) => histogram.stats(),
+                    None => LatencyStats::default(),
+                };
+                adaptive.timeout(&stats, fallback)
+            }
+            None => fallback,
+        }
+    }
+
+    /// Try to generate a short URL from each provider, iterating over each
+    /// provider until a short URL is successfully generated.
+    /// If you wish to override the list or providers or their priority,
+    /// provide your own list of providers as second argument.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let long_url = "https://rust-lang.org";
+    /// let _short_url = us.try_generate(long_url, None);
+    /// ```
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let providers = [
+    ///     Provider::GooGl { api_key: "MY_API_KEY".to_owned() },
+    ///     Provider::IsGd,
+    /// ];
+    /// let long_url = "https://rust-lang.org";
+    /// let _short_url = us.try_generate(long_url, Some(&providers));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Error<ProviderError>` if there is an error generating a
+    /// short URL from all providers.
+    ///
+    /// The providers which show preview or ad pages instead of redirecting
+    /// are skipped, unless allowed by
+    /// [`UrlShortenerBuilder::allow_previews`].
+    /// The disabled providers, and the ones which do not take URLs as long,
+    /// are skipped too. If no provider is left to attempt, the function fails
+    /// with `ProviderError::NoProvidersAvailable`. If the URL has a query
+    /// string, the providers which do not preserve it are attempted last.
+    ///
+    /// # Notes
+    ///
+    /// This function has been deprecated since it does not bring any UX improvements.
+    /// The body could be easily re-written as:
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let providers = [
+    ///     Provider::GooGl { api_key: "MY_API_KEY".to_owned() },
+    ///     Provider::IsGd,
+    /// ];
+    /// let long_url = "https://rust-lang.org";
+    /// let mut short_url = None;
+    /// for provider in &providers {
+    ///     if let Ok(short_url_res) = us.generate(long_url, provider) {
+    ///         short_url = Some(short_url_res);
+    ///         break;
+    ///     }
+    /// }
+    ///
+    /// ```
+    #[deprecated(since = "1.0.0", note = "Please use `generate` directly instead.")]
+    pub fn try_generate(
         &self,
-        url: S,
-        provider: &providers::Provider,
+        url: &str,
+        use_providers: Option<&[providers::Provider]>,
     ) -> Result<String, ProviderError> {
-        let req = request(url.as_ref(), provider);
+        let providers =
+            self.prefer_query_preserving(url, use_providers.unwrap_or(providers::PROVIDERS));
+        let mut error = ProviderError::NoProvidersAvailable;
+        for provider in providers.iter() {
+            if !self.accepts(provider, url)
+                || (!self.allow_previews && provider.kind() != ProviderKind::Direct)
+            {
+                continue;
+            }
+
+            let res = self.generate(url, provider);
+
+            if res.is_ok() {
+                return res;
+            }
+            error = ProviderError::Connection;
+        }
+
+        Err(error)
+    }
+
+    /// Attempts to get a short URL using the specified provider.
+    ///
+    /// Identical requests (the same URL and provider) made concurrently
+    /// through clones of the same `UrlShortener` are coalesced: only one of
+    /// them is sent, and the others wait for its result.
+    /// A short URL prefetched by [`UrlShortener::generate_prefetching`] is
+    /// returned once without any request, unless the `UrlShortener` has been
+    /// shut down or the provider disabled.
+    ///
+    /// # Examples
+    ///
+    /// ```rust, no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let long_url = "http://rust-lang.org";
+    /// let _short_url = us.generate(long_url, &Provider::IsGd);
+    /// ```
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let api_key = "MY_API_KEY".to_owned();
+    /// let long_url = "http://rust-lang.org";
+    /// let _short_url = us.generate(long_url, &Provider::GooGl { api_key: api_key });
+    /// ```
+    pub fn generate<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let url = self.target(url.as_ref(), provider)?;
+        let key = (provider.clone(), url.clone().into_owned());
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(ProviderError::Cancelled);
+        }
+        if self.is_disabled(provider) {
+            return Err(ProviderError::ProviderDisabled);
+        }
+        if let Some(short_url) = self.prefetched.take(&key) {
+            return Ok(short_url);
+        }
+        let (flight, leader) = {
+            let mut flights = self.flights.lock().unwrap();
+            match flights.get(&key) {
+                Some(flight) => (flight.clone(), false),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    flights.insert(key.clone(), flight.clone());
+                    (flight, true)
+                }
+            }
+        };
+
+        if !leader {
+            let mut result = flight.result.lock().unwrap();
+            while result.is_none() {
+                result = flight.done.wait(result).unwrap();
+            }
+            return result.clone().unwrap();
+        }
+
+        let mut guard = FlightGuard {
+            flights: &self.flights,
+            key,
+            flight,
+            result: None,
+        };
+        let res = self
+            .attempt(&request(&url, provider), provider)
+            .map(|(short_url, _)| short_url);
+        guard.result = Some(res.clone());
+
+        res
+    }
+
+    /// Shortens the URL with the first provider of the chain, then shortens
+    /// the resulting short URL with the second one, and so on, returning the
+    /// short URL of the last provider. An empty chain returns the URL as is.
+    ///
+    /// Stops at the first failing provider and returns its error.
+    ///
+    /// Note that the final link only works as long as every provider of the
+    /// chain does: the failure of any of them breaks it.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let chain = [Provider::IsGd, Provider::VGd];
+    /// let _short_url = us.generate_chained("https://rust-lang.org", &chain);
+    /// ```
+    pub fn generate_chained<S: AsRef<str>>(
+        &self,
+        url: S,
+        chain: &[providers::Provider],
+    ) -> Result<String, ProviderError> {
+        chain
+            .iter()
+            .try_fold(url.as_ref().to_owned(), |url, provider| {
+                self.generate(url, provider)
+            })
+    }
+
+    /// Attempts to get a short URL using the specified provider, returning
+    /// the details of the provider response alongside it. Unlike
+    /// [`UrlShortener::generate`], the identical requests are not coalesced.
+    ///
+    /// Only the headers selected by [`UrlShortenerBuilder::capture_headers`]
+    /// are included.
+    pub fn generate_with_meta<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<(String, ResponseMeta), ProviderError> {
+        let url = self.target(url.as_ref(), provider)?;
+        self.attempt(&request(&url, provider), provider)
+    }
+
+    /// Attempts to get a short URL using the specified provider, then renders
+    /// the QR code of the short URL as a PNG image through the QR service
+    /// (see [`UrlShortenerBuilder::qr_service`]).
+    ///
+    /// Returns `ProviderError::Deserialize` if the QR service does not answer
+    /// with a PNG image.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let (short_url, png) = us
+    ///     .generate_with_qr("https://rust-lang.org", &Provider::IsGd)
+    ///     .unwrap();
+    /// std::fs::write("qr.png", png).unwrap();
+    /// ```
+    pub fn generate_with_qr<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<(String, Vec<u8>), ProviderError> {
+        let short_url = self.generate(url, provider)?;
+        let qr = self.qr_code(&short_url)?;
+
+        Ok((short_url, qr))
+    }
+
+    /// Renders the QR code of the URL through the QR service.
+    fn qr_code(&self, url: &str) -> Result<Vec<u8>, ProviderError> {
+        let service = url::Url::parse(&self.qr_service).map_err(|_| ProviderError::InvalidInput)?;
+        let response = self
+            .clients(&service)?
+            .provider
+            .get(service)
+            .query(&[("data", url), ("size", "300x300"), ("format", "png")])
+            .timeout(self.timeout.unwrap_or(DEFAULT_TIMEOUT))
+            .send()
+            .map_err(|e| classify_error(&e))?;
+        if !response.status().is_success() {
+            return Err(ProviderError::Status(response.status().as_u16()));
+        }
+
+        let png = read_bytes(response, self.max_response_size)?;
+        if !png.starts_with(PNG_SIGNATURE) {
+            return Err(ProviderError::Deserialize);
+        }
+
+        Ok(png)
+    }
+
+    /// Attempts to get a short URL using the specified provider, like
+    /// [`UrlShortener::generate`], returning it along with the details of its
+    /// generation.
+    ///
+    /// A `201 Created` response means a new short link has been created.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let short_url = us.generate_detailed("https://rust-lang.org", &Provider::IsGd).unwrap();
+    /// if short_url.created == Some(false) {
+    ///     println!("{} already existed", short_url);
+    /// }
+    /// ```
+    pub fn generate_detailed<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<ShortUrl, ProviderError> {
+        let original = url.as_ref().to_owned();
+        let (url, meta) = self.generate_with_meta(&original, provider)?;
+        #[cfg(feature = "hash")]
+        let url_hash = if self.hash_urls {
+            let long_url = match self.default_scheme {
+                Some(ref scheme) => target::default_scheme(&original, scheme),
+                None => Cow::Borrowed(original.as_str()),
+            };
+            Some(target::url_hash(&long_url))
+        } else {
+            None
+        };
+        #[cfg(not(feature = "hash"))]
+        let url_hash = None;
+
+        Ok(ShortUrl {
+            code: providers::short_code(&url, provider),
+            url,
+            provider: provider.clone(),
+            created: meta.created,
+            created_at: meta.created_at,
+            original,
+            url_hash,
+        })
+    }
+
+    /// Prepares the long URL for sending to a provider, refusing it if it is
+    /// invalid or too long for the provider, or if its domain or its address
+    /// is not allowed.
+    fn target<'a>(
+        &self,
+        url: &'a str,
+        provider: &providers::Provider,
+    ) -> Result<Cow<'a, str>, ProviderError> {
+        let url = match self.default_scheme {
+            Some(ref scheme) => target::default_scheme(url, scheme),
+            None => Cow::Borrowed(url),
+        };
+        target::validate_url(&url)?;
+        if !fits(&url, provider) {
+            return Err(ProviderError::InputTooLong);
+        }
+        let url = match (self.normalize_urls, url) {
+            (true, Cow::Borrowed(url)) => target::normalize_url(url),
+            (true, Cow::Owned(url)) => Cow::Owned(target::normalize_url(&url).into_owned()),
+            (false, url) => url,
+        };
+        self.domain_filter.check(&url)?;
+        if self.block_private_addresses {
+            target::check_public_address(&url)?;
+        }
+
+        Ok(url)
+    }
+
+    /// Returns whether the short URLs of the provider keep the query strings
+    /// of the long URLs: whether the provider is known to (see
+    /// [`providers::Provider::preserves_query`]) and has not been flagged
+    /// otherwise with [`UrlShortenerBuilder::strips_query`].
+    pub fn preserves_query(&self, provider: &providers::Provider) -> bool {
+        provider.preserves_query() && !self.query_stripping.contains(provider)
+    }
+
+    /// Moves the providers which do not preserve query strings to the end,
+    /// if the URL has one.
+    fn prefer_query_preserving<'a>(
+        &self,
+        url: &str,
+        providers: &'a [providers::Provider],
+    ) -> Cow<'a, [providers::Provider]> {
+        let has_query = match url::Url::parse(url) {
+            Ok(url) => matches!(url.query(), Some(query) if !query.is_empty()),
+            Err(_) => false,
+        };
+        if !has_query || providers.iter().all(|p| self.preserves_query(p)) {
+            return Cow::Borrowed(providers);
+        }
+
+        let mut ordered = providers.to_vec();
+        ordered.sort_by_key(|provider| !self.preserves_query(provider));
+        Cow::Owned(ordered)
+    }
+
+    /// Performs a single provider attempt, notifying the attempt hook.
+    fn attempt(
+        &self,
+        req: &Request,
+        provider: &providers::Provider,
+    ) -> Result<(String, ResponseMeta), ProviderError> {
+        self.guarded(
+            provider,
+            || self.fetch(req, provider),
+            |(short_url, _)| short_url.clone(),
+        )
+    }
+
+    /// Runs a request to the provider, unless the shortener has been shut
+    /// down or the provider is disabled, within the per-provider slots. The
+    /// attempt hook is notified, with the outcome described by `outcome`, and
+    /// the reliability record and the latencies of the provider are updated.
+    fn guarded<T, F, O>(
+        &self,
+        provider: &providers::Provider,
+        run: F,
+        outcome: O,
+    ) -> Result<T, ProviderError>
+    where
+        F: FnOnce() -> Result<T, ProviderError>,
+        O: FnOnce(&T) -> String,
+    {
+        if self.shut_down.load(Ordering::SeqCst) {
+            return Err(ProviderError::Cancelled);
+        }
+        if self.is_disabled(provider) {
+            return Err(ProviderError::ProviderDisabled);
+        }
+
+        let _slot = self
+            .max_concurrent_per_provider
+            .map(|max| self.slots.acquire(provider, max));
+        let started = Instant::now();
+        self.notify(provider, AttemptPhase::Started, started, None);
+
+        let res = run();
+        self.record(provider, res.is_ok());
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry(provider.clone())
+            .or_default()
+            .record(started.elapsed());
+
+        if self.attempt_hook.is_some() {
+            let outcome = res.as_ref().map(outcome).map_err(|e| *e);
+            let outcome = outcome.as_ref().map(String::as_str).map_err(|e| *e);
+            self.notify(provider, AttemptPhase::Finished, started, Some(outcome));
+        }
+
+        res
+    }
+
+    /// Adds the outcome of a request to the reliability record of the
+    /// provider.
+    fn record(&self, provider: &providers::Provider, success: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let record = stats.entry(provider.to_name().to_owned()).or_default();
+        if success {
+            record.successes += 1;
+        } else {
+            record.failures += 1;
+        }
+    }
+
+    /// Returns the reliability record of the provider, empty if it has not
+    /// been requested yet.
+    pub fn provider_stats(&self, provider: &providers::Provider) -> ProviderStats {
+        let stats = self.stats.lock().unwrap();
+        stats.get(provider.to_name()).copied().unwrap_or_default()
+    }
+
+    /// Returns the providers ordered by their reliability (see
+    /// [`ProviderStats::reliability`]), the most reliable first. The
+    /// providers which are equally reliable keep their order, so that the
+    /// order of [`providers::PROVIDERS`] stands until the records say
+    /// otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::{RetryPolicy, UrlShortener}, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let ranked = us.rank_providers(PROVIDERS);
+    /// let res = us.generate_robust("https://rust-lang.org", &ranked, &RetryPolicy::default());
+    /// ```
+    pub fn rank_providers(&self, providers: &[providers::Provider]) -> Vec<providers::Provider> {
+        let mut ranked = providers.to_vec();
+        ranked.sort_by(|a, b| {
+            self.provider_stats(b)
+                .cmp_reliability(&self.provider_stats(a))
+        });
+        ranked
+    }
+
+    /// Returns the response time percentiles of every provider requested so
+    /// far, failed requests included.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let _ = us.generate("https://rust-lang.org", &Provider::TnyIm);
+    /// let stats = us.latency_stats();
+    /// println!("tny.im p99: {:?}", stats[&Provider::TnyIm].p99);
+    /// ```
+    pub fn latency_stats(&self) -> HashMap<providers::Provider, LatencyStats> {
+        let latencies = self.latencies.lock().unwrap();
+        latencies
+            .iter()
+            .map(|(provider, histogram)| (provider.clone(), histogram.stats()))
+            .collect()
+    }
+
+    /// Measures the round trip time to each provider from the current
+    /// network, with a `HEAD` request to the root of its host which creates
+    /// no link. The providers are probed concurrently.
+    ///
+    /// The measures are kept for [`UrlShortener::rank_by_latency`], a
+    /// failed probe ranking the provider last.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// for (provider, latency) in us.probe_latencies(PROVIDERS) {
+    ///     println!("{}: {:?}", provider.to_name(), latency);
+    /// }
+    /// ```
+    pub fn probe_latencies(
+        &self,
+        providers: &[providers::Provider],
+    ) -> Vec<(providers::Provider, Result<Duration, ProviderError>)> {
+        let results: Vec<_> = thread::scope(|scope| {
+            let probes: Vec<_> = providers
+                .iter()
+                .map(|provider| scope.spawn(move || self.probe(provider)))
+                .collect();
+            providers
+                .iter()
+                .cloned()
+                .zip(probes)
+                .map(|(provider, probe)| {
+                    let res = probe.join().unwrap_or(Err(ProviderError::Connection));
+                    (provider, res)
+                })
+                .collect()
+        });
+
+        let mut probed = self.probed.lock().unwrap();
+        for (provider, res) in &results {
+            probed.insert(provider.clone(), res.ok());
+        }
+        results
+    }
+
+    /// Sends the `HEAD` request of [`UrlShortener::probe_latencies`] to the
+    /// provider.
+    fn probe(&self, provider: &providers::Provider) -> Result<Duration, ProviderError> {
+        let url = request("", provider).url;
+        let url = match self.endpoints.get(provider) {
+            Some(base) => rebase(&url, base),
+            None => url,
+        };
+        let mut url = url::Url::parse(&url).map_err(|_| ProviderError::Connection)?;
+        url.set_path("/");
+        url.set_query(None);
+
+        let started = Instant::now();
+        self.clients(&url)?
+            .provider
+            .head(url)
+            .timeout(self.timeout_for(provider))
+            .send()
+            .map_err(|e| classify_error(&e))?;
+        Ok(started.elapsed())
+    }
+
+    /// Returns the providers ordered by the latencies measured by
+    /// [`UrlShortener::probe_latencies`], the fastest first. The providers
+    /// which have not been probed come next, then the ones whose probe has
+    /// failed, each keeping their order.
+    ///
+    /// This adapts the order of the providers to the network the client
+    /// runs from, rather than to a fixed table of regions.
+    pub fn rank_by_latency(&self, providers: &[providers::Provider]) -> Vec<providers::Provider> {
+        let probed = self.probed.lock().unwrap();
+        let mut ranked = providers.to_vec();
+        ranked.sort_by_key(|provider| match probed.get(provider) {
+            Some(&Some(latency)) => (0, latency),
+            None => (1, Duration::ZERO),
+            Some(None) => (2, Duration::ZERO),
+        });
+        ranked
+    }
+
+    /// Attempts to get a short URL from each provider in the order of
+    /// [`UrlShortener::rank_by_latency`], probing first the providers which
+    /// have not been probed yet. The disabled providers, and the ones which
+    /// do not take URLs as long, are skipped.
+    ///
+    /// Returns the provider which has generated the short URL, or the error
+    /// of the last provider if all of them have failed, or
+    /// `ProviderError::NoProvidersAvailable` if no provider was left to
+    /// attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// // The first call probes the providers, the next ones reuse the order.
+    /// let (provider, short_url) = us.generate_nearest("https://rust-lang.org", PROVIDERS).unwrap();
+    /// ```
+    pub fn generate_nearest<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let url = url.as_ref();
+        let candidates: Vec<_> = providers
+            .iter()
+            .filter(|provider| self.accepts(provider, url))
+            .cloned()
+            .collect();
+        let unprobed: Vec<_> = {
+            let probed = self.probed.lock().unwrap();
+            candidates
+                .iter()
+                .filter(|provider| !probed.contains_key(provider))
+                .cloned()
+                .collect()
+        };
+        if !unprobed.is_empty() {
+            self.probe_latencies(&unprobed);
+        }
+
+        let mut last_error = ProviderError::NoProvidersAvailable;
+        for provider in self.rank_by_latency(&candidates) {
+            match self.generate(url, &provider) {
+                Ok(short_url) => return Ok((provider, short_url)),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Exports the state learned about the providers, to seed another
+    /// `UrlShortener` with it (see [`UrlShortenerBuilder::with_state`]).
+    pub fn export_state(&self) -> ClientState {
+        ClientState {
+            providers: self.stats.lock().unwrap().clone(),
+        }
+    }
+
+    /// Shuts the `UrlShortener` and all its clones down: the background
+    /// prefetching (see [`UrlShortener::generate_prefetching`]) stops after
+    /// the requests in flight, which are waited for, and every request made
+    /// afterwards fails with `ProviderError::Cancelled`.
+    ///
+    /// Returns the final state learned about the providers, to be persisted
+    /// and restored by [`UrlShortenerBuilder::with_state`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// // Serving requests...
+    /// let state = us.shutdown();
+    /// let next = UrlShortener::builder().with_state(state).build().unwrap();
+    /// ```
+    pub fn shutdown(self) -> ClientState {
+        self.shut_down.store(true, Ordering::SeqCst);
+        let background = mem::take(&mut *self.background.lock().unwrap());
+        for task in background {
+            let _ = task.join();
+        }
+
+        self.export_state()
+    }
+
+    /// Attempts to get a short URL from each provider in order, retrying the
+    /// transient failures (see [`ProviderError::is_transient`]) of a provider
+    /// according to the retry policy, and moving to the next provider on the
+    /// other failures.
+    ///
+    /// The disabled providers, and the ones which do not take URLs as long,
+    /// are skipped. If the URL has a query string, the providers known to
+    /// strip it or flagged with [`UrlShortenerBuilder::strips_query`] (see
+    /// [`UrlShortener::preserves_query`]) are attempted last.
+    ///
+    /// Returns the provider which has generated the short URL, or the last
+    /// error of every provider if all of them have failed. The errors are
+    /// empty if no provider was left to attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::{RetryPolicy, UrlShortener}, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let res = us.generate_robust("https://rust-lang.org", PROVIDERS, &RetryPolicy::default());
+    /// ```
+    pub fn generate_robust<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        retry: &RetryPolicy,
+    ) -> Result<(providers::Provider, String), Vec<(providers::Provider, ProviderError)>> {
+        let backoff = ExponentialBackoff::new(retry.backoff);
+        self.generate_with_backoff(url, providers, retry.retries, &backoff)
+    }
+
+    /// Returns a [`DecorrelatedJitter`] backoff with delays between `base`
+    /// and `max`, seeded from the source of the random numbers of the
+    /// client (see [`UrlShortenerBuilder::rng`]), for
+    /// [`UrlShortener::generate_with_backoff`].
+    pub fn jitter(&self, base: Duration, max: Duration) -> DecorrelatedJitter {
+        let mut rng = self.rng.0.lock().unwrap();
+        DecorrelatedJitter::with_seed(base, max, rng.next_u64())
+    }
+
+    /// Like [`UrlShortener::generate_robust`], but retries the transient
+    /// failures of a provider up to `retries` times, waiting the delays of
+    /// the backoff strategy between them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::time::Duration;
+    /// use urlshortener::{client::UrlShortener, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let jitter = us.jitter(Duration::from_millis(100), Duration::from_secs(5));
+    /// let res = us.generate_with_backoff("https://rust-lang.org", PROVIDERS, 3, &jitter);
+    /// ```
+    pub fn generate_with_backoff<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        retries: u32,
+        backoff: &dyn Backoff,
+    ) -> Result<(providers::Provider, String), Vec<(providers::Provider, ProviderError)>> {
+        match self.fallback(url.as_ref(), providers, retries, backoff) {
+            (Some(success), _) => Ok(success),
+            (None, errors) => Err(errors),
+        }
+    }
+
+    /// Like [`UrlShortener::generate_robust`], but also returns the errors of
+    /// the providers which have failed before the one which has generated the
+    /// short URL, for example to notice that a preferred provider is down
+    /// while another one has stepped in.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::{RetryPolicy, UrlShortener}, providers::{self, PROVIDERS}};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let res = us.generate_collecting("https://rust-lang.org", PROVIDERS, &RetryPolicy::default());
+    /// if let Ok((provider, short_url, failures)) = res {
+    ///     if !failures.is_empty() {
+    ///         println!("{} by {}; {}", short_url, provider.to_name(), providers::explain(&failures));
+    ///     }
+    /// }
+    /// ```
+    pub fn generate_collecting<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        retry: &RetryPolicy,
+    ) -> Result<(providers::Provider, String, ProviderErrors), ProviderErrors> {
+        let backoff = ExponentialBackoff::new(retry.backoff);
+        match self.fallback(url.as_ref(), providers, retry.retries, &backoff) {
+            (Some((provider, short_url)), errors) => Ok((provider, short_url, errors)),
+            (None, errors) => Err(errors),
+        }
+    }
+
+    /// Attempts each provider in order until one succeeds, retrying the
+    /// transient failures, and returns the success, if any, along with the
+    /// errors of the providers which have failed before.
+    fn fallback(
+        &self,
+        url: &str,
+        providers: &[providers::Provider],
+        retries: u32,
+        backoff: &dyn Backoff,
+    ) -> (Option<(providers::Provider, String)>, ProviderErrors) {
+        let mut errors = Vec::new();
+        let providers = self.prefer_query_preserving(url, providers);
+
+        for provider in providers.iter().filter(|p| self.accepts(p, url)) {
+            let (mut attempt, mut delay) = (0, None);
+
+            let err = loop {
+                match self.generate(url, provider) {
+                    Ok(short_url) => return (Some((provider.clone(), short_url)), errors),
+                    Err(e) if e.is_transient() && attempt < retries => {
+                        let next = backoff.next_delay(attempt, delay);
+                        thread::sleep(next);
+                        delay = Some(next);
+                        attempt += 1;
+                    }
+                    Err(e) => break e,
+                }
+            };
+            errors.push((provider.clone(), err));
+        }
+
+        (None, errors)
+    }
+
+    /// Like [`UrlShortener::generate_robust`], but treats the provider tiers
+    /// (see [`providers::tier`]) as priorities: every provider of a tier is
+    /// attempted before any provider of the next one, keeping the given
+    /// order within a tier. The providers of the tiers worse than `max_tier`
+    /// are never attempted.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{
+    ///     client::{RetryPolicy, UrlShortener},
+    ///     providers::{ProviderTier, PROVIDERS},
+    /// };
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let res = us.generate_tiered(
+    ///     "https://rust-lang.org",
+    ///     PROVIDERS,
+    ///     ProviderTier::Slow,
+    ///     &RetryPolicy::default(),
+    /// );
+    /// ```
+    pub fn generate_tiered<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        max_tier: ProviderTier,
+        retry: &RetryPolicy,
+    ) -> Result<(providers::Provider, String), Vec<(providers::Provider, ProviderError)>> {
+        let mut tiered: Vec<_> = providers
+            .iter()
+            .filter(|p| providers::tier(p) <= max_tier)
+            .cloned()
+            .collect();
+        tiered.sort_by_key(providers::tier);
+
+        self.generate_robust(url, &tiered, retry)
+    }
+
+    /// Attempts to get a short URL from each provider in order, until one
+    /// succeeds or the `cancel` flag is set. The flag is checked before each
+    /// provider, so the request in flight, if any, is not interrupted.
+    ///
+    /// Returns the provider which has generated the short URL,
+    /// `ProviderError::Cancelled` if the flag has been set, the error of the
+    /// last provider if all of them have failed, or
+    /// `ProviderError::NoProvidersAvailable` if no provider was left to
+    /// attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::sync::atomic::AtomicBool;
+    /// use urlshortener::{client::UrlShortener, providers::PROVIDERS};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let cancel = AtomicBool::new(false);
+    /// let res = us.generate_with_cancel("https://rust-lang.org", PROVIDERS, &cancel);
+    /// ```
+    pub fn generate_with_cancel<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        cancel: &AtomicBool,
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let mut last_error = ProviderError::NoProvidersAvailable;
+
+        for provider in providers.iter().filter(|p| self.accepts(p, url.as_ref())) {
+            if cancel.load(Ordering::SeqCst) {
+                return Err(ProviderError::Cancelled);
+            }
+            match self.generate(url.as_ref(), provider) {
+                Ok(short_url) => return Ok((provider.clone(), short_url)),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Sends the URL to all the providers at once, each from its own thread,
+    /// and returns the first short URL received. The requests still in
+    /// flight then finish in the background, their results being dropped.
+    ///
+    /// The disabled providers, and the ones which do not take URLs as long,
+    /// are skipped. Returns the provider which has generated the short URL,
+    /// the error of the last provider to fail if all of them have failed, or
+    /// `ProviderError::NoProvidersAvailable` if no provider was left to
+    /// attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let res = us.generate_race("https://rust-lang.org", &[Provider::IsGd, Provider::VGd]);
+    /// ```
+    pub fn generate_race<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let url = url.as_ref();
+        let (sender, receiver) = mpsc::channel();
+        let mut racing = 0;
+
+        for provider in providers.iter().filter(|p| self.accepts(p, url)) {
+            let (us, url, provider) = (self.clone(), url.to_owned(), provider.clone());
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let res = us.generate(&url, &provider);
+                let _ = sender.send((provider, res));
+            });
+            racing += 1;
+        }
+
+        let mut last_error = ProviderError::NoProvidersAvailable;
+        for (provider, res) in receiver.iter().take(racing) {
+            match res {
+                Ok(short_url) => return Ok((provider, short_url)),
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Generates a short URL using a provider picked at random, each with a
+    /// probability proportional to its weight (see
+    /// [`providers::weighted_random`]), which spreads the load over several
+    /// providers. Only the providers which are not disabled and take URLs as
+    /// long may be picked.
+    ///
+    /// Returns the picked provider along with the short URL, or
+    /// `ProviderError::NoProvidersAvailable` if no provider may be picked.
+    /// A failure of the picked provider is returned as is: there is no
+    /// fallback.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let weights = [(Provider::IsGd, 2), (Provider::VGd, 2), (Provider::TinyPh, 1)];
+    /// let res = us.generate_weighted("https://rust-lang.org", &weights);
+    /// ```
+    pub fn generate_weighted<S: AsRef<str>>(
+        &self,
+        url: S,
+        weights: &[(providers::Provider, u32)],
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let candidates: Vec<_> = weights
+            .iter()
+            .filter(|(provider, _)| self.accepts(provider, url.as_ref()))
+            .cloned()
+            .collect();
+        let mut rng = self.rng.0.lock().unwrap();
+        let provider = providers::weighted_random_with(&candidates, || rng.next_u64());
+        drop(rng);
+        let provider = provider.ok_or(ProviderError::NoProvidersAvailable)?;
+        let short_url = self.generate(url, &provider)?;
+        Ok((provider, short_url))
+    }
+
+    /// Races the first `n` providers of [`providers::PROVIDERS`] taking the
+    /// URL, like [`UrlShortener::generate_race`]. This bounds the number of
+    /// requests sent, while still not waiting for a slow provider.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let (provider, short_url) = us.generate_race_top("https://rust-lang.org", 2).unwrap();
+    /// ```
+    pub fn generate_race_top<S: AsRef<str>>(
+        &self,
+        url: S,
+        n: usize,
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let candidates: Vec<_> = providers::PROVIDERS
+            .iter()
+            .filter(|p| self.accepts(p, url.as_ref()))
+            .take(n)
+            .cloned()
+            .collect();
+        self.generate_race(url, &candidates)
+    }
+
+    /// Attempts to get a short URL from each provider in order until one
+    /// succeeds, like [`UrlShortener::generate_with_cancel`], and returns it
+    /// right away. Meanwhile, up to `extra` of the next providers are
+    /// shortening the URL in the background, so that their short URLs are
+    /// ready if the first one ever gets blocked: [`UrlShortener::generate`]
+    /// returns them without any request (see also
+    /// [`UrlShortener::prefetched`]).
+    ///
+    /// Returns the provider which has generated the short URL, the error of
+    /// the last provider if all of them have failed, or
+    /// `ProviderError::NoProvidersAvailable` if no provider was left to
+    /// attempt.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::{Provider, PROVIDERS}};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let (provider, short_url) = us
+    ///     .generate_prefetching("https://rust-lang.org", PROVIDERS, 2)
+    ///     .unwrap();
+    /// // Later, if the first short URL gets blocked:
+    /// let backup = us.generate("https://rust-lang.org", &Provider::VGd);
+    /// ```
+    pub fn generate_prefetching<S: AsRef<str>>(
+        &self,
+        url: S,
+        providers: &[providers::Provider],
+        extra: usize,
+    ) -> Result<(providers::Provider, String), ProviderError> {
+        let url = url.as_ref();
+        let mut last_error = ProviderError::NoProvidersAvailable;
+        let mut candidates = providers.iter().filter(|p| self.accepts(p, url));
+
+        for provider in candidates.by_ref() {
+            match self.generate(url, provider) {
+                Ok(short_url) => {
+                    let rest: Vec<_> = candidates.take(extra).cloned().collect();
+                    if !rest.is_empty() {
+                        let (us, url) = (self.clone(), url.to_owned());
+                        let task = thread::spawn(move || us.prefetch(&url, &rest));
+                        let mut background = self.background.lock().unwrap();
+                        background.retain(|task| !task.is_finished());
+                        background.push(task);
+                    }
+                    return Ok((provider.clone(), short_url));
+                }
+                Err(e) => last_error = e,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Shortens the URL with every provider, keeping the short URLs for
+    /// [`UrlShortener::generate`].
+    fn prefetch(&self, url: &str, providers: &[providers::Provider]) {
+        for provider in providers {
+            if self.shut_down.load(Ordering::SeqCst) {
+                return;
+            }
+            let short_url = match self.generate(url, provider) {
+                Ok(short_url) => short_url,
+                Err(_) => continue,
+            };
+            if let Ok(target) = self.target(url, provider) {
+                let key = (provider.clone(), target.into_owned());
+                self.prefetched.insert(key, short_url);
+            }
+        }
+    }
+
+    /// Returns the short URL of the provider generated in the background by
+    /// [`UrlShortener::generate_prefetching`], if it is ready and has not
+    /// been returned by [`UrlShortener::generate`] yet.
+    ///
+    /// The prefetched short URLs are kept for an hour at most, and only the
+    /// 1024 most recent ones are kept.
+    pub fn prefetched(&self, url: &str, provider: &providers::Provider) -> Option<String> {
+        let target = self.target(url, provider).ok()?;
+        let key = (provider.clone(), target.into_owned());
+        self.prefetched.get(&key)
+    }
+
+    /// Attempts to get a short URL from each provider requested over HTTPS
+    /// (see [`providers::https_providers`]) in order, until one succeeds. No
+    /// provider requested over plain HTTP is ever attempted.
+    ///
+    /// The scheme of every request is checked once the endpoints and the
+    /// interceptor have been applied: a provider whose endpoint is set to a
+    /// plain HTTP one fails with `ProviderError::Unsupported`, and there is
+    /// no fallback to plain HTTP on a TLS failure, even if allowed with
+    /// [`UrlShortenerBuilder::allow_insecure_fallback`]. The short URLs
+    /// prefetched or requested meanwhile by other calls are not shared.
+    ///
+    /// Returns the provider which has generated the short URL, or the error
+    /// of every provider if all of them have failed.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::client::UrlShortener;
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let (provider, short_url) = us.generate_secure("https://rust-lang.org").unwrap();
+    /// ```
+    pub fn generate_secure<S: AsRef<str>>(
+        &self,
+        url: S,
+    ) -> Result<(providers::Provider, String), Vec<(providers::Provider, ProviderError)>> {
+        let no_retries = RetryPolicy {
+            retries: 0,
+            backoff: Duration::from_secs(0),
+        };
+        let secure = UrlShortener {
+            https_only: true,
+            allow_insecure_fallback: false,
+            flights: Arc::default(),
+            prefetched: Arc::default(),
+            ..self.clone()
+        };
+        secure.generate_robust(url, &providers::https_providers(), &no_retries)
+    }
+
+    /// Generates a short URL which expires at the specified time, using the
+    /// specified provider.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProviderError::Unsupported` if the provider does not support
+    /// link expiration.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use std::time::{Duration, SystemTime};
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let provider = Provider::Kutt { api_key: "MY_API_KEY".to_owned(), host: None };
+    /// let expires_at = SystemTime::now() + Duration::from_secs(24 * 60 * 60);
+    /// let _short_url = us.generate_with_expiry("https://rust-lang.org", expires_at, &provider);
+    /// ```
+    pub fn generate_with_expiry<S: AsRef<str>>(
+        &self,
+        url: S,
+        expires_at: SystemTime,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let req = providers::request_with_expiry(
+            &self.target(url.as_ref(), provider)?,
+            expires_at,
+            provider,
+        )?;
+        self.attempt(&req, provider).map(|(short_url, _)| short_url)
+    }
+
+    /// Generates a short URL with the given custom alias, using the
+    /// specified provider, see [`providers::request_with_alias`]. The alias is
+    /// validated before sending any request.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let _short_url = us.generate_with_alias("https://rust-lang.org", "rust_lang", &Provider::IsGd);
+    /// ```
+    pub fn generate_with_alias<S: AsRef<str>>(
+        &self,
+        url: S,
+        alias: &str,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let req =
+            providers::request_with_alias(&self.target(url.as_ref(), provider)?, alias, provider)?;
+        self.attempt(&req, provider).map(|(short_url, _)| short_url)
+    }
+
+    /// Generates short URLs for every URL of `urls` using the specified
+    /// provider, one after another, retrying the transient failures (see
+    /// [`ProviderError::is_transient`]) according to the options. Returns the
+    /// results in the order of `urls`.
+    ///
+    /// If the provider has a batch endpoint (see
+    /// [`UrlShortenerBuilder::batch_api`]), the URLs are sent in as few
+    /// requests as it allows instead, and a failed request fails all its
+    /// URLs. Each of these requests is made like a single attempt of
+    /// [`UrlShortener::generate`]: it is refused once the shortener is shut
+    /// down or the provider disabled, and it is reported to the hooks and
+    /// recorded in the statistics of the provider.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::{BatchOptions, UrlShortener}, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let urls = ["https://rust-lang.org", "https://crates.io"];
+    /// let options = BatchOptions {
+    ///     retry_budget: Some(10),
+    ///     ..BatchOptions::default()
+    /// };
+    /// let _results = us.generate_batch(&urls, &Provider::Rlu, &options);
+    /// ```
+    pub fn generate_batch<S: AsRef<str>>(
+        &self,
+        urls: &[S],
+        provider: &providers::Provider,
+        options: &BatchOptions,
+    ) -> Vec<Result<String, ProviderError>> {
+        let backoff = ExponentialBackoff::new(options.retry.backoff);
+        let mut budget = options.retry_budget;
+        let api = match self.batch_apis.get(provider) {
+            Some(api) => api,
+            None => {
+                return urls
+                    .iter()
+                    .map(|url| {
+                        retry_transient(&options.retry, &backoff, &mut budget, || {
+                            self.generate(url, provider)
+                        })
+                    })
+                    .collect()
+            }
+        };
+
+        let mut results = vec![Err(ProviderError::Connection); urls.len()];
+        let mut pending = Vec::new();
+        for (i, url) in urls.iter().enumerate() {
+            match self.target(url.as_ref(), provider) {
+                Ok(target) => pending.push((i, target.into_owned())),
+                Err(e) => results[i] = Err(e),
+            }
+        }
+        for chunk in pending.chunks(api.max_urls) {
+            let targets: Vec<String> = chunk.iter().map(|(_, target)| target.clone()).collect();
+            let res = retry_transient(&options.retry, &backoff, &mut budget, || {
+                self.send_batch(&targets, provider, api)
+            });
+            match res {
+                Ok(short_urls) => {
+                    for (&(i, _), short_url) in chunk.iter().zip(short_urls) {
+                        results[i] = Ok(short_url);
+                    }
+                }
+                Err(e) => {
+                    for &(i, _) in chunk {
+                        results[i] = Err(e);
+                    }
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Returns the most URLs sent in one request to the batch endpoint of the
+    /// provider, or `None` if it has none (see
+    /// [`UrlShortenerBuilder::batch_api`]).
+    pub fn max_batch_size(&self, provider: &providers::Provider) -> Option<usize> {
+        self.batch_apis.get(provider).map(|api| api.max_urls)
+    }
+
+    /// Sends one request to the batch endpoint of the provider, returning
+    /// the short URLs of all the URLs. The request is guarded and reported
+    /// like a single attempt, its outcome being the short URLs separated by
+    /// spaces.
+    fn send_batch(
+        &self,
+        urls: &[String],
+        provider: &providers::Provider,
+        api: &BatchApi,
+    ) -> Result<Vec<String>, ProviderError> {
+        let joined = |short_urls: &Vec<String>| short_urls.join(" ");
+        let parse = |body: &str| match (api.parser.0)(body) {
+            Some(short_urls) if short_urls.len() == urls.len() => Ok(short_urls),
+            _ => Err(ProviderError::Deserialize),
+        };
+        let req = (api.request.0)(urls);
+
+        self.guarded(
+            provider,
+            || self.fetch_with(&req, provider, parse, joined),
+            joined,
+        )
+    }
+
+    /// Sends the request to the provider and parses the body of its
+    /// successful response, passing the audit record, with the outcome
+    /// described by `outcome`, to the audit hook, if any.
+    fn fetch_with<T, P, O>(
+        &self,
+        req: &Request,
+        provider: &providers::Provider,
+        parse: P,
+        outcome: O,
+    ) -> Result<T, ProviderError>
+    where
+        P: FnOnce(&str) -> Result<T, ProviderError>,
+        O: FnOnce(&T) -> String,
+    {
+        let req = self.build_request(req, provider)?;
+        self.check_secure(req.url())?;
+        let max_size = self.max_response_size;
+        self.exchange(
+            req,
+            provider,
+            false,
+            |response| {
+                if !response.status().is_success() {
+                    return Err(ProviderError::Status(response.status().as_u16()));
+                }
+                read_body(response, max_size).and_then(|body| parse(&body))
+            },
+            outcome,
+        )
+    }
+
+    /// Asks the provider for the long URL the short URL leads to (see
+    /// [`providers::request_expand`]), without following it. The request is
+    /// made like an attempt of [`UrlShortener::generate`], refused for a
+    /// disabled provider or once the shortener is shut down.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let long_url = us.expand("http://tny.im/abc", &Provider::TnyIm);
+    /// ```
+    pub fn expand(
+        &self,
+        short_url: &str,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let req = providers::request_expand(short_url, provider)?;
+        self.guarded(
+            provider,
+            || {
+                self.fetch_with(
+                    &req,
+                    provider,
+                    |body| providers::parse_expand(body, provider),
+                    String::clone,
+                )
+            },
+            String::clone,
+        )
+    }
+
+    /// Generates a short URL using the specified provider, then asks the
+    /// provider for the long URL it leads to (see [`UrlShortener::expand`])
+    /// to check that the query string of the long URL has survived.
+    ///
+    /// The check is skipped for a URL without a query string, and for a
+    /// provider which cannot expand its short URLs: its short URL is then
+    /// trusted according to [`UrlShortener::preserves_query`] alone.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProviderError::VerificationFailed` if the short URL leads to
+    /// a long URL with another query string, along with the errors of
+    /// [`UrlShortener::generate`] and [`UrlShortener::expand`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let short_url = us.generate_checking_query("https://example.com/?id=1", &Provider::TnyIm);
+    /// ```
+    pub fn generate_checking_query<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let short_url = self.generate(url.as_ref(), provider)?;
+        let query = |url: &str| match url::Url::parse(url) {
+            Ok(parsed) => parsed.query().filter(|q| !q.is_empty()).map(str::to_owned),
+            Err(_) => None,
+        };
+        let submitted = query(&self.target(url.as_ref(), provider)?);
+        if submitted.is_none() {
+            return Ok(short_url);
+        }
+
+        match self.expand(&short_url, provider) {
+            Ok(long_url) if query(&long_url) == submitted => Ok(short_url),
+            Ok(_) => Err(ProviderError::VerificationFailed),
+            Err(ProviderError::Unsupported) => Ok(short_url),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Asks the provider for the statistics of the short URL (see
+    /// [`providers::request_url_stats`]), like [`UrlShortener::expand`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{client::UrlShortener, providers::Provider};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let stats = us.url_stats("http://tny.im/abc", &Provider::TnyIm).unwrap();
+    /// println!("{} clicks", stats.clicks);
+    /// ```
+    pub fn url_stats(
+        &self,
+        short_url: &str,
+        provider: &providers::Provider,
+    ) -> Result<providers::UrlStats, ProviderError> {
+        let req = providers::request_url_stats(short_url, provider)?;
+        let long_url = |stats: &providers::UrlStats| stats.long_url.clone();
+        self.guarded(
+            provider,
+            || {
+                self.fetch_with(
+                    &req,
+                    provider,
+                    |body| providers::parse_url_stats(body, provider),
+                    long_url,
+                )
+            },
+            long_url,
+        )
+    }
+
+    /// Generates short URLs for every URL of `urls` using the specified
+    /// provider, one after another, with the custom aliases produced by the
+    /// generator. Returns the results in the order of `urls`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{alias::RandomBase62, providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let urls = ["https://rust-lang.org", "https://crates.io"];
+    /// let _results = us.generate_batch_with_aliases(&urls, &Provider::IsGd, &mut RandomBase62::new(8));
+    /// ```
+    pub fn generate_batch_with_aliases<S, G>(
+        &self,
+        urls: &[S],
+        provider: &providers::Provider,
+        generator: &mut G,
+    ) -> Vec<Result<String, ProviderError>>
+    where
+        S: AsRef<str>,
+        G: AliasGenerator + ?Sized,
+    {
+        urls.iter()
+            .map(|url| {
+                let alias = generator.next(url.as_ref());
+                self.generate_with_alias(url, &alias, provider)
+            })
+            .collect()
+    }
+
+    /// Generates a short URL using the specified provider, passing it extra
+    /// parameters, see [`providers::request_with_params`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let params = [("logstats", "1")];
+    /// let _short_url = us.generate_with_params("https://rust-lang.org", &Provider::IsGd, &params);
+    /// ```
+    pub fn generate_with_params<S: AsRef<str>, K: AsRef<str>, V: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+        params: &[(K, V)],
+    ) -> Result<String, ProviderError> {
+        let req =
+            providers::request_with_params(&self.target(url.as_ref(), provider)?, provider, params);
+        self.attempt(&req, provider).map(|(short_url, _)| short_url)
+    }
+
+    /// Generates a short URL using the specified provider and then checks
+    /// that it actually redirects to the long URL, possibly through several
+    /// redirects.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ProviderError::VerificationFailed` if the short URL does not
+    /// lead to the long URL, as it has been submitted to the provider with
+    /// [`UrlShortenerBuilder::default_scheme`] and
+    /// [`UrlShortenerBuilder::normalize_urls`] applied.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let _short_url = us.generate_and_verify("https://rust-lang.org/", &Provider::IsGd);
+    /// ```
+    pub fn generate_and_verify<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<String, ProviderError> {
+        let short_url = self.generate(url.as_ref(), provider)?;
+        // The short URL leads to the long URL as it has been submitted, with
+        // the default scheme and the normalization applied.
+        self.verify(&short_url, &self.target(url.as_ref(), provider)?)?;
+        Ok(short_url)
+    }
+
+    /// Checks that `short_url` redirects to `target`.
+    fn verify(&self, short_url: &str, target: &str) -> Result<(), ProviderError> {
+        let target = url::Url::parse(target).map_err(|_| ProviderError::VerificationFailed)?;
+        let mut current =
+            url::Url::parse(short_url).map_err(|_| ProviderError::VerificationFailed)?;
+
+        for _ in 0..MAX_VERIFY_REDIRECTS {
+            let response = self
+                .clients(&current)
+                .map_err(|_| ProviderError::VerificationFailed)?
+                .verify
+                .get(current.as_str())
+                .send()
+                .map_err(|_| ProviderError::VerificationFailed)?;
+
+            if !response.status().is_redirection() {
+                break;
+            }
+
+            current = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| current.join(location).ok())
+                .ok_or(ProviderError::VerificationFailed)?;
+
+            if current == target {
+                return Ok(());
+            }
+        }
+
+        Err(ProviderError::VerificationFailed)
+    }
+
+    /// Invokes the attempt hook, if any.
+    fn notify(
+        &self,
+        provider: &providers::Provider,
+        phase: AttemptPhase,
+        started: Instant,
+        outcome: Option<Result<&str, ProviderError>>,
+    ) {
+        if let Some(Callback(ref hook)) = self.attempt_hook {
+            let event = AttemptEvent {
+                provider,
+                phase,
+                elapsed: started.elapsed(),
+                outcome,
+            };
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&event)));
+        }
+    }
+
+    /// Returns the request which would be sent to the provider for
+    /// shortening the URL, without performing any network I/O.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let preview = us.preview("https://rust-lang.org", &Provider::IsGd).unwrap();
+    /// assert_eq!(
+    ///     preview.url,
+    ///     "https://is.gd/create.php?format=simple&url=https%3A%2F%2Frust-lang.org"
+    /// );
+    /// ```
+    pub fn preview<S: AsRef<str>>(
+        &self,
+        url: S,
+        provider: &providers::Provider,
+    ) -> Result<RequestPreview, ProviderError> {
+        if self.is_disabled(provider) {
+            return Err(ProviderError::ProviderDisabled);
+        }
+
+        let req = self.build_request(
+            &request(&self.target(url.as_ref(), provider)?, provider),
+            provider,
+        )?;
+
+        Ok(RequestPreview::new(&req))
+    }
+
+    /// Returns the HTTP clients of the requests to the URL: those connecting
+    /// to the addresses the resolver returns for its host, if any, or the
+    /// ones using the system resolver.
+    fn clients(&self, url: &url::Url) -> Result<Clients, ProviderError> {
+        let (resolving, host) = match (&self.resolving, url.host()) {
+            (Some(resolving), Some(url::Host::Domain(host))) => (resolving, host),
+            _ => return Ok(self.clients.clone()),
+        };
+        let addrs = (resolving.resolver.0)(host);
+        if addrs.is_empty() {
+            return Ok(self.clients.clone());
+        }
+
+        let mut cache = resolving.clients.lock().unwrap();
+        match cache.get(host) {
+            Some((cached, clients)) if *cached == addrs => return Ok(clients.clone()),
+            _ => {}
+        }
+        // The port is taken from the request URL.
+        let sockets: Vec<_> = addrs.iter().map(|&ip| SocketAddr::new(ip, 0)).collect();
+        let clients =
+            (resolving.build.0)(Some((host, &sockets))).map_err(|e| classify_error(&e))?;
+        if cache.len() >= MAX_RESOLVED_HOSTS && !cache.contains_key(host) {
+            cache.clear();
+        }
+        cache.insert(host.to_owned(), (addrs, clients.clone()));
+
+        Ok(clients)
+    }
+
+    /// Prepares the request to the provider.
+    fn prepare(&self, req: &Request, provider: &providers::Provider) -> RequestBuilder {
+        let builder = match self.endpoints.get(provider) {
+            Some(base) => Request {
+                url: rebase(&req.url, base),
+                ..req.clone()
+            }
+            .build(&self.clients.provider),
+            None => req.build(&self.clients.provider),
+        };
+
+        builder.timeout(self.timeout_for(provider))
+    }
+
+    /// Builds the request to the provider, applying the interceptor.
+    fn build_request(
+        &self,
+        req: &Request,
+        provider: &providers::Provider,
+    ) -> Result<reqwest::blocking::Request, ProviderError> {
+        let mut req = self
+            .prepare(req, provider)
+            .build()
+            .map_err(|e| classify_error(&e))?;
+        if let Some(ref name) = self.correlation_header {
+            let id = match self.correlation_id {
+                Some(ref id) => id.clone(),
+                None => {
+                    let random = self.rng.0.lock().unwrap().next_u64();
+                    header::HeaderValue::from_str(&format!("{:016x}", random))
+                        .expect("a hexadecimal string is a valid header value")
+                }
+            };
+            req.headers_mut().insert(name.clone(), id);
+        }
+        if let Some(Callback(ref interceptor)) = self.interceptor {
+            interceptor(&mut req);
+        }
+
+        Ok(req)
+    }
+
+    /// Returns a `UrlShortener` sharing the state of this one, whose
+    /// provider requests all carry the given correlation ID, for example the
+    /// trace ID of the operation being served. The header is the one set
+    /// with [`UrlShortenerBuilder::correlation_header`], `X-Request-Id` by
+    /// default.
+    ///
+    /// Returns `ProviderError::InvalidInput` if the ID is not a valid header
+    /// value.
+    pub fn with_correlation_id(&self, id: &str) -> Result<UrlShortener, ProviderError> {
+        let id = header::HeaderValue::from_str(id).map_err(|_| ProviderError::InvalidInput)?;
+        let mut traced = self.clone();
+        traced
+            .correlation_header
+            .get_or_insert_with(|| header::HeaderName::from_static("x-request-id"));
+        traced.correlation_id = Some(id);
+
+        Ok(traced)
+    }
+
+    /// Sends the request to the provider and parses its response, falling
+    /// back to plain HTTP on a TLS failure if allowed.
+    fn fetch(
+        &self,
+        req: &Request,
+        provider: &providers::Provider,
+    ) -> Result<(String, ResponseMeta), ProviderError> {
+        let req = match self.token_steps.get(provider) {
+            Some(step) => Cow::Owned(self.fetch_token(req, provider, step)?),
+            None => Cow::Borrowed(req),
+        };
+        let req = self.build_request(&req, provider)?;
+        self.check_secure(req.url())?;
+        let fallback = if self.allow_insecure_fallback && req.url().scheme() == "https" {
+            req.try_clone()
+        } else {
+            None
+        };
+
+        let res = self.send(req, provider);
+        match (res, fallback) {
+            (Err(ProviderError::Tls), Some(mut req)) => {
+                let _ = req.url_mut().set_scheme("http");
+                log::warn!(
+                    "The TLS connection to {} has failed, retrying over plain HTTP: {}",
+                    provider.to_name(),
+                    req.url()
+                );
+                self.send(req, provider)
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Refuses a request over plain HTTP with `ProviderError::Unsupported`
+    /// for [`UrlShortener::generate_secure`].
+    fn check_secure(&self, url: &url::Url) -> Result<(), ProviderError> {
+        if self.https_only && url.scheme() != "https" {
+            Err(ProviderError::Unsupported)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Fetches the page of the token step and returns the request carrying
+    /// its token and its cookies.
+    fn fetch_token(
+        &self,
+        req: &Request,
+        provider: &providers::Provider,
+        step: &TokenStep,
+    ) -> Result<Request, ProviderError> {
+        let page = Request {
+            url: step.page.clone(),
+            body: None,
+            content_type: None,
+            user_agent: req.user_agent.clone(),
+            headers: None,
+            method: crate::request::Method::Get,
+        };
+        let page = self
+            .prepare(&page, provider)
+            .build()
+            .map_err(|e| classify_error(&e))?;
+        self.check_secure(page.url())?;
+        let response = self
+            .clients(page.url())?
+            .provider
+            .execute(page)
+            .map_err(|e| classify_error(&e))?;
+        if !response.status().is_success() {
+            return Err(ProviderError::Status(response.status().as_u16()));
+        }
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|cookie| cookie.to_str().ok()?.split(';').next())
+            .map(str::trim)
+            .filter(|cookie| !cookie.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        let body = read_body(response, self.max_response_size)?;
+        let token = (step.extract.0)(&body).ok_or(ProviderError::Deserialize)?;
+        let mut req = req.clone();
+        (step.apply.0)(&mut req, &token);
+        if !cookies.is_empty() {
+            let cookies = header::HeaderValue::from_str(&cookies.join("; "))
+                .map_err(|_| ProviderError::Deserialize)?;
+            req.headers
+                .get_or_insert_with(header::HeaderMap::new)
+                .insert(header::COOKIE, cookies);
+        }
+
+        Ok(req)
+    }
+
+    /// Sends the request to the provider and parses its response, passing
+    /// the audit record to the audit hook, if any.
+    fn send(
+        &self,
+        req: reqwest::blocking::Request,
+        provider: &providers::Provider,
+    ) -> Result<(String, ResponseMeta), ProviderError> {
+        self.exchange(
+            req,
+            provider,
+            self.location_providers.contains(provider),
+            |response| self.read_response(response, provider),
+            |(short_url, _)| short_url.clone(),
+        )
+    }
+
+    /// Sends the request to the provider, with the client not following the
+    /// redirects if `location` is set, and reads its response, passing the
+    /// audit record, with the outcome described by `outcome`, to the audit
+    /// hook, if any.
+    fn exchange<T, R, O>(
+        &self,
+        req: reqwest::blocking::Request,
+        provider: &providers::Provider,
+        location: bool,
+        read: R,
+        outcome: O,
+    ) -> Result<T, ProviderError>
+    where
+        R: FnOnce(Response) -> Result<T, ProviderError>,
+        O: FnOnce(&T) -> String,
+    {
+        let sent = self.audit_hook.as_ref().map(|_| RequestPreview::new(&req));
+
+        let started = Instant::now();
+        let mut status = None;
+        let res = self
+            .clients(req.url())
+            .and_then(|clients| {
+                let client = match clients.location {
+                    Some(client) if location => client,
+                    _ => clients.provider,
+                };
+                client.execute(req).map_err(|e| classify_error(&e))
+            })
+            .and_then(|response| {
+                status = Some(response.status().as_u16());
+                read(response)
+            });
+
+        if let (Some(Callback(hook)), Some(request)) = (&self.audit_hook, sent) {
+            let record = AuditRecord {
+                provider: provider.clone(),
+                request,
+                status,
+                duration: started.elapsed(),
+                outcome: res.as_ref().map(outcome).map_err(|e| *e),
+            };
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| hook(&record)));
+        }
+
+        res
+    }
+
+    /// Parses the response of the provider.
+    fn read_response(
+        &self,
+        response: Response,
+        provider: &providers::Provider,
+    ) -> Result<(String, ResponseMeta), ProviderError> {
+        let status = response.status();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .filter(|_| self.location_providers.contains(provider));
+        let accepted = status.is_success() || (status.is_redirection() && location.is_some());
+        if !accepted {
+            return Err(ProviderError::Status(status.as_u16()));
+        }
+
+        let mut meta = ResponseMeta {
+            status: status.as_u16(),
+            headers: self
+                .capture_headers
+                .iter()
+                .filter_map(|name| {
+                    let value = response.headers().get(name.as_str())?.to_str().ok()?;
+                    Some((name.clone(), value.to_owned()))
+                })
+                .collect(),
+            created: None,
+            format: None,
+            created_at: None,
+        };
+        if let Some(location) = location {
+            let short_url = location
+                .to_str()
+                .ok()
+                .and_then(|location| response.url().join(location.trim()).ok())
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .ok_or(ProviderError::Deserialize)?;
+            meta.created = if meta.status == 201 { Some(true) } else { None };
+            meta.format = Some(providers::ResponseFormat::Location);
+            return Ok((short_url.into(), meta));
+        }
+
+        let body = read_body(response, self.max_response_size)?;
+        let (short_url, format) = match self.parsers.get(provider) {
+            Some(Callback(parser)) => (
+                parser(&body).ok_or(ProviderError::Deserialize)?,
+                providers::ResponseFormat::Custom,
+            ),
+            None => (parse(&body, provider)?, provider.response_format()),
+        };
+        meta.format = Some(format);
+        meta.created = match meta.status {
+            201 => Some(true),
+            _ => providers::link_created(&body, provider),
+        };
+        meta.created_at = providers::created_at(&body, provider);
+
+        Ok((short_url, meta))
+    }
+
+    /// Generates short URLs for every URL of `urls` using the specified
+    /// provider, keeping at most `concurrency` requests in flight, and calls
+    /// `on_progress` with the number of the finished URLs and the total
+    /// number of URLs each time one of them finishes.
+    ///
+    /// Returns the results in the order of `urls`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let urls = ["https://rust-lang.org", "https://crates.io"];
+    /// let results = us.generate_batch_with_progress(&urls, &Provider::IsGd, 4, |done, total| {
+    ///     eprint!("\r{}/{}", done, total);
+    /// });
+    /// ```
+    pub fn generate_batch_with_progress<S, F>(
+        &self,
+        urls: &[S],
+        provider: &providers::Provider,
+        concurrency: usize,
+        mut on_progress: F,
+    ) -> Vec<Result<String, ProviderError>>
+    where
+        S: AsRef<str>,
+        F: FnMut(usize, usize),
+    {
+        let total = urls.len();
+        let mut positions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, url) in urls.iter().enumerate().rev() {
+            positions.entry(url.as_ref()).or_default().push(i);
+        }
+
+        let mut results = vec![Err(ProviderError::Connection); total];
+        let stream = self.generate_stream(
+            urls.iter().map(|url| url.as_ref().to_owned()),
+            provider,
+            concurrency,
+        );
+        for (done, (url, res)) in stream.enumerate() {
+            if let Some(i) = positions.get_mut(url.as_str()).and_then(Vec::pop) {
+                results[i] = res;
+            }
+            on_progress(done + 1, total);
+        }
+
+        results
+    }
+
+    /// Lazily generates short URLs for every URL of `urls` using the
+    /// specified provider, keeping at most `concurrency` requests in flight.
+    ///
+    /// The input is only pulled when there is a free slot, so the memory
+    /// usage stays flat regardless of the input size. The results are
+    /// yielded as they complete, which is not necessarily the input order.
+    ///
+    /// As the crate has no async runtime, this is the blocking counterpart
+    /// of an asynchronous stream: the results are pulled from an `Iterator`
+    /// backed by worker threads, which are joined when it is dropped (see
+    /// [`GenerateStream`]).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use urlshortener::{providers::Provider, client::UrlShortener};
+    ///
+    /// let us = UrlShortener::new().unwrap();
+    /// let urls = (0..1000).map(|i| format!("https://rust-lang.org/?page={}", i));
+    /// for (long_url, short_url) in us.generate_stream(urls, &Provider::IsGd, 4) {
+    ///     println!("{} -> {:?}", long_url, short_url);
+    /// }
+    /// ```
+    pub fn generate_stream<I>(
+        &self,
+        urls: I,
+        provider: &providers::Provider,
+        concurrency: usize,
+    ) -> GenerateStream<I::IntoIter>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let (jobs, queue) = mpsc::channel();
+
+        GenerateStream {
+            shortener: self.clone(),
+            provider: provider.clone(),
+            urls: urls.into_iter(),
+            concurrency: concurrency.max(1),
+            in_flight: 0,
+            workers: Vec::new(),
+            jobs: Some(jobs),
+            queue: Arc::new(Mutex::new(queue)),
+            sender: Some(sender),
+            receiver,
+        }
+    }
+}
+
+/// Shortens the URL using the specified provider and the given HTTP client,
+/// in a single call: the request is built, sent with the default timeout of
+/// the provider, and its response is read and parsed.
+///
+/// This is handy when an HTTP client is already at hand. The
+/// [`UrlShortener`] offers more options.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten, providers::Provider};
+///
+/// let client = Client::new();
+/// let _short_url = shorten("https://rust-lang.org", &client, &Provider::IsGd);
+/// ```
+pub fn shorten(
+    url: &str,
+    client: &Client,
+    provider: &providers::Provider,
+) -> Result<String, ProviderError> {
+    let response = send_direct(url, client, provider)?;
+    read_body(response, DEFAULT_MAX_RESPONSE_SIZE).and_then(|t| parse(&t, provider))
+}
+
+/// Sends the request shortening the URL to the provider with the given HTTP
+/// client, and returns its successful response.
+fn send_direct(
+    url: &str,
+    client: &Client,
+    provider: &providers::Provider,
+) -> Result<Response, ProviderError> {
+    let response = request(url, provider)
+        .build(client)
+        .timeout(provider.default_timeout())
+        .send()
+        .map_err(|e| classify_error(&e))?;
+    if !response.status().is_success() {
+        return Err(ProviderError::Status(response.status().as_u16()));
+    }
+
+    Ok(response)
+}
+
+/// Shortens the URL like [`shorten`] with each provider requested over HTTPS
+/// (see [`providers::https_providers`]) in order, until one succeeds, and
+/// returns the short URL along with its provider. No provider requested
+/// over plain HTTP is ever attempted, and a response reached over plain
+/// HTTP, like after a redirect by the client, fails its provider with
+/// `ProviderError::Unsupported`.
+///
+/// Returns the error of the last provider if all of them have failed. See
+/// [`UrlShortener::generate_secure`] for the same policy with the options of
+/// a [`UrlShortener`].
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::client::shorten_secure;
+///
+/// let client = Client::new();
+/// let (provider, short_url) = shorten_secure("https://rust-lang.org", &client).unwrap();
+/// ```
+pub fn shorten_secure(
+    url: &str,
+    client: &Client,
+) -> Result<(providers::Provider, String), ProviderError> {
+    let mut last_error = ProviderError::NoProvidersAvailable;
+    for provider in providers::https_providers() {
+        let res = send_direct(url, client, &provider).and_then(|response| {
+            if response.url().scheme() != "https" {
+                return Err(ProviderError::Unsupported);
+            }
+            read_body(response, DEFAULT_MAX_RESPONSE_SIZE).and_then(|t| parse(&t, &provider))
+        });
+        match res {
+            Ok(short_url) => return Ok((provider, short_url)),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Shortens the URL like [`shorten`], appending the short URL to `buf`
+/// instead of returning it, so that one buffer can be reused across many
+/// calls by a writer of batch results. The response body is read into `buf`
+/// as well, and the short URL then takes its place, so that a buffer grown
+/// by the first calls spares the allocation of the next bodies. `buf` is
+/// left as is on failure.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten_into, providers::Provider};
+///
+/// let client = Client::new();
+/// let mut buf = String::new();
+/// for url in &["https://rust-lang.org", "https://crates.io"] {
+///     buf.clear();
+///     if shorten_into(url, &mut buf, &client, &Provider::IsGd).is_ok() {
+///         println!("{}", buf);
+///     }
+/// }
+/// ```
+pub fn shorten_into(
+    url: &str,
+    buf: &mut String,
+    client: &Client,
+    provider: &providers::Provider,
+) -> Result<(), ProviderError> {
+    let response = send_direct(url, client, provider)?;
+    let start = buf.len();
+    let res = read_body_into(response, DEFAULT_MAX_RESPONSE_SIZE, buf)
+        .and_then(|()| parse(&buf[start..], provider));
+    buf.truncate(start);
+    buf.push_str(&res?);
+    Ok(())
+}
+
+/// Shortens the URL like [`shorten`], and bundles the short URL with its
+/// related addresses (see [`LinkBundle`]).
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten_full, providers::Provider};
+///
+/// let client = Client::new();
+/// let bundle = shorten_full("https://rust-lang.org", &client, &Provider::IsGd).unwrap();
+/// println!("{} (stats: {:?})", bundle.short, bundle.stats);
+/// ```
+pub fn shorten_full(
+    url: &str,
+    client: &Client,
+    provider: &providers::Provider,
+) -> Result<LinkBundle, ProviderError> {
+    shorten(url, client, provider).map(|short| LinkBundle::new(short, provider))
+}
+
+/// Shortens the URL like [`shorten`] with each provider of `order` until one
+/// succeeds, and returns the short URL along with its provider. When every
+/// provider fails, the original URL is returned instead, without any
+/// provider. This suits the uses where a long URL is still fine, like
+/// logging.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten_or_original, providers::PROVIDERS};
+///
+/// let client = Client::new();
+/// let (_provider, url) = shorten_or_original("https://rust-lang.org", &client, PROVIDERS);
+/// println!("See {}", url);
+/// ```
+pub fn shorten_or_original(
+    url: &str,
+    client: &Client,
+    order: &[providers::Provider],
+) -> (Option<providers::Provider>, String) {
+    match shorten_where(url, client, order.iter().cloned(), |_| true) {
+        Some((provider, short_url)) => (Some(provider), short_url),
+        None => (None, url.to_owned()),
+    }
+}
+
+/// Shortens the URL like [`shorten`] with each provider pulled from
+/// `providers` until one succeeds with a short URL accepted by `accept`, and
+/// returns it along with its provider, or `None` if there is none. The
+/// providers are pulled lazily, so none is attempted after the accepted
+/// one.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten_where, providers::PROVIDERS};
+///
+/// let client = Client::new();
+/// let res = shorten_where(
+///     "https://rust-lang.org",
+///     &client,
+///     PROVIDERS.iter().cloned(),
+///     |short_url| short_url.len() < 20,
+/// );
+/// ```
+pub fn shorten_where<I, F>(
+    url: &str,
+    client: &Client,
+    providers: I,
+    accept: F,
+) -> Option<(providers::Provider, String)>
+where
+    I: IntoIterator<Item = providers::Provider>,
+    F: Fn(&str) -> bool,
+{
+    providers.into_iter().find_map(|provider| {
+        shorten(url, client, &provider)
+            .ok()
+            .filter(|short_url| accept(short_url))
+            .map(|short_url| (provider, short_url))
+    })
+}
+
+/// Checks whether the short URL still works, that is whether it redirects
+/// somewhere, using the given HTTP client. A `HEAD` request is sent, or a
+/// `GET` one if the server does not take `HEAD`.
+///
+/// Returns `false` when the server answers without redirecting, like with
+/// `404 Not Found` or `410 Gone`.
+///
+/// # Errors
+///
+/// Returns `ProviderError::InvalidInput` if the short URL is not a valid
+/// URL, `ProviderError::Status` if the server fails with `429 Too Many
+/// Requests` or a `5xx` status, since the link may work once it has
+/// recovered, and the connection errors.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::client::is_reachable;
+///
+/// let client = Client::new();
+/// if !is_reachable("https://is.gd/abc", &client).unwrap() {
+///     println!("The link is dead.");
+/// }
+/// ```
+pub fn is_reachable(short_url: &str, client: &Client) -> Result<bool, ProviderError> {
+    let url = url::Url::parse(short_url).map_err(|_| ProviderError::InvalidInput)?;
+    let send = |method: reqwest::Method| {
+        client
+            .request(method, url.clone())
+            .send()
+            .map_err(|e| classify_error(&e))
+    };
+
+    let mut response = send(reqwest::Method::HEAD)?;
+    if matches!(response.status().as_u16(), 405 | 501) {
+        response = send(reqwest::Method::GET)?;
+    }
+
+    let status = response.status();
+    if status.as_u16() == 429 || status.is_server_error() {
+        return Err(ProviderError::Status(status.as_u16()));
+    }
+
+    // A client following the redirects ends up on another URL.
+    Ok(status.is_redirection() || *response.url() != url)
+}
+
+/// Returns the share of the long URL length saved by the short URL (see
+/// [`ShortUrl::savings_ratio`]).
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::{client::{savings, ShortUrl}, providers::Provider};
+///
+/// let short_url = ShortUrl {
+///     url: "https://is.gd/abc".to_owned(),
+///     provider: Provider::IsGd,
+///     code: Some("abc".to_owned()),
+///     created: None,
+///     created_at: None,
+///     original: format!("https://example.com/{}", "a".repeat(48)),
+///     url_hash: None,
+/// };
+/// assert_eq!(savings(&short_url), 0.75);
+/// ```
+pub fn savings(result: &ShortUrl) -> f32 {
+    result.savings_ratio()
+}
+
+/// Reads a provider response body from `reader` and parses the short URL
+/// out of it, like the responses received by a `UrlShortener`: the body is
+/// limited to 1 MiB, decoded as UTF-8 and checked the same way (see
+/// [`providers::parse`]). This serves the responses received through
+/// another HTTP client.
+///
+/// # Example
+///
+/// ```rust
+/// use std::io::Cursor;
+/// use urlshortener::{client::parse_response, providers::Provider};
+///
+/// let body = Cursor::new(r#"{"url": "https://bam.bz/abc"}"#);
+/// assert_eq!(
+///     parse_response(body, &Provider::BamBz),
+///     Ok("https://bam.bz/abc".to_owned())
+/// );
+/// ```
+pub fn parse_response<R: Read>(
+    reader: R,
+    provider: &providers::Provider,
+) -> Result<String, ProviderError> {
+    let body = read_limited(reader, 0, DEFAULT_MAX_RESPONSE_SIZE)?;
+    parse(&decode_body(body, None), provider)
+}
+
+/// Returns the address of the QR code image of the URL, rendered by the
+/// default QR service as a PNG image.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::client::qr_code_url;
+///
+/// assert_eq!(
+///     qr_code_url("https://is.gd/abc"),
+///     "https://api.qrserver.com/v1/create-qr-code/?data=https%3A%2F%2Fis.gd%2Fabc&size=300x300&format=png"
+/// );
+/// ```
+pub fn qr_code_url(url: &str) -> String {
+    url::Url::parse_with_params(
+        DEFAULT_QR_SERVICE,
+        &[("data", url), ("size", "300x300"), ("format", "png")],
+    )
+    .expect("the default QR service address is valid")
+    .into()
+}
+
+/// Shortens every URL of `urls` like [`shorten`], spreading them over
+/// `workers` threads sharing the HTTP client. Returns the results in the
+/// order of `urls`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use reqwest::blocking::Client;
+/// use urlshortener::{client::shorten_batch_parallel, providers::Provider};
+///
+/// let client = Client::new();
+/// let urls = ["https://rust-lang.org", "https://crates.io"];
+/// let results = shorten_batch_parallel(&urls, &client, &Provider::IsGd, 4);
+/// ```
+pub fn shorten_batch_parallel<S: AsRef<str> + Sync>(
+    urls: &[S],
+    client: &Client,
+    provider: &providers::Provider,
+    workers: usize,
+) -> Vec<Result<String, ProviderError>> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(vec![Err(ProviderError::Connection); urls.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, urls.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let url = match urls.get(i) {
+                    Some(url) => url.as_ref(),
+                    None => break,
+                };
+                let res = shorten(url, client, provider);
+                results.lock().unwrap()[i] = res;
+            });
+        }
+    });
+
+    results.into_inner().unwrap()
+}
+
+/// Calls `send` until it succeeds or fails with an error which is not
+/// transient, at most `retry.retries` more times and within the remaining
+/// retry budget, if any.
+fn retry_transient<T, F>(
+    retry: &RetryPolicy,
+    backoff: &dyn Backoff,
+    budget: &mut Option<u32>,
+    mut send: F,
+) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Result<T, ProviderError>,
+{
+    let (mut attempt, mut delay) = (0, None);
+    loop {
+        match send() {
+            Err(e) if e.is_transient() && attempt < retry.retries && *budget != Some(0) => {
+                if let Some(ref mut budget) = budget {
+                    *budget -= 1;
+                }
+                let next = backoff.next_delay(attempt, delay);
+                thread::sleep(next);
+                delay = Some(next);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Reads the whole response body, whatever its transfer encoding is,
+/// enforcing the maximum response size.
+fn read_body(response: Response, max_size: u64) -> Result<String, ProviderError> {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    read_bytes(response, max_size).map(|body| decode_body(body, content_type.as_deref()))
+}
+
+/// Reads the response body like [`read_body`], appending it to `buf`, which
+/// is left as is on failure.
+fn read_body_into(
+    response: Response,
+    max_size: u64,
+    buf: &mut String,
+) -> Result<(), ProviderError> {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let announced = response.content_length();
+    if matches!(announced, Some(len) if len > max_size) {
+        return Err(ProviderError::ResponseTooLarge);
+    }
+
+    let start = buf.len();
+    let mut bytes = mem::take(buf).into_bytes();
+    bytes.reserve(announced.unwrap_or(0) as usize);
+    let res = read_limited_into(response, &mut bytes, max_size);
+    if res.is_ok() && decodes_to_itself(&bytes[start..], content_type.as_deref()) {
+        *buf = String::from_utf8(bytes).unwrap_or_default();
+        return Ok(());
+    }
+
+    let body = bytes.split_off(start);
+    *buf = String::from_utf8(bytes).unwrap_or_default();
+    res.map(|()| buf.push_str(&decode_body(body, content_type.as_deref())))
+}
+
+/// Reads the whole response body as bytes, enforcing the maximum response
+/// size.
+///
+/// A body announced larger than the maximum is rejected before reading any
+/// of it, and the buffer is allocated once for the announced length.
+fn read_bytes(response: Response, max_size: u64) -> Result<Vec<u8>, ProviderError> {
+    let announced = response.content_length();
+    if matches!(announced, Some(len) if len > max_size) {
+        return Err(ProviderError::ResponseTooLarge);
+    }
+
+    read_limited(response, announced.unwrap_or(0) as usize, max_size)
+}
+
+/// Reads the whole stream into a buffer of the given initial capacity,
+/// failing with `ProviderError::ResponseTooLarge` beyond `max_size` bytes.
+fn read_limited<R: Read>(
+    reader: R,
+    capacity: usize,
+    max_size: u64,
+) -> Result<Vec<u8>, ProviderError> {
+    let mut body = Vec::with_capacity(capacity);
+    read_limited_into(reader, &mut body, max_size)?;
+    Ok(body)
+}
+
+/// Reads the whole stream like [`read_limited`], appending it to `body`.
+fn read_limited_into<R: Read>(
+    reader: R,
+    body: &mut Vec<u8>,
+    max_size: u64,
+) -> Result<(), ProviderError> {
+    let start = body.len();
+    reader
+        .take(max_size + 1)
+        .read_to_end(body)
+        .map_err(|e| classify_io_error(&e))?;
+
+    if (body.len() - start) as u64 > max_size {
+        return Err(ProviderError::ResponseTooLarge);
+    }
+
+    Ok(())
+}
+
+/// Returns whether the URL is not longer than the provider accepts.
+fn fits(url: &str, provider: &providers::Provider) -> bool {
+    match provider.max_input_length() {
+        Some(max) => url.len() <= max,
+        None => true,
+    }
+}
+
+/// Decodes the response body with the charset declared by its content type,
+/// falling back to UTF-8.
+#[cfg(feature = "charset")]
+fn decode_body(body: Vec<u8>, content_type: Option<&str>) -> String {
+    let encoding = body_encoding(content_type);
+
+    // A valid UTF-8 body without a byte order mark decodes to itself, so the
+    // buffer is reused rather than copied.
+    let body = if encoding == encoding_rs::UTF_8 && encoding_rs::Encoding::for_bom(&body).is_none()
+    {
+        match String::from_utf8(body) {
+            Ok(body) => return body,
+            Err(e) => e.into_bytes(),
+        }
+    } else {
+        body
+    };
+
+    encoding.decode(&body).0.into_owned()
+}
+
+/// Returns the encoding declared by the charset of the content type,
+/// defaulting to UTF-8.
+#[cfg(feature = "charset")]
+fn body_encoding(content_type: Option<&str>) -> &'static encoding_rs::Encoding {
+    content_type
+        .and_then(|content_type| {
+            content_type
+                .split(';')
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+                .map(|(_, value)| value.trim().trim_matches('"').to_owned())
+        })
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8)
+}
+
+/// Returns whether [`decode_body`] would return the body unchanged.
+#[cfg(feature = "charset")]
+fn decodes_to_itself(body: &[u8], content_type: Option<&str>) -> bool {
+    body_encoding(content_type) == encoding_rs::UTF_8
+        && encoding_rs::Encoding::for_bom(body).is_none()
+        && std::str::from_utf8(body).is_ok()
+}
+
+/// Decodes the response body as UTF-8, reusing the buffer when it is valid.
+#[cfg(not(feature = "charset"))]
+fn decode_body(body: Vec<u8>, _content_type: Option<&str>) -> String {
+    String::from_utf8(body).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+/// Returns whether [`decode_body`] would return the body unchanged.
+#[cfg(not(feature = "charset"))]
+fn decodes_to_itself(body: &[u8], _content_type: Option<&str>) -> bool {
+    std::str::from_utf8(body).is_ok()
+}
+
+/// Maps an HTTP client error into the matching `ProviderError`.
+///
+/// A timeout is reported as `ProviderError::ConnectTimeout` or
+/// `ProviderError::ReadTimeout`, depending on whether the connection has been
+/// established. A failure to resolve the provider domain is reported as
+/// `ProviderError::Dns`, as it usually means the service is gone, a failure
+/// of the TLS handshake as `ProviderError::Tls`, and any other failure as
+/// `ProviderError::Connection`.
+pub fn classify_error(err: &reqwest::Error) -> ProviderError {
+    if err.is_timeout() {
+        return if err.is_connect() {
+            ProviderError::ConnectTimeout
+        } else {
+            ProviderError::ReadTimeout
+        };
+    }
+
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    let mut root_cause = String::new();
+    while let Some(e) = source {
+        root_cause = e.to_string();
+        if root_cause.starts_with("dns error") {
+            return ProviderError::Dns;
+        }
+        source = e.source();
+    }
+
+    // The root cause never contains the request URL, which could mention
+    // these words too.
+    let root_cause = root_cause.to_lowercase();
+    if err.is_connect()
+        && ["ssl", "tls", "certificate"]
+            .iter()
+            .any(|w| root_cause.contains(w))
+    {
+        return ProviderError::Tls;
+    }
+
+    ProviderError::Connection
+}
+
+/// Maps an error of reading a response body into the matching
+/// `ProviderError`.
+fn classify_io_error(err: &std::io::Error) -> ProviderError {
+    let timed_out = err.kind() == std::io::ErrorKind::TimedOut
+        || matches!(
+            err.get_ref().and_then(|e| e.downcast_ref::<reqwest::Error>()),
+            Some(e) if e.is_timeout()
+        );
+
+    if timed_out {
+        ProviderError::ReadTimeout
+    } else {
+        ProviderError::Connection
+    }
+}
+
+/// Replaces the scheme, the host and the port of the URL with the base ones.
+fn rebase(url: &str, base: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(parsed) => format!(
+            "{}{}",
+            base.trim_end_matches('/'),
+            &parsed[url::Position::BeforePath..]
+        ),
+        Err(_) => url.to_owned(),
+    }
+}
+
+/// A long URL along with the result of shortening it.
+type StreamResult = (String, Result<String, ProviderError>);
+
+/// An iterator over the results of [`UrlShortener::generate_stream`].
+///
+/// Yields the long URL together with the result of shortening it. The URLs
+/// are shortened by up to `concurrency` worker threads, started as needed.
+/// Dropping the stream discards the URLs no worker has picked up yet, and
+/// waits for the workers to finish the requests in flight.
+#[derive(Debug)]
+pub struct GenerateStream<I> {
+    shortener: UrlShortener,
+    provider: providers::Provider,
+    urls: I,
+    concurrency: usize,
+    in_flight: usize,
+    workers: Vec<thread::JoinHandle<()>>,
+    /// Closed when the stream is dropped.
+    jobs: Option<Sender<String>>,
+    queue: Arc<Mutex<Receiver<String>>>,
+    /// Handed to the workers started, and dropped once no other one will be,
+    /// so that the results end when all the workers are gone.
+    sender: Option<Sender<StreamResult>>,
+    receiver: Receiver<StreamResult>,
+}
+
+impl<I> GenerateStream<I> {
+    /// Starts a worker shortening the URLs of the queue until it is closed.
+    fn start_worker(&mut self, sender: Sender<StreamResult>) {
+        let (shortener, provider) = (self.shortener.clone(), self.provider.clone());
+        let queue = self.queue.clone();
+
+        self.workers.push(thread::spawn(move || loop {
+            // The queue is unlocked before the URL is shortened.
+            let next = queue.lock().unwrap().recv();
+            let url = match next {
+                Ok(url) => url,
+                Err(_) => return,
+            };
+            // A panic, like one of a request interceptor, fails this URL only.
+            let res = panic::catch_unwind(AssertUnwindSafe(|| shortener.generate(&url, &provider)))
+                .unwrap_or(Err(ProviderError::Connection));
+            if sender.send((url, res)).is_err() {
+                return;
+            }
+        }));
+    }
+}
+
+impl<I> Drop for GenerateStream<I> {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Ok(queue) = self.queue.lock() {
+            while queue.try_recv().is_ok() {}
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<I: Iterator<Item = String>> Iterator for GenerateStream<I> {
+    type Item = StreamResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.in_flight < self.concurrency {
+            let url = match self.urls.next() {
+                Some(url) => url,
+                None => {
+                    self.sender = None;
+                    break;
+                }
+            };
+            if self.workers.len() <= self.in_flight {
+                if let Some(sender) = self.sender.clone() {
+                    self.start_worker(sender);
+                }
+            }
+            if self.workers.len() == self.concurrency {
+                self.sender = None;
+            }
+            match self.jobs {
+                Some(ref jobs) if jobs.send(url).is_ok() => {}
+                _ => break,
+            }
+            self.in_flight += 1;
+        }
+
+        if self.in_flight == 0 {
+            return None;
+        }
+
+        self.in_flight -= 1;
+        self.receiver.recv().ok()
+    }
+}
+
+/// Periodically checks that the short URLs created earlier still work (see
+/// [`is_reachable`]), and reports the ones which have broken.
+///
+/// A link is reported when it breaks, and again only if it breaks after
+/// having worked again. The failed checks, like the timeouts, leave the
+/// health of the link unchanged, as it may be fine.
+///
+/// The links are checked by [`LinkMonitor::poll`], or in the background
+/// once the monitor is started. No check is made once the `UrlShortener`
+/// has been shut down.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+/// use urlshortener::{client::{LinkMonitor, UrlShortener}, providers::Provider};
+///
+/// let us = UrlShortener::new().unwrap();
+/// let link = us.generate_detailed("https://rust-lang.org", &Provider::IsGd).unwrap();
+/// let handle = LinkMonitor::new(&us, vec![link], Duration::from_secs(600))
+///     .on_broken(|link| eprintln!("{} is broken", link))
+///     .start();
+/// // Serving requests...
+/// let monitor = handle.stop();
+/// println!("{} broken links", monitor.broken().len());
+/// ```
+#[derive(Debug)]
+pub struct LinkMonitor {
+    shortener: UrlShortener,
+    links: Vec<MonitoredLink>,
+    interval: Duration,
+    on_broken: Option<Callback<BreakageHook>>,
+    clock: Callback<Clock>,
+}
+
+/// A link checked by a [`LinkMonitor`].
+#[derive(Debug)]
+struct MonitoredLink {
+    link: ShortUrl,
+    next_check: Option<Instant>,
+    broken: bool,
+}
+
+impl LinkMonitor {
+    /// Creates a monitor checking each of the links every `interval`, with
+    /// the HTTP client of the `UrlShortener`. The first check of every link
+    /// is due at once.
+    pub fn new<I: IntoIterator<Item = ShortUrl>>(
+        shortener: &UrlShortener,
+        links: I,
+        interval: Duration,
+    ) -> LinkMonitor {
+        LinkMonitor {
+            shortener: shortener.clone(),
+            links: links
+                .into_iter()
+                .map(|link| MonitoredLink {
+                    link,
+                    next_check: None,
+                    broken: false,
+                })
+                .collect(),
+            interval,
+            on_broken: None,
+            clock: Callback(Arc::new(Instant::now)),
+        }
+    }
+
+    /// Sets a callback called with every link found broken.
+    pub fn on_broken<F>(mut self, hook: F) -> LinkMonitor
+    where
+        F: Fn(&ShortUrl) + Send + Sync + 'static,
+    {
+        self.on_broken = Some(Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Sets the source of the current time deciding which links are due, in
+    /// place of [`Instant::now`], for example to control it in tests.
+    pub fn clock<F>(mut self, clock: F) -> LinkMonitor
+    where
+        F: Fn() -> Instant + Send + Sync + 'static,
+    {
+        self.clock = Callback(Arc::new(clock));
+        self
+    }
+
+    /// Adds a link to monitor, whose first check is due at once.
+    pub fn add(&mut self, link: ShortUrl) {
+        self.links.push(MonitoredLink {
+            link,
+            next_check: None,
+            broken: false,
+        });
+    }
+
+    /// Returns the links found broken by their last successful check.
+    pub fn broken(&self) -> Vec<&ShortUrl> {
+        self.links
+            .iter()
+            .filter(|monitored| monitored.broken)
+            .map(|monitored| &monitored.link)
+            .collect()
+    }
+
+    /// Returns when the next check is due, or `None` if there is no link to
+    /// monitor.
+    pub fn next_check(&self) -> Option<Instant> {
+        let now = (self.clock.0)();
+        self.links
+            .iter()
+            .map(|monitored| monitored.next_check.unwrap_or(now))
+            .min()
+    }
+
+    /// Checks the links which are due, and returns the ones which have
+    /// broken since their previous check, after calling the callback with
+    /// each of them.
+    pub fn poll(&mut self) -> Vec<ShortUrl> {
+        let now = (self.clock.0)();
+        let mut broken = Vec::new();
+
+        for monitored in &mut self.links {
+            if matches!(monitored.next_check, Some(at) if at > now) {
+                continue;
+            }
+            if self.shortener.shut_down.load(Ordering::SeqCst) {
+                break;
+            }
+
+            monitored.next_check = Some(now + self.interval);
+            let shortener = &self.shortener;
+            let client = url::Url::parse(&monitored.link.url)
+                .ok()
+                .and_then(|url| shortener.clients(&url).ok())
+                .map_or_else(
+                    || shortener.clients.verify.clone(),
+                    |clients| clients.verify,
+                );
+            match is_reachable(&monitored.link.url, &client) {
+                Ok(true) => monitored.broken = false,
+                Ok(false) if !monitored.broken => {
+                    monitored.broken = true;
+                    log::warn!("The short URL {} is broken", monitored.link.url);
+                    if let Some(ref hook) = self.on_broken {
+                        (hook.0)(&monitored.link);
+                    }
+                    broken.push(monitored.link.clone());
+                }
+                _ => {}
+            }
+        }
+
+        broken
+    }
+
+    /// Starts checking the links in the background, on time, until the
+    /// returned handle is stopped or dropped, or the `UrlShortener` is shut
+    /// down.
+    pub fn start(mut self) -> MonitorHandle {
+        let (stop, stopped) = mpsc::channel();
+        let task = thread::spawn(move || loop {
+            self.poll();
+            if self.shortener.shut_down.load(Ordering::SeqCst) {
+                return self;
+            }
+
+            let wait = match self.next_check() {
+                Some(at) => at.saturating_duration_since((self.clock.0)()),
+                None => self.interval,
+            };
+            match stopped.recv_timeout(wait) {
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                _ => return self,
+            }
+        });
+
+        MonitorHandle { stop, task }
+    }
+}
+
+/// A [`LinkMonitor`] checking its links in the background, returned by
+/// [`LinkMonitor::start`]. The monitor stops when the handle is dropped.
+#[derive(Debug)]
+pub struct MonitorHandle {
+    stop: Sender<()>,
+    task: thread::JoinHandle<LinkMonitor>,
+}
+
+impl MonitorHandle {
+    /// Stops the monitor after the check in progress, if any, and returns
+    /// it, so that it can be inspected or started again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the breakage callback has panicked.
+    pub fn stop(self) -> LinkMonitor {
+        let _ = self.stop.send(());
+        self.task
+            .join()
+            .unwrap_or_else(|cause| panic::resume_unwind(cause))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client;
+    use crate::mock::{MockResponse, MockServer};
+    use crate::providers::{self, Provider};
+    use crate::target;
+    use std::collections::HashSet;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Returns an address nothing listens on.
+    fn dead_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    /// A provider which sends its requests to the given mock address.
+    fn mock_provider(address: &str) -> providers::Provider {
+        providers::Provider::Kutt {
+            api_key: "key".to_owned(),
+            host: Some(address.to_owned()),
+        }
+    }
+
+    /// A short URL response of the `mock_provider`.
+    fn short(short_url: &str) -> MockResponse {
+        MockResponse::json("shortUrl", short_url)
+    }
+
+    #[test]
+    fn generate_stream() {
+        let current = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+        let (c, m) = (current.clone(), max.clone());
+        let server = MockServer::start(move |req| {
+            let now = c.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            c.fetch_sub(1, Ordering::SeqCst);
+            let id = req
+                .body
+                .rsplit("page=")
+                .next()
+                .unwrap()
+                .trim_end_matches("\"}");
+            short(&format!("http://kutt.it/{}", id))
+        })
+        .unwrap();
+
+        let us = client::UrlShortener::new().unwrap();
+        let urls = (0..10).map(|i| format!("http://example.com/?page={}", i));
+        let results: Vec<_> = us
+            .generate_stream(urls, &mock_provider(server.address()), 3)
+            .collect();
+
+        assert_eq!(results.len(), 10);
+        let inputs: HashSet<_> = results.iter().map(|(url, _)| url.clone()).collect();
+        assert_eq!(inputs.len(), 10);
+        for (url, res) in results {
+            let id = url.rsplit("page=").next().unwrap();
+            assert_eq!(res.unwrap(), format!("http://kutt.it/{}", id));
+        }
+        assert!(max.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn generate_stream_workers() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let seen = threads.clone();
+        let us = client::UrlShortener::builder()
+            .request_interceptor(move |req| {
+                seen.lock().unwrap().insert(thread::current().id());
+                let body = req
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .unwrap_or_default();
+                if String::from_utf8_lossy(body).contains("panic") {
+                    panic!("interceptor failure");
+                }
+            })
+            .build()
+            .unwrap();
+
+        let urls = (0..20).map(|i| match i {
+            7 => "http://example.com/panic".to_owned(),
+            i => format!("http://example.com/{}", i),
+        });
+        let results: Vec<_> = us
+            .generate_stream(urls, &mock_provider(server.address()), 3)
+            .collect();
+
+        assert_eq!(results.len(), 20);
+        for (url, res) in results {
+            if url.ends_with("panic") {
+                assert_eq!(res, Err(providers::ProviderError::Connection));
+            } else {
+                assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+            }
+        }
+        assert!(threads.lock().unwrap().len() <= 3);
+        assert_eq!(server.hits(), 19);
+
+        // Dropping the stream early joins the workers, no URL left queued.
+        let urls = (0..20).map(|i| format!("http://example.com/{}", i));
+        let mut stream = us.generate_stream(urls, &mock_provider(server.address()), 3);
+        assert!(stream.next().is_some());
+        drop(stream);
+        assert_eq!(Arc::strong_count(&us.flights), 1);
+        assert!(server.hits() <= 19 + 3);
+    }
+
+    #[test]
+    fn correlation_id() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .correlation_header(reqwest::header::HeaderName::from_static("x-trace-id"))
+            .build()
+            .unwrap();
+
+        let traced = us.with_correlation_id("trace-1").unwrap();
+        assert!(traced.generate("http://example.com/a", &provider).is_ok());
+        assert!(us.generate("http://example.com/b", &provider).is_ok());
+        assert!(us.generate("http://example.com/c", &provider).is_ok());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("X-Trace-Id"), Some("trace-1"));
+        let generated: Vec<_> = requests[1..]
+            .iter()
+            .map(|req| req.header("X-Trace-Id").unwrap().to_owned())
+            .collect();
+        assert!(generated
+            .iter()
+            .all(|id| id.len() == 16 && id.chars().all(|c| c.is_ascii_hexdigit())));
+        assert_ne!(generated[0], generated[1]);
+
+        let untraced = client::UrlShortener::new().unwrap();
+        let preview = untraced.preview("http://example.com", &provider).unwrap();
+        assert!(preview.headers.get("X-Request-Id").is_none());
+        let preview = untraced
+            .with_correlation_id("trace-2")
+            .unwrap()
+            .preview("http://example.com", &provider)
+            .unwrap();
+        assert_eq!(preview.headers["X-Request-Id"], "trace-2");
+        assert_eq!(
+            untraced.with_correlation_id("a\nb").unwrap_err(),
+            providers::ProviderError::InvalidInput
+        );
+    }
+
+    #[test]
+    fn max_concurrent_per_provider() {
+        let current = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+        let (c, m) = (current.clone(), max.clone());
+        let server = MockServer::start(move |_| {
+            let now = c.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            c.fetch_sub(1, Ordering::SeqCst);
+            short("http://kutt.it/abc")
+        })
+        .unwrap();
+
+        let us = client::UrlShortener::builder()
+            .max_concurrent_per_provider(2)
+            .build()
+            .unwrap();
+        let urls = (0..8).map(|i| format!("http://example.com/?page={}", i));
+        let results: Vec<_> = us
+            .generate_stream(urls, &mock_provider(server.address()), 8)
+            .collect();
+
+        assert_eq!(results.len(), 8);
+        assert!(results.iter().all(|(_, res)| res.is_ok()));
+        assert_eq!(server.hits(), 8);
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+
+    /// This test does not cover services which require authentication for obvious reasons.
+    #[test]
+    fn providers() {
+        let us = client::UrlShortener::with_timeout(5).unwrap();
+        let url = "http://yandex.com";
+        let mut valid = 0;
+
+        for provider in providers::PROVIDERS {
+            if let Err(e) = us.generate(url, provider) {
+                println!("{:?} -> {:?}", provider, e);
+            } else {
+                valid += 1;
+                println!("{:?} -> OK", provider);
+            }
+        }
+
+        assert!(valid > 0, "There are no valid providers to use.");
+    }
+
+    #[test]
+    fn attempt_hook() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let observed = events.clone();
+        let us = client::UrlShortener::builder()
+            .attempt_hook(move |event| {
+                observed.lock().unwrap().push((
+                    event.provider.clone(),
+                    event.phase,
+                    event.outcome.map(|o| o.map(String::from)),
+                ));
+                panic!("The hook must not break the request.");
+            })
+            .build()
+            .unwrap();
+        let provider = mock_provider(server.address());
+
+        let res = us.generate("http://example.com", &provider);
+
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (provider.clone(), client::AttemptPhase::Started, None),
+                (
+                    provider,
+                    client::AttemptPhase::Finished,
+                    Some(Ok("http://kutt.it/abc".to_owned()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_identical_requests() {
+        let server = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(200));
+            short("http://kutt.it/abc")
+        })
+        .unwrap();
+        let us = client::UrlShortener::new().unwrap();
+        let provider = mock_provider(server.address());
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let us = us.clone();
+                let provider = provider.clone();
+                thread::spawn(move || us.generate("http://example.com", &provider))
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok("http://kutt.it/abc".to_owned()));
+        }
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn generate_and_verify() {
+        fn shortener_to(target: &'static str) -> MockServer {
+            MockServer::start(move |req| match (req.method.as_str(), req.path.as_str()) {
+                ("POST", _) => short(&format!("http://{}/abc", req.header("host").unwrap())),
+                (_, "/abc") => MockResponse::redirect(target),
+                _ => MockResponse::plain(""),
+            })
+            .unwrap()
+        }
+        let us = client::UrlShortener::new().unwrap();
+        let long_url = "http://example.com/";
+
+        let server = shortener_to("http://example.com/");
+        let short_url = format!("{}/abc", server.address());
+        let res = us.generate_and_verify(long_url, &mock_provider(server.address()));
+        assert_eq!(res, Ok(short_url));
+
+        let server = shortener_to("/elsewhere");
+        let res = us.generate_and_verify(long_url, &mock_provider(server.address()));
+        assert_eq!(res, Err(providers::ProviderError::VerificationFailed));
+
+        let us = client::UrlShortener::builder()
+            .default_scheme("http")
+            .normalize_urls(true)
+            .build()
+            .unwrap();
+        let server = shortener_to("http://example.com/x");
+        let short_url = format!("{}/abc", server.address());
+        let res = us.generate_and_verify("Example.com/x", &mock_provider(server.address()));
+        assert_eq!(res, Ok(short_url));
+    }
+
+    #[test]
+    fn short_url_in_location() {
+        let server =
+            MockServer::with_response(MockResponse::redirect("https://tiny.ph/abc")).unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::TinyPh)
+            .short_url_in_location(Provider::TinyPh)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::TinyPh);
+        assert_eq!(res, Ok("https://tiny.ph/abc".to_owned()));
+        assert_eq!(server.hits(), 1);
+
+        let server =
+            MockServer::with_response(MockResponse::new(201, "").header("Location", "/abc"))
+                .unwrap();
+        let builder = server.attach(client::UrlShortener::builder(), Provider::TinyPh);
+        let us = builder
+            .clone()
+            .short_url_in_location(Provider::TinyPh)
+            .build()
+            .unwrap();
+        let res = us.generate_detailed("http://example.com", &Provider::TinyPh);
+        let short = res.unwrap();
+        assert_eq!(short.url, format!("{}/abc", server.address()));
+
+        let res = builder
+            .build()
+            .unwrap()
+            .generate("http://example.com", &Provider::TinyPh);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn chunked_response() {
+        let server = MockServer::with_response(short("http://kutt.it/abcdef").chunked()).unwrap();
+        let provider = mock_provider(server.address());
+
+        let us = client::UrlShortener::new().unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Ok("http://kutt.it/abcdef".to_owned()));
+
+        let us = client::UrlShortener::builder()
+            .max_response_size(16)
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Err(providers::ProviderError::ResponseTooLarge));
+    }
+
+    #[test]
+    fn preview() {
+        let server = MockServer::with_response(MockResponse::plain("")).unwrap();
+        let us = client::UrlShortener::new().unwrap();
+
+        let preview = us
+            .preview("http://example.com/?a=b", &mock_provider(server.address()))
+            .unwrap();
+
+        assert_eq!(preview.method, reqwest::Method::POST);
+        assert_eq!(preview.url, format!("{}/api/url/submit", server.address()));
+        assert_eq!(preview.headers["content-type"], "application/json");
+        assert_eq!(preview.headers["x-api-key"], "key");
+        assert_eq!(
+            preview.body.as_deref(),
+            Some(r#"{"target": "http://example.com/?a=b"}"#)
+        );
+        assert_eq!(server.hits(), 0);
+
+        let preview = us.preview("a b", &providers::Provider::SirBz).unwrap();
+        assert_eq!(
+            preview.headers["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+    }
+
+    #[test]
+    fn adaptive_timeout() {
+        let us = client::UrlShortener::builder()
+            .adaptive_timeout(client::AdaptiveTimeout::new(Duration::from_millis(100)))
+            .provider_timeout(Provider::VGd, Duration::from_secs(1))
+            .build()
+            .unwrap();
+        let record = |provider: &Provider, millis: u64| {
+            us.latencies
+                .lock()
+                .unwrap()
+                .entry(provider.clone())
+                .or_default()
+                .record(Duration::from_millis(millis));
+        };
+
+        for _ in 0..19 {
+            record(&Provider::IsGd, 200);
+        }
+        assert_eq!(us.timeout_for(&Provider::IsGd), Duration::from_secs(2));
+        for i in 0..81 {
+            record(&Provider::IsGd, 150 + i % 50);
+        }
+        let timeout = us.timeout_for(&Provider::IsGd);
+        assert!(
+            Duration::from_millis(280) <= timeout && timeout <= Duration::from_millis(320),
+            "{:?}",
+            timeout
+        );
+
+        // The timeouts are measured too, but can't push the timeout past twice
+        // the static one.
+        for _ in 0..100 {
+            record(&Provider::TnyIm, 30_000);
+        }
+        assert_eq!(us.timeout_for(&Provider::TnyIm), Duration::from_secs(20));
+        for _ in 0..100 {
+            record(&Provider::VGd, 50);
+        }
+        assert_eq!(us.timeout_for(&Provider::VGd), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn provider_timeouts() {
+        let us = client::UrlShortener::new().unwrap();
+        assert!(us.timeout_for(&Provider::TnyIm) > us.timeout_for(&Provider::BamBz));
+        assert!(us.timeout_for(&Provider::IsGd) < us.timeout_for(&Provider::BamBz));
+
+        let us = client::UrlShortener::builder()
+            .timeout(Duration::from_secs(7))
+            .provider_timeout(Provider::IsGd, Duration::from_secs(1))
+            .build()
+            .unwrap();
+        assert_eq!(us.timeout_for(&Provider::TnyIm), Duration::from_secs(7));
+        assert_eq!(us.timeout_for(&Provider::IsGd), Duration::from_secs(1));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn try_generate_skips_previews() {
+        let attempted = Arc::new(Mutex::new(Vec::new()));
+        let observed = attempted.clone();
+        let us = client::UrlShortener::builder()
+            .attempt_hook(move |event| {
+                if event.phase == client::AttemptPhase::Started {
+                    observed.lock().unwrap().push(event.provider.clone());
+                }
+            })
+            .build()
+            .unwrap();
+        let failing = mock_provider(&dead_address());
+        let providers = [failing.clone(), Provider::NowLinks, Provider::PhxCoIn];
+
+        let res = us.try_generate("http://example.com", Some(&providers));
+
+        assert!(res.is_err());
+        assert_eq!(*attempted.lock().unwrap(), vec![failing]);
+    }
+
+    #[test]
+    fn normalize_urls() {
+        let server = MockServer::start(|req| {
+            let target = req.body.rsplit("\"target\": \"").next().unwrap();
+            short(target.trim_end_matches("\"}"))
+        })
+        .unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .normalize_urls(true)
+            .build()
+            .unwrap();
+
+        let res = us.generate("HTTP://Example.com:80/a/../b", &provider);
+        assert_eq!(res, Ok("http://example.com/b".to_owned()));
+    }
+
+    #[test]
+    fn default_scheme() {
+        let server = MockServer::start(|req| {
+            let target = req.body.rsplit("\"target\": \"").next().unwrap();
+            short(target.trim_end_matches("\"}"))
+        })
+        .unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .default_scheme("https")
+            .build()
+            .unwrap();
+
+        let res = us.generate("example.com", &provider);
+        assert_eq!(res, Ok("https://example.com".to_owned()));
+        let res = us.generate("http://example.com/a", &provider);
+        assert_eq!(res, Ok("http://example.com/a".to_owned()));
+        assert_eq!(
+            us.generate("example.com/\n", &provider),
+            Err(providers::ProviderError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn endpoint() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let us = client::UrlShortener::builder()
+            .endpoint(Provider::IsGd, server.address())
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            us.generate("http://example.com", &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<client::UrlShortener>();
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let server =
+            MockServer::start(|req| short(&format!("http://kutt.it/{}", req.body.len()))).unwrap();
+        let provider = mock_provider(server.address());
+        let us = Arc::new(client::UrlShortener::new().unwrap());
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let us = us.clone();
+                let provider = provider.clone();
+                thread::spawn(move || {
+                    for j in 0..10 {
+                        let url = format!("http://example.com/{}/{}", i, j);
+                        assert!(us.generate(&url, &provider).is_ok());
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(server.hits(), 80);
+    }
+
+    #[test]
+    fn classify_error() {
+        let client = reqwest::blocking::Client::new();
+
+        let err = client
+            .get("http://does-not-exist.invalid/")
+            .send()
+            .unwrap_err();
+        assert_eq!(client::classify_error(&err), providers::ProviderError::Dns);
+
+        let err = client.get(dead_address()).send().unwrap_err();
+        assert_eq!(
+            client::classify_error(&err),
+            providers::ProviderError::Connection
+        );
+    }
+
+    #[test]
+    fn generate_robust() {
+        let a_hits = Arc::new(AtomicUsize::new(0));
+        let hits = a_hits.clone();
+        let a = MockServer::start(move |_| match hits.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new(503, ""),
+            _ => MockResponse::new(400, ""),
+        })
+        .unwrap();
+        let b = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let retry = client::RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let order = [mock_provider(a.address()), mock_provider(b.address())];
+        let us = client::UrlShortener::new().unwrap();
+
+        let res = us.generate_robust("http://example.com", &order, &retry);
+
+        assert_eq!(res, Ok((order[1].clone(), "http://kutt.it/abc".to_owned())));
+        assert_eq!(a.hits(), 2);
+        assert_eq!(b.hits(), 1);
+
+        let res = us.generate_robust("http://example.com", &order[..1], &retry);
+        assert_eq!(
+            res,
+            Err(vec![(
+                order[0].clone(),
+                providers::ProviderError::Status(400)
+            )])
+        );
+    }
+
+    #[test]
+    fn token_step() {
+        let server = MockServer::start(|req| match req.method.as_str() {
+            "GET" => MockResponse::new(
+                200,
+                r#"<form><input type="hidden" name="csrf" value="t0k3n"></form>"#,
+            )
+            .header("Set-Cookie", "session=abc; Path=/; HttpOnly"),
+            _ if req.body.ends_with("&csrf=t0k3n")
+                && req.header("Cookie") == Some("session=abc") =>
+            {
+                MockResponse::json("hash", "abc")
+            }
+            _ => MockResponse::new(403, "Invalid token"),
+        })
+        .unwrap();
+        let builder = server.attach(client::UrlShortener::builder(), Provider::TinyPh);
+
+        let us = builder
+            .clone()
+            .token_step(
+                Provider::TinyPh,
+                client::TokenStep::form_field("http://tiny.ph/form", "csrf"),
+            )
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &Provider::TinyPh);
+        assert_eq!(res, Ok("http://tiny.ph/abc".to_owned()));
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/form");
+        assert_eq!(requests[1].body, "url=http%3A%2F%2Fexample.com&csrf=t0k3n");
+
+        let res = builder
+            .clone()
+            .build()
+            .unwrap()
+            .generate("http://example.com", &Provider::TinyPh);
+        assert_eq!(res, Err(providers::ProviderError::Status(403)));
+
+        let us = builder
+            .token_step(
+                Provider::TinyPh,
+                client::TokenStep::form_field("http://tiny.ph/form", "nonce"),
+            )
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &Provider::TinyPh);
+        assert_eq!(res, Err(providers::ProviderError::Deserialize));
+    }
+
+    #[test]
+    fn shorten_where() {
+        let long = MockServer::with_response(short("http://kutt.it/0123456789abcdef")).unwrap();
+        let failing = MockServer::with_response(MockResponse::new(500, "")).unwrap();
+        let accepted = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let order = [
+            mock_provider(long.address()),
+            mock_provider(failing.address()),
+            mock_provider(accepted.address()),
+            mock_provider(&dead_address()),
+        ];
+        let pulled = AtomicUsize::new(0);
+        let providers = order.iter().cloned().inspect(|_| {
+            pulled.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let res = client::shorten_where(
+            "http://example.com",
+            &reqwest::blocking::Client::new(),
+            providers,
+            |short_url| short_url.len() < 20,
+        );
+        assert_eq!(
+            res,
+            Some((order[2].clone(), "http://kutt.it/abc".to_owned()))
+        );
+        assert_eq!(pulled.load(Ordering::SeqCst), 3);
+        assert_eq!(long.hits() + failing.hits(), 2);
+
+        let res = client::shorten_where(
+            "http://example.com",
+            &reqwest::blocking::Client::new(),
+            order[..2].iter().cloned(),
+            |_| true,
+        );
+        assert_eq!(
+            res,
+            Some((
+                order[0].clone(),
+                "http://kutt.it/0123456789abcdef".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn generate_collecting() {
+        let a = MockServer::with_response(MockResponse::new(500, "")).unwrap();
+        let b = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let order = [mock_provider(a.address()), mock_provider(b.address())];
+        let us = client::UrlShortener::new().unwrap();
+        let no_retries = client::RetryPolicy {
+            retries: 0,
+            ..client::RetryPolicy::default()
+        };
+
+        let res = us.generate_collecting("http://example.com", &order, &no_retries);
+        assert_eq!(
+            res,
+            Ok((
+                order[1].clone(),
+                "http://kutt.it/abc".to_owned(),
+                vec![(order[0].clone(), providers::ProviderError::Status(500))]
+            ))
+        );
+
+        let res = us.generate_collecting("http://example.com", &order[1..], &no_retries);
+        assert_eq!(res.unwrap().2, Vec::new());
+        let res = us.generate_collecting("http://example.com", &order[..1], &no_retries);
+        assert_eq!(
+            res,
+            Err(vec![(
+                order[0].clone(),
+                providers::ProviderError::Status(500)
+            )])
+        );
+    }
+
+    #[test]
+    fn generate_with_backoff() {
+        let server = MockServer::with_response(MockResponse::new(503, "")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::new().unwrap();
+        let attempts = Mutex::new(Vec::new());
+        let backoff = |attempt: u32| {
+            attempts.lock().unwrap().push(attempt);
+            Duration::from_millis(1)
+        };
+
+        let res = us.generate_with_backoff("http://example.com", &[provider], 3, &backoff);
+
+        assert!(res.is_err());
+        assert_eq!(server.hits(), 4);
+        assert_eq!(*attempts.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn capture_headers() {
+        let server = MockServer::with_response(
+            short("http://kutt.it/abc")
+                .header("X-RateLimit-Remaining", "41")
+                .header("X-RateLimit-Reset", "1700000000")
+                .header("X-Other", "ignored"),
+        )
+        .unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .capture_headers(&["X-RateLimit-Remaining", "X-RateLimit-Reset"])
+            .build()
+            .unwrap();
+
+        let (short_url, meta) = us
+            .generate_with_meta("http://example.com", &provider)
+            .unwrap();
+
+        assert_eq!(short_url, "http://kutt.it/abc");
+        assert_eq!(meta.status, 200);
+        assert_eq!(meta.header("x-ratelimit-remaining"), Some("41"));
+        assert_eq!(meta.header("X-RateLimit-Reset"), Some("1700000000"));
+        assert_eq!(meta.header("X-Other"), None);
+
+        let us = client::UrlShortener::new().unwrap();
+        let (_, meta) = us
+            .generate_with_meta("http://example.com", &provider)
+            .unwrap();
+        assert!(meta.headers.is_empty());
+    }
+
+    #[test]
+    fn created_at() {
+        let server = MockServer::with_response(
+            MockResponse::new(
+                200,
+                r#"{"shortUrl": "http://kutt.it/abc", "createdAt": "2021-03-04T05:06:07Z"}"#,
+            )
+            .header("Content-Type", "application/json"),
+        )
+        .unwrap();
+        let us = client::UrlShortener::new().unwrap();
+
+        let short_url = us
+            .generate_detailed("http://example.com", &mock_provider(server.address()))
+            .unwrap();
+        assert_eq!(
+            short_url.created_at,
+            Some(std::time::UNIX_EPOCH + Duration::from_secs(1_614_834_367))
+        );
+
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let short_url = us
+            .generate_detailed("http://example.com", &mock_provider(server.address()))
+            .unwrap();
+        assert_eq!(short_url.created_at, None);
+    }
+
+    #[test]
+    fn response_format() {
+        use crate::providers::ResponseFormat;
+
+        let format = |res: MockResponse, provider: Provider, location: bool| {
+            let server = MockServer::with_response(res).unwrap();
+            let mut builder = server.attach(client::UrlShortener::builder(), provider.clone());
+            if location {
+                builder = builder.short_url_in_location(provider.clone());
+            }
+            let us = builder.build().unwrap();
+            let (_, meta) = us
+                .generate_with_meta("http://example.com", &provider)
+                .unwrap();
+            meta.format
+        };
+
+        assert_eq!(
+            format(
+                MockResponse::json("url", "https://bam.bz/abc"),
+                Provider::BamBz,
+                false
+            ),
+            Some(ResponseFormat::Json)
+        );
+        assert_eq!(
+            format(
+                MockResponse::xml("shorturl", "http://tny.im/abc"),
+                Provider::TnyIm,
+                false
+            ),
+            Some(ResponseFormat::Xml)
+        );
+        assert_eq!(
+            format(
+                MockResponse::plain("https://is.gd/abc"),
+                Provider::IsGd,
+                false
+            ),
+            Some(ResponseFormat::PlainText)
+        );
+        assert_eq!(
+            format(
+                MockResponse::redirect("https://tiny.ph/abc"),
+                Provider::TinyPh,
+                true
+            ),
+            Some(ResponseFormat::Location)
+        );
+
+        let server = MockServer::with_response(MockResponse::plain("abc")).unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .parser(Provider::IsGd, |res| Some(format!("https://is.gd/{}", res)))
+            .build()
+            .unwrap();
+        let (_, meta) = us
+            .generate_with_meta("http://example.com", &Provider::IsGd)
+            .unwrap();
+        assert_eq!(meta.format, Some(ResponseFormat::Custom));
+    }
+
+    #[test]
+    fn generate_with_qr() {
+        let png = b"\x89PNG\r\n\x1a\nrest of the image".to_vec();
+        let qr = MockServer::with_response(
+            MockResponse::new(200, png.clone()).header("Content-Type", "image/png"),
+        )
+        .unwrap();
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .qr_service(&format!("{}/qr", qr.address()))
+            .build()
+            .unwrap();
+
+        let res = us.generate_with_qr("http://example.com", &provider);
+
+        assert_eq!(res, Ok(("http://kutt.it/abc".to_owned(), png)));
+        assert_eq!(
+            qr.requests()[0].path,
+            "/qr?data=http%3A%2F%2Fkutt.it%2Fabc&size=300x300&format=png"
+        );
+
+        let html = MockServer::with_response(MockResponse::plain("<html></html>")).unwrap();
+        let us = client::UrlShortener::builder()
+            .qr_service(html.address())
+            .build()
+            .unwrap();
+        let res = us.generate_with_qr("http://example.com", &provider);
+        assert_eq!(res, Err(providers::ProviderError::Deserialize));
+    }
+
+    #[test]
+    fn domain_filter() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .domain_filter(target::DomainFilter::new().allow("example.com"))
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://www.example.com/", &provider);
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+
+        let res = us.generate("http://example.org/", &provider);
+        assert_eq!(res, Err(providers::ProviderError::BlockedDomain));
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn block_private_addresses() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .block_private_addresses(true)
+            .build()
+            .unwrap();
+
+        for url in &["http://127.0.0.1/", "http://10.1.2.3/", "http://localhost/"] {
+            let res = us.generate(url, &provider);
+            assert_eq!(res, Err(providers::ProviderError::PrivateAddressBlocked));
+        }
+        assert_eq!(server.hits(), 0);
+
+        let res = us.generate("http://93.184.216.34/", &provider);
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+    }
+
+    #[test]
+    fn decode_body_matches_naive() {
+        #[cfg(feature = "charset")]
+        fn naive(body: &[u8]) -> String {
+            encoding_rs::UTF_8.decode(body).0.into_owned()
+        }
+        #[cfg(not(feature = "charset"))]
+        fn naive(body: &[u8]) -> String {
+            String::from_utf8_lossy(body).into_owned()
+        }
+
+        let mut bodies: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"https://is.gd/abc".to_vec(),
+            b"\xef\xbb\xbfhttps://is.gd/abc".to_vec(),
+            b"https://is.gd/\xff\xfeabc".to_vec(),
+            "<html>Erreur: l\u{2019}URL</html>".as_bytes().to_vec(),
+        ];
+        bodies.extend(
+            providers::PROVIDERS
+                .iter()
+                .map(|provider| provider.example_response().as_bytes().to_vec()),
+        );
+
+        for body in bodies {
+            let expected = naive(&body);
+            assert_eq!(client::decode_body(body, None), expected);
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn fallback_matches_naive() {
+        let responses = vec![
+            MockResponse::new(500, "Internal Server Error"),
+            MockResponse::new(200, "<html>Please enter a valid URL</html>"),
+            MockResponse::new(200, vec![b'x'; 64]),
+            short("http://kutt.it/abc"),
+        ];
+        let servers: Vec<_> = responses
+            .into_iter()
+            .map(|res| MockServer::with_response(res).unwrap())
+            .collect();
+        let providers: Vec<_> = servers
+            .iter()
+            .map(|server| mock_provider(server.address()))
+            .collect();
+        let us = client::UrlShortener::builder()
+            .max_response_size(48)
+            .build()
+            .unwrap();
+
+        let naive: Vec<_> = providers
+            .iter()
+            .map(|provider| us.generate("http://example.com", provider))
+            .collect();
+        assert_eq!(
+            naive[..3],
+            [
+                Err(providers::ProviderError::Status(500)),
+                Err(providers::ProviderError::InvalidInput),
+                Err(providers::ProviderError::ResponseTooLarge),
+            ]
+        );
+
+        let res = us.try_generate("http://example.com", Some(&providers));
+        assert_eq!(Some(&res), naive.iter().find(|res| res.is_ok()));
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn charset() {
+        let (body, _, _) = encoding_rs::WINDOWS_1251
+            .encode("<result><title>Ссылка</title><shorturl>http://tny.im/abc</shorturl></result>");
+        assert_eq!(
+            client::decode_body(body.to_vec(), Some("text/xml; charset=\"Windows-1251\"")),
+            "<result><title>Ссылка</title><shorturl>http://tny.im/abc</shorturl></result>"
+        );
+
+        let server = MockServer::with_response(
+            MockResponse::new(200, body.into_owned())
+                .header("Content-Type", "text/xml; charset=windows-1251"),
+        )
+        .unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::TnyIm)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::TnyIm);
+        assert_eq!(res, Ok("http://tny.im/abc".to_owned()));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn disable() {
+        let disabled = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let enabled = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let kutt = mock_provider(enabled.address());
+        let us = disabled
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .disable(&[Provider::IsGd])
+            .build()
+            .unwrap();
+
+        let res = us.try_generate("http://example.com", Some(&[Provider::IsGd, kutt.clone()]));
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+
+        let res = us.generate_robust(
+            "http://example.com",
+            &[Provider::IsGd, kutt],
+            &client::RetryPolicy::default(),
+        );
+        assert!(matches!(res, Ok((Provider::Kutt { .. }, _))));
+
+        let res = us.generate("http://example.com", &Provider::IsGd);
+        assert_eq!(res, Err(providers::ProviderError::ProviderDisabled));
+        assert_eq!(disabled.hits(), 0);
+        assert!(us.is_disabled(&Provider::IsGd));
+    }
+
+    #[test]
+    fn audit_hook() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let failing = MockServer::with_response(MockResponse::new(500, "")).unwrap();
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let recorded = records.clone();
+        let us = client::UrlShortener::builder()
+            .audit_hook(move |record| recorded.lock().unwrap().push(record.clone()))
+            .build()
+            .unwrap();
+
+        let provider = mock_provider(server.address());
+        us.generate("http://example.com", &provider).unwrap();
+        let failing_provider = mock_provider(failing.address());
+        let _ = us.generate("http://example.com", &failing_provider);
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].provider, provider);
+        assert_eq!(records[0].status, Some(200));
+        assert!(records[0].duration > Duration::from_secs(0));
+        assert_eq!(records[0].outcome, Ok("http://kutt.it/abc".to_owned()));
+        assert_eq!(records[0].request.method, reqwest::Method::POST);
+        assert_eq!(
+            records[0].request.url,
+            format!("{}/api/url/submit", server.address())
+        );
+        assert_eq!(records[1].provider, failing_provider);
+        assert_eq!(records[1].status, Some(500));
+        assert_eq!(
+            records[1].outcome,
+            Err(providers::ProviderError::Status(500))
+        );
+    }
+
+    #[test]
+    fn generate_chained() {
+        let first = MockServer::with_response(short("http://first/abc")).unwrap();
+        let second = MockServer::with_response(short("http://second/def")).unwrap();
+        let chain = [
+            mock_provider(first.address()),
+            mock_provider(second.address()),
+        ];
+        let us = client::UrlShortener::new().unwrap();
+
+        let res = us.generate_chained("http://example.com", &chain);
+
+        assert_eq!(res, Ok("http://second/def".to_owned()));
+        assert!(first.requests()[0]
+            .body
+            .contains(r#""target": "http://example.com""#));
+        assert!(second.requests()[0]
+            .body
+            .contains(r#""target": "http://first/abc""#));
+
+        let broken = [mock_provider(&dead_address()), chain[1].clone()];
+        let res = us.generate_chained("http://example.com", &broken);
+        assert_eq!(res, Err(providers::ProviderError::Connection));
+        assert_eq!(second.hits(), 1);
+
+        let res = us.generate_chained("http://example.com", &[]);
+        assert_eq!(res, Ok("http://example.com".to_owned()));
+    }
+
+    #[test]
+    fn insecure_fallback() {
+        use std::io::{Read, Write};
+
+        // A provider serving plain HTTP only, answering right away so that
+        // the TLS handshake fails fast.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut buf = [0; 4096];
+                let _ = stream.read(&mut buf);
+                let body = r#"{"shortUrl": "http://kutt.it/abc"}"#;
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+            }
+        });
+        let provider = mock_provider(&format!("https://127.0.0.1:{}", port));
+
+        let us = client::UrlShortener::new().unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Err(providers::ProviderError::Tls));
+
+        let us = client::UrlShortener::builder()
+            .allow_insecure_fallback(true)
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+
+        // Never for the providers requested over HTTPS only.
+        let mut builder = client::UrlShortener::builder().allow_insecure_fallback(true);
+        for provider in providers::https_providers() {
+            builder = builder.endpoint(provider, &format!("https://127.0.0.1:{}", port));
+        }
+        let errors = builder
+            .build()
+            .unwrap()
+            .generate_secure("http://example.com")
+            .unwrap_err();
+        assert!(!errors.is_empty());
+        for (_, error) in errors {
+            assert_eq!(error, providers::ProviderError::Tls);
+        }
+    }
+
+    #[test]
+    fn generate_secure_endpoints() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let mut builder = client::UrlShortener::builder();
+        for provider in providers::https_providers() {
+            builder = server.attach(builder, provider);
+        }
+        let us = builder.build().unwrap();
+
+        let errors = us.generate_secure("http://example.com").unwrap_err();
+        assert_eq!(errors.len(), providers::https_providers().len());
+        for (_, error) in errors {
+            assert_eq!(error, providers::ProviderError::Unsupported);
+        }
+        assert_eq!(server.hits(), 0);
+
+        assert_eq!(
+            us.generate("http://example.com", &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn timeouts() {
+        use std::io::{Read, Write};
+
+        // A listener which never accepts, with its backlog filled up, so
+        // that the next connections stall.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        while let Ok(stream) =
+            std::net::TcpStream::connect_timeout(&address, Duration::from_millis(100))
+        {
+            backlog.push(stream);
+        }
+        let us = client::UrlShortener::builder()
+            .connect_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let res = us.generate(
+            "http://example.com",
+            &mock_provider(&format!("http://{}", address)),
+        );
+        assert_eq!(res, Err(providers::ProviderError::ConnectTimeout));
+
+        let slow = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(500));
+            short("http://kutt.it/abc")
+        })
+        .unwrap();
+        let provider = mock_provider(slow.address());
+        let us = client::UrlShortener::builder()
+            .provider_timeout(provider.clone(), Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Err(providers::ProviderError::ReadTimeout));
+
+        // A provider sending the headers, then stalling the body.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let provider = mock_provider(&format!("http://{}", listener.local_addr().unwrap()));
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n{");
+                thread::sleep(Duration::from_millis(500));
+            }
+        });
+        let us = client::UrlShortener::builder()
+            .provider_timeout(provider.clone(), Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Err(providers::ProviderError::ReadTimeout));
+    }
+
+    #[test]
+    fn request_interceptor() {
+        let isgd = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let kutt = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let us = isgd
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .request_interceptor(|req| {
+                req.headers_mut().insert(
+                    "X-Gateway-Key",
+                    reqwest::header::HeaderValue::from_static("secret"),
+                );
+            })
+            .build()
+            .unwrap();
+
+        us.generate("http://example.com", &Provider::IsGd).unwrap();
+        us.generate("http://example.com", &mock_provider(kutt.address()))
+            .unwrap();
+
+        for server in &[isgd, kutt] {
+            assert_eq!(server.requests()[0].header("X-Gateway-Key"), Some("secret"));
+        }
+    }
+
+    #[test]
+    fn panicking_leader() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let panicked = Arc::new(AtomicBool::new(false));
+        let once = panicked.clone();
+        let us = client::UrlShortener::builder()
+            .request_interceptor(move |_| {
+                if !once.swap(true, Ordering::SeqCst) {
+                    panic!("interceptor failure");
+                }
+            })
+            .build()
+            .unwrap();
+
+        let (leader, target) = (us.clone(), provider.clone());
+        let res = thread::spawn(move || leader.generate("http://example.com", &target)).join();
+        assert!(res.is_err());
+        assert!(us.flights.lock().unwrap().is_empty());
+
+        assert_eq!(
+            us.generate("http://example.com", &provider),
+            Ok("http://kutt.it/abc".to_owned())
+        );
+        assert!(panicked.load(Ordering::SeqCst));
+
+        // A request waiting for a leader which panics is given an error.
+        let flights = client::Flights::default();
+        let key = (provider, "http://example.com".to_owned());
+        let flight = Arc::new(client::Flight::default());
+        flights.lock().unwrap().insert(key.clone(), flight.clone());
+        let waiting = flight.clone();
+        let follower = thread::spawn(move || {
+            let mut result = waiting.result.lock().unwrap();
+            while result.is_none() {
+                result = waiting.done.wait(result).unwrap();
+            }
+            result.clone().unwrap()
+        });
+        drop(client::FlightGuard {
+            flights: &flights,
+            key,
+            flight,
+            result: None,
+        });
+        assert_eq!(
+            follower.join().unwrap(),
+            Err(providers::ProviderError::Connection)
+        );
+        assert!(flights.lock().unwrap().is_empty());
+    }
 
-        if let Ok(response) = req.execute(&self.client) {
-            response
-                .text()
-                .map_err(|_| ProviderError::Connection)
-                .and_then(|t| parse(&t, provider))
-        } else {
-            Err(ProviderError::Connection)
+    #[test]
+    fn invalid_input() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::new().unwrap();
+
+        for url in &[
+            "http://example.com\n",
+            "http://example.com\r\n",
+            "http://\texample.com",
+        ] {
+            let res = us.generate(url, &provider);
+            assert_eq!(res, Err(providers::ProviderError::InvalidInput));
         }
+        assert_eq!(server.hits(), 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::client;
-    use crate::providers;
+    #[test]
+    fn generate_batch_with_progress() {
+        let server = MockServer::start(|req| {
+            let code = if req.body.contains("/fail") { 400 } else { 200 };
+            MockResponse::new(code, r#"{"shortUrl": "http://kutt.it/abc"}"#)
+        })
+        .unwrap();
+        let provider = mock_provider(server.address());
+        let urls: Vec<String> = (0..10)
+            .map(|i| format!("http://example.com/{}", if i == 3 { "fail" } else { "ok" }))
+            .chain((0..5).map(|i| format!("http://example.com/{}", i)))
+            .collect();
+        let us = client::UrlShortener::new().unwrap();
+
+        let mut progress = Vec::new();
+        let results = us.generate_batch_with_progress(&urls, &provider, 3, |done, total| {
+            progress.push((done, total))
+        });
+
+        assert_eq!(
+            progress,
+            (1..=15).map(|done| (done, 15)).collect::<Vec<_>>()
+        );
+        assert_eq!(results.len(), 15);
+        assert_eq!(results[3], Err(providers::ProviderError::Status(400)));
+        for (i, res) in results.iter().enumerate().filter(|&(i, _)| i != 3) {
+            assert_eq!(res.as_deref(), Ok("http://kutt.it/abc"), "{}", i);
+        }
+    }
 
-    /// This test does not cover services which require authentication for obvious reasons.
     #[test]
-    fn providers() {
-        let us = client::UrlShortener::with_timeout(5).unwrap();
-        let url = "http://yandex.com";
-        let mut valid = 0;
+    fn shorten() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let client = reqwest::blocking::Client::new();
+
+        let res = client::shorten(
+            "http://example.com",
+            &client,
+            &mock_provider(server.address()),
+        );
+
+        assert_eq!(res, Ok("http://kutt.it/abc".to_owned()));
+        assert_eq!(server.requests()[0].path, "/api/url/submit");
+    }
+
+    #[test]
+    fn generate_batch_with_aliases() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::new().unwrap();
+
+        let results = us.generate_batch_with_aliases(
+            &["http://example.com/1", "http://example.com/2"],
+            &provider,
+            &mut crate::alias::Counter::new("link-", 1),
+        );
+
+        assert_eq!(results.len(), 2);
+        let requests = server.requests();
+        assert!(requests[0].body.ends_with(r#""customurl": "link-1"}"#));
+        assert!(requests[1].body.ends_with(r#""customurl": "link-2"}"#));
+    }
+
+    #[test]
+    fn generate_detailed() {
+        let reused = MockServer::with_response(MockResponse::new(
+            200,
+            r#"{"shortUrl": "http://kutt.it/abc", "reuse": true}"#,
+        ))
+        .unwrap();
+        let created = MockServer::with_response(MockResponse::new(
+            201,
+            r#"{"shortUrl": "http://kutt.it/def"}"#,
+        ))
+        .unwrap();
+        let us = client::UrlShortener::new().unwrap();
+
+        let provider = mock_provider(reused.address());
+        let short_url = us
+            .generate_detailed("http://example.com", &provider)
+            .unwrap();
+        assert_eq!(
+            short_url,
+            client::ShortUrl {
+                url: "http://kutt.it/abc".to_owned(),
+                provider,
+                // The mock provider is served from another host.
+                code: None,
+                created: Some(false),
+                created_at: None,
+                original: "http://example.com".to_owned(),
+                url_hash: None,
+            }
+        );
+
+        let provider = mock_provider(created.address());
+        let short_url = us
+            .generate_detailed("http://example.com", &provider)
+            .unwrap();
+        assert_eq!(short_url.created, Some(true));
+        assert_eq!(short_url.to_string(), "http://kutt.it/def");
+    }
 
+    #[test]
+    fn generate_secure() {
+        let attempted = Arc::new(Mutex::new(Vec::new()));
+        let recorded = attempted.clone();
+        let mut builder = client::UrlShortener::builder().attempt_hook(move |event| {
+            if event.phase == client::AttemptPhase::Started {
+                recorded.lock().unwrap().push(event.provider.clone());
+            }
+        });
+        let dead = dead_address();
         for provider in providers::PROVIDERS {
-            if let Err(e) = us.generate(url, provider) {
-                println!("{:?} -> {:?}", provider, e);
+            builder = builder.endpoint(provider.clone(), &dead);
+        }
+        let us = builder.build().unwrap();
+
+        let res = us.generate_secure("http://example.com");
+
+        let https = providers::https_providers();
+        let failed: Vec<_> = res.unwrap_err().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(failed, https);
+        assert_eq!(*attempted.lock().unwrap(), https);
+    }
+
+    #[test]
+    fn generate_with_cancel() {
+        let second = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let order = [
+            mock_provider(&dead_address()),
+            mock_provider(second.address()),
+        ];
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        let us = client::UrlShortener::builder()
+            .attempt_hook(move |event| {
+                if event.phase == client::AttemptPhase::Finished {
+                    flag.store(true, Ordering::SeqCst);
+                }
+            })
+            .build()
+            .unwrap();
+
+        let res = us.generate_with_cancel("http://example.com", &order, &cancel);
+
+        assert_eq!(res, Err(providers::ProviderError::Cancelled));
+        assert_eq!(second.hits(), 0);
+
+        cancel.store(false, Ordering::SeqCst);
+        let res = client::UrlShortener::new().unwrap().generate_with_cancel(
+            "http://example.com",
+            &order,
+            &cancel,
+        );
+        assert_eq!(res, Ok((order[1].clone(), "http://kutt.it/abc".to_owned())));
+    }
+
+    #[test]
+    fn max_input_length() {
+        let isgd = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let other =
+            MockServer::with_response(MockResponse::json("url", "https://bam.bz/abc")).unwrap();
+        let builder = isgd.attach(client::UrlShortener::builder(), Provider::IsGd);
+        let us = other.attach(builder, Provider::BamBz).build().unwrap();
+        let long_url = format!("http://example.com/{}", "a".repeat(5000));
+
+        let res = us.generate_with_cancel(
+            &long_url,
+            &[Provider::IsGd, Provider::BamBz],
+            &std::sync::atomic::AtomicBool::new(false),
+        );
+        assert_eq!(res, Ok((Provider::BamBz, "https://bam.bz/abc".to_owned())));
+        assert_eq!(isgd.hits(), 0);
+
+        let res = us.generate(&long_url, &Provider::IsGd);
+        assert_eq!(res, Err(providers::ProviderError::InputTooLong));
+        assert_eq!(isgd.hits(), 0);
+    }
+
+    #[test]
+    fn short_url_code() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .build()
+            .unwrap();
+
+        let short_url = us
+            .generate_detailed("http://example.com", &Provider::IsGd)
+            .unwrap();
+
+        assert_eq!(short_url.url, "https://is.gd/abc");
+        assert_eq!(short_url.code.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn generate_tiered() {
+        let server = MockServer::with_response(MockResponse::plain("http://rlu.ru/abc")).unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::Rlu)
+            .build()
+            .unwrap();
+        let no_retries = client::RetryPolicy {
+            retries: 0,
+            backoff: Duration::from_secs(0),
+        };
+
+        let res = us.generate_tiered(
+            "http://example.com",
+            &[Provider::Rlu],
+            providers::ProviderTier::Slow,
+            &no_retries,
+        );
+        assert_eq!(res, Err(Vec::new()));
+        assert_eq!(server.hits(), 0);
+
+        let res = us.generate_tiered(
+            "http://example.com",
+            &[Provider::Rlu],
+            providers::ProviderTier::Discouraged,
+            &no_retries,
+        );
+        assert_eq!(res, Ok((Provider::Rlu, "http://rlu.ru/abc".to_owned())));
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn link_bundle() {
+        let bundle = client::LinkBundle::new("https://is.gd/abc".to_owned(), &Provider::IsGd);
+        assert_eq!(bundle.preview.as_deref(), Some("https://is.gd/abc-"));
+        assert_eq!(
+            bundle.stats.as_deref(),
+            Some("https://is.gd/stats.php?url=abc")
+        );
+        assert!(bundle
+            .qr
+            .unwrap()
+            .contains("data=https%3A%2F%2Fis.gd%2Fabc"));
+
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let bundle = client::shorten_full(
+            "http://example.com",
+            &reqwest::blocking::Client::new(),
+            &mock_provider(server.address()),
+        )
+        .unwrap();
+        assert_eq!(bundle.short, "http://kutt.it/abc");
+        assert_eq!(bundle.preview, None);
+        assert_eq!(bundle.stats, None);
+    }
+
+    #[test]
+    fn restore_state() {
+        let good = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let bad = MockServer::with_response(MockResponse::new(500, "")).unwrap();
+        let order = [mock_provider(bad.address()), mock_provider(good.address())];
+        let us = client::UrlShortener::new().unwrap();
+        assert_eq!(us.rank_providers(&order), order.to_vec());
+
+        let _ = us.generate("http://example.com", &order[0]);
+        let _ = us.generate("http://example.com", &order[1]);
+        let state = us.export_state();
+        assert_eq!(
+            state.providers[order[0].to_name()],
+            client::ProviderStats {
+                successes: 0,
+                failures: 1,
+            }
+        );
+
+        let restored = client::UrlShortener::builder()
+            .with_state(state)
+            .build()
+            .unwrap();
+        assert_eq!(
+            restored.rank_providers(&order),
+            vec![order[1].clone(), order[0].clone()]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_state() {
+        let mut state = client::ClientState::default();
+        state.providers.insert(
+            "is.gd".to_owned(),
+            client::ProviderStats {
+                successes: 3,
+                failures: 1,
+            },
+        );
+
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(
+            json,
+            r#"{"providers":{"is.gd":{"successes":3,"failures":1}}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<client::ClientState>(&json).unwrap(),
+            state
+        );
+    }
+
+    #[test]
+    fn generate_prefetching() {
+        let primary = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let release = released.clone();
+        let secondary = MockServer::start(move |_| {
+            while !release.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            short("http://kutt.it/def")
+        })
+        .unwrap();
+        let unused = MockServer::with_response(short("http://kutt.it/ghi")).unwrap();
+        let order = [
+            mock_provider(primary.address()),
+            mock_provider(secondary.address()),
+            mock_provider(unused.address()),
+        ];
+        let us = client::UrlShortener::new().unwrap();
+
+        // Returns while the next provider is still answering.
+        let res = us.generate_prefetching("http://example.com", &order, 1);
+        assert_eq!(res, Ok((order[0].clone(), "http://kutt.it/abc".to_owned())));
+        assert_eq!(us.prefetched("http://example.com", &order[1]), None);
+        released.store(true, Ordering::SeqCst);
+
+        let mut prefetched = None;
+        for _ in 0..100 {
+            prefetched = us.prefetched("http://example.com", &order[1]);
+            if prefetched.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(prefetched.as_deref(), Some("http://kutt.it/def"));
+
+        let res = us.generate("http://example.com", &order[1]);
+        assert_eq!(res, Ok("http://kutt.it/def".to_owned()));
+        assert_eq!(secondary.hits(), 1);
+        assert_eq!(unused.hits(), 0);
+
+        // The prefetched short URL is returned once.
+        assert_eq!(us.prefetched("http://example.com", &order[1]), None);
+        assert!(us.generate("http://example.com", &order[1]).is_ok());
+        assert_eq!(secondary.hits(), 2);
+
+        // Not after a shutdown.
+        us.prefetched.insert(
+            (order[1].clone(), "http://example.com".to_owned()),
+            "http://kutt.it/def".to_owned(),
+        );
+        us.clone().shutdown();
+        assert_eq!(
+            us.generate("http://example.com", &order[1]),
+            Err(providers::ProviderError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn prefetched_bound() {
+        let prefetched = client::Prefetched::default();
+        let key = |i: usize| (Provider::IsGd, format!("http://example.com/{}", i));
+        for i in 0..=client::MAX_PREFETCHED {
+            prefetched.insert(key(i), format!("https://is.gd/{}", i));
+        }
+
+        assert_eq!(
+            prefetched.links.lock().unwrap().len(),
+            client::MAX_PREFETCHED
+        );
+        assert_eq!(prefetched.get(&key(0)), None);
+        assert_eq!(prefetched.get(&key(1)).as_deref(), Some("https://is.gd/1"));
+
+        // The monotonic clock may start too recently to go an hour back.
+        if let Some(expired) = Instant::now().checked_sub(client::PREFETCH_TTL) {
+            prefetched.links.lock().unwrap().get_mut(&key(1)).unwrap().1 = expired;
+            assert_eq!(prefetched.get(&key(1)), None);
+            assert_eq!(prefetched.take(&key(1)), None);
+        }
+        assert_eq!(prefetched.take(&key(2)).as_deref(), Some("https://is.gd/2"));
+        assert_eq!(prefetched.get(&key(2)), None);
+    }
+
+    #[test]
+    fn no_proxy_matching() {
+        let mut no_proxy = client::NoProxy::default();
+        for entry in &["Example.com", ".corp.example", "*.internal", "10.0.0.1", ""] {
+            no_proxy.add(entry);
+        }
+
+        assert!(no_proxy.bypasses("example.com"));
+        assert!(no_proxy.bypasses("www.example.com"));
+        assert!(no_proxy.bypasses("api.corp.example"));
+        assert!(no_proxy.bypasses("svc.internal"));
+        assert!(no_proxy.bypasses("10.0.0.1"));
+        assert!(!no_proxy.bypasses("notexample.com"));
+        assert!(!no_proxy.bypasses("10.0.0.2"));
+
+        no_proxy.add("*");
+        assert!(no_proxy.bypasses("is.gd"));
+    }
+
+    #[test]
+    fn no_proxy() {
+        let proxy = MockServer::with_response(short("http://kutt.it/proxied")).unwrap();
+        let server = MockServer::with_response(short("http://kutt.it/direct")).unwrap();
+        let provider = mock_provider(server.address());
+
+        let us = client::UrlShortener::builder()
+            .proxy(proxy.address())
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Ok("http://kutt.it/proxied".to_owned()));
+        assert_eq!(proxy.hits(), 1);
+        assert_eq!(server.hits(), 0);
+
+        let us = client::UrlShortener::builder()
+            .proxy(proxy.address())
+            .no_proxy(&["127.0.0.1"])
+            .build()
+            .unwrap();
+        let res = us.generate("http://example.com", &provider);
+        assert_eq!(res, Ok("http://kutt.it/direct".to_owned()));
+        assert_eq!(proxy.hits(), 1);
+        assert_eq!(server.hits(), 1);
+
+        let invalid = client::UrlShortener::builder().proxy("http://[::1").build();
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn no_providers_available() {
+        let us = client::UrlShortener::builder()
+            .disable(&[Provider::IsGd])
+            .build()
+            .unwrap();
+        let cancel = std::sync::atomic::AtomicBool::new(false);
+        let no_retries = client::RetryPolicy {
+            retries: 0,
+            backoff: Duration::from_secs(0),
+        };
+        let url = "http://example.com";
+        let none = providers::ProviderError::NoProvidersAvailable;
+
+        for candidates in &[&[][..], &[Provider::IsGd][..]] {
+            assert_eq!(us.try_generate(url, Some(candidates)), Err(none));
+            assert_eq!(us.generate_with_cancel(url, candidates, &cancel), Err(none));
+            assert_eq!(us.generate_prefetching(url, candidates, 1), Err(none));
+            assert_eq!(
+                us.generate_robust(url, candidates, &no_retries),
+                Err(Vec::new())
+            );
+        }
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn url_hash() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .hash_urls(true)
+            .build()
+            .unwrap();
+
+        let a = us
+            .generate_detailed("HTTP://Example.com:80/a/../b", &provider)
+            .unwrap();
+        let b = us
+            .generate_detailed("http://example.com/b", &provider)
+            .unwrap();
+        assert_eq!(a.url_hash, b.url_hash);
+        assert_eq!(a.url_hash.unwrap().len(), 64);
+        assert_eq!(a.original, "HTTP://Example.com:80/a/../b");
+
+        let us = client::UrlShortener::new().unwrap();
+        let c = us
+            .generate_detailed("http://example.com/b", &provider)
+            .unwrap();
+        assert_eq!(c.url_hash, None);
+    }
+
+    #[test]
+    fn savings() {
+        let mut short_url = client::ShortUrl {
+            url: "https://is.gd/abc".to_owned(),
+            provider: Provider::IsGd,
+            code: Some("abc".to_owned()),
+            created: None,
+            created_at: None,
+            original: "http://example.com/0123456789abcde".to_owned(),
+            url_hash: None,
+        };
+        assert_eq!(short_url.short_len(), 17);
+        assert_eq!(client::savings(&short_url), 0.5);
+
+        short_url.original = format!("http://example.com/{}", "é".repeat(17 * 4 - 19));
+        assert_eq!(short_url.original_len(), 17 * 4);
+        assert_eq!(short_url.savings_ratio(), 0.75);
+        short_url.original = "http://x.y".to_owned();
+        assert!(short_url.savings_ratio() < 0.0);
+        short_url.original.clear();
+        assert_eq!(short_url.savings_ratio(), 0.0);
+    }
+
+    #[test]
+    fn generate_batch_retry_budget() {
+        let server = MockServer::with_response(MockResponse::new(503, "")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::new().unwrap();
+        let options = client::BatchOptions {
+            retry: client::RetryPolicy {
+                retries: 2,
+                backoff: Duration::from_millis(1),
+            },
+            retry_budget: Some(3),
+        };
+        let urls = [
+            "http://example.com/1",
+            "http://example.com/2",
+            "http://example.com/3",
+        ];
+
+        let results = us.generate_batch(&urls, &provider, &options);
+
+        assert_eq!(results, vec![Err(providers::ProviderError::Status(503)); 3]);
+        // Two retries for the first URL, the last one of the budget for the
+        // second URL, and none for the third one.
+        assert_eq!(server.hits(), 3 + 2 + 1);
+    }
+
+    #[test]
+    fn generate_batch_native() {
+        let server = MockServer::start(|req| {
+            let body: serde_json::Value = serde_json::from_str(&req.body).unwrap();
+            let short_urls: Vec<_> = body["urls"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|url| {
+                    let path = url.as_str().unwrap().rsplit('/').next().unwrap();
+                    format!("http://kutt.it/{}", path)
+                })
+                .collect();
+            MockResponse::new(200, serde_json::to_string(&short_urls).unwrap())
+        })
+        .unwrap();
+        let endpoint = format!("{}/api/bulk", server.address());
+        let api = client::BatchApi::new(
+            2,
+            move |urls| crate::request::Request {
+                url: endpoint.clone(),
+                body: Some(serde_json::json!({ "urls": urls }).to_string()),
+                content_type: Some(crate::request::ContentType::Json),
+                user_agent: None,
+                headers: None,
+                method: crate::request::Method::Post,
+            },
+            |res| serde_json::from_str(res).ok(),
+        );
+        let provider = Provider::TinyPh;
+        let outcomes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = outcomes.clone();
+        let us = client::UrlShortener::builder()
+            .batch_api(provider.clone(), api)
+            .attempt_hook(move |event| {
+                if let Some(outcome) = event.outcome {
+                    recorded.lock().unwrap().push(outcome.map(str::to_owned));
+                }
+            })
+            .build()
+            .unwrap();
+        let urls = [
+            "http://example.com/a",
+            "http://example.com/b",
+            "http://example.com/\n",
+            "http://example.com/c",
+            "http://example.com/d",
+            "http://example.com/e",
+        ];
+
+        let results = us.generate_batch(&urls, &provider, &client::BatchOptions::default());
+
+        assert_eq!(us.max_batch_size(&provider), Some(2));
+        assert_eq!(us.max_batch_size(&Provider::IsGd), None);
+        assert_eq!(server.hits(), 3);
+        assert_eq!(results[0], Ok("http://kutt.it/a".to_owned()));
+        assert_eq!(results[1], Ok("http://kutt.it/b".to_owned()));
+        assert_eq!(results[2], Err(providers::ProviderError::InvalidInput));
+        assert_eq!(results[3], Ok("http://kutt.it/c".to_owned()));
+        assert_eq!(results[5], Ok("http://kutt.it/e".to_owned()));
+        assert_eq!(
+            server.requests()[0].body,
+            r#"{"urls":["http://example.com/a","http://example.com/b"]}"#
+        );
+        assert_eq!(
+            outcomes.lock().unwrap()[0],
+            Ok("http://kutt.it/a http://kutt.it/b".to_owned())
+        );
+        assert_eq!(us.provider_stats(&provider).successes, 3);
+
+        us.clone().shutdown();
+        let results = us.generate_batch(&urls[..2], &provider, &client::BatchOptions::default());
+        assert_eq!(results, vec![Err(providers::ProviderError::Cancelled); 2]);
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[test]
+    fn shutdown() {
+        let server = MockServer::start(|_| {
+            thread::sleep(Duration::from_millis(100));
+            short("http://kutt.it/abc")
+        })
+        .unwrap();
+        let providers: Vec<_> = (0..4)
+            .map(|i| Provider::Kutt {
+                api_key: format!("key{}", i),
+                host: Some(server.address().to_owned()),
+            })
+            .collect();
+        let us = client::UrlShortener::new().unwrap();
+        let clone = us.clone();
+
+        let res = us.generate_prefetching("http://example.com", &providers, 3);
+        assert_eq!(
+            res,
+            Ok((providers[0].clone(), "http://kutt.it/abc".to_owned()))
+        );
+        let state = us.shutdown();
+
+        let hits = server.hits();
+        assert!(hits < 4, "{}", hits);
+        let successes: u64 = state.providers.values().map(|stats| stats.successes).sum();
+        assert_eq!(successes, hits as u64);
+        assert_eq!(
+            clone.generate("http://example.com/other", &providers[0]),
+            Err(providers::ProviderError::Cancelled)
+        );
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(server.hits(), hits);
+    }
+
+    #[test]
+    fn query_stripping_deprioritized() {
+        let stripping = MockServer::with_response(short("http://kutt.it/strip")).unwrap();
+        let preserving = MockServer::with_response(short("http://kutt.it/keep")).unwrap();
+        let providers = [
+            mock_provider(stripping.address()),
+            mock_provider(preserving.address()),
+        ];
+        let us = client::UrlShortener::builder()
+            .strips_query(providers[0].clone())
+            .build()
+            .unwrap();
+        let retry = client::RetryPolicy::default();
+
+        assert!(!us.preserves_query(&providers[0]));
+        assert!(us.preserves_query(&providers[1]));
+        let res = us.generate_robust("http://example.com/?id=1", &providers, &retry);
+        assert_eq!(
+            res,
+            Ok((providers[1].clone(), "http://kutt.it/keep".to_owned()))
+        );
+        assert_eq!(stripping.hits(), 0);
+
+        let res = us.generate_robust("http://example.com/", &providers, &retry);
+        assert_eq!(
+            res,
+            Ok((providers[0].clone(), "http://kutt.it/strip".to_owned()))
+        );
+    }
+
+    #[test]
+    fn generate_checking_query() {
+        let stripping = Arc::new(AtomicBool::new(false));
+        let strips = stripping.clone();
+        let server = MockServer::start(move |req| {
+            if !req.path.contains("action=expand") {
+                return MockResponse::plain(
+                    "<result><shorturl>http://tny.im/abc</shorturl></result>",
+                );
+            }
+            let long_url = if strips.load(Ordering::SeqCst) {
+                "http://example.com/"
             } else {
-                valid += 1;
-                println!("{:?} -> OK", provider);
+                "http://example.com/?id=1&b=%20"
+            };
+            MockResponse::json("longurl", long_url)
+        })
+        .unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::TnyIm)
+            .build()
+            .unwrap();
+        let url = "http://example.com/?id=1&b=%20";
+
+        assert_eq!(
+            us.generate_checking_query(url, &Provider::TnyIm),
+            Ok("http://tny.im/abc".to_owned())
+        );
+        assert_eq!(server.hits(), 2);
+        stripping.store(true, Ordering::SeqCst);
+        assert_eq!(
+            us.generate_checking_query(url, &Provider::TnyIm),
+            Err(providers::ProviderError::VerificationFailed)
+        );
+        assert_eq!(server.hits(), 4);
+
+        // Without a query string, there is nothing to check.
+        assert!(us
+            .generate_checking_query("http://example.com/", &Provider::TnyIm)
+            .is_ok());
+        assert_eq!(server.hits(), 5);
+
+        // Nor for a provider which cannot expand its short URLs.
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        assert_eq!(
+            us.generate_checking_query(url, &provider),
+            Ok("http://kutt.it/abc".to_owned())
+        );
+        assert_eq!(server.hits(), 1);
+    }
+
+    #[test]
+    fn parse_response() {
+        use std::io::Cursor;
+
+        for provider in providers::PROVIDERS {
+            let body = Cursor::new(provider.example_response());
+            assert_eq!(
+                client::parse_response(body, provider),
+                providers::parse(provider.example_response(), provider),
+                "{:?}",
+                provider
+            );
+        }
+
+        let body = Cursor::new(b"\xef\xbb\xbfhttps://is.gd/abc".to_vec());
+        assert_eq!(
+            client::parse_response(body, &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+        let body = Cursor::new(vec![b'x'; 2 * 1024 * 1024]);
+        assert_eq!(
+            client::parse_response(body, &Provider::IsGd),
+            Err(providers::ProviderError::ResponseTooLarge)
+        );
+        let body = Cursor::new("<html>Please enter a valid URL</html>");
+        assert_eq!(
+            client::parse_response(body, &Provider::IsGd),
+            Err(providers::ProviderError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn yourls_expand_and_stats() {
+        let server = MockServer::start(|req| {
+            if req.path.contains("action=expand") && req.path.contains("tny.im%2Fabc") {
+                MockResponse::json("longurl", "https://example.com/?a=b")
+            } else if req.path.contains("action=url-stats") && req.path.contains("tny.im%2Fabc") {
+                MockResponse::new(
+                    200,
+                    r#"{"statusCode": 200, "message": "success", "link": {"shorturl": "http://tny.im/abc", "url": "https://example.com/?a=b", "title": "Example", "clicks": "42"}}"#,
+                )
+            } else {
+                MockResponse::new(
+                    404,
+                    r#"{"statusCode": 404, "message": "Error: short URL not found"}"#,
+                )
             }
+        })
+        .unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::TnyIm)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            us.expand("http://tny.im/abc", &Provider::TnyIm),
+            Ok("https://example.com/?a=b".to_owned())
+        );
+        assert_eq!(
+            us.url_stats("http://tny.im/abc", &Provider::TnyIm),
+            Ok(providers::UrlStats {
+                long_url: "https://example.com/?a=b".to_owned(),
+                clicks: 42,
+            })
+        );
+        assert_eq!(
+            us.expand("http://tny.im/zzz", &Provider::TnyIm),
+            Err(providers::ProviderError::Status(404))
+        );
+        assert!(server.requests()[0]
+            .path
+            .starts_with("/yourls-api.php?action=expand&"));
+        assert_eq!(
+            us.expand("https://is.gd/abc", &Provider::IsGd),
+            Err(providers::ProviderError::Unsupported)
+        );
+        assert_eq!(server.hits(), 3);
+
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::TnyIm)
+            .disable(&[Provider::TnyIm])
+            .build()
+            .unwrap();
+        assert_eq!(
+            us.expand("http://tny.im/abc", &Provider::TnyIm),
+            Err(providers::ProviderError::ProviderDisabled)
+        );
+        assert_eq!(
+            us.url_stats("http://tny.im/abc", &Provider::TnyIm),
+            Err(providers::ProviderError::ProviderDisabled)
+        );
+        assert_eq!(server.hits(), 3);
+    }
+
+    #[test]
+    fn is_reachable() {
+        let target = MockServer::with_response(MockResponse::new(200, "")).unwrap();
+        let live = MockServer::with_response(MockResponse::redirect(target.address())).unwrap();
+        let dead = MockServer::with_response(MockResponse::new(404, "")).unwrap();
+        let no_head = MockServer::start(|req| match req.method.as_str() {
+            "HEAD" => MockResponse::new(405, ""),
+            _ => MockResponse::new(410, ""),
+        })
+        .unwrap();
+        let following = reqwest::blocking::Client::new();
+        let not_following = reqwest::blocking::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+
+        for http in &[&following, &not_following] {
+            assert_eq!(client::is_reachable(live.address(), http), Ok(true));
+            assert_eq!(client::is_reachable(dead.address(), http), Ok(false));
         }
+        assert_eq!(target.hits(), 1);
+        assert_eq!(
+            client::is_reachable(no_head.address(), &following),
+            Ok(false)
+        );
+        assert_eq!(no_head.hits(), 2);
+        assert_eq!(
+            client::is_reachable("not a url", &following),
+            Err(providers::ProviderError::InvalidInput)
+        );
+    }
 
-        assert!(valid > 0, "There are no valid providers to use.");
+    #[test]
+    fn link_monitor() {
+        let gone = Arc::new(AtomicBool::new(false));
+        let is_gone = gone.clone();
+        let server = MockServer::start(move |_| {
+            if is_gone.load(Ordering::SeqCst) {
+                MockResponse::new(404, "")
+            } else {
+                MockResponse::redirect("http://example.com")
+            }
+        })
+        .unwrap();
+        let link = client::ShortUrl {
+            url: format!("{}/abc", server.address()),
+            provider: Provider::IsGd,
+            code: Some("abc".to_owned()),
+            created: None,
+            created_at: None,
+            original: "http://example.com".to_owned(),
+            url_hash: None,
+        };
+
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let advance = |by: u64| *now.lock().unwrap() += Duration::from_secs(by);
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let (clock, hook) = (now.clone(), reported.clone());
+        let us = client::UrlShortener::new().unwrap();
+        let mut monitor =
+            client::LinkMonitor::new(&us, vec![link.clone()], Duration::from_secs(60))
+                .clock(move || *clock.lock().unwrap())
+                .on_broken(move |link| hook.lock().unwrap().push(link.url.clone()));
+
+        assert!(monitor.poll().is_empty());
+        assert_eq!(server.hits(), 1);
+
+        gone.store(true, Ordering::SeqCst);
+        advance(30);
+        assert!(monitor.poll().is_empty());
+        assert_eq!(server.hits(), 1);
+
+        advance(30);
+        assert_eq!(monitor.poll(), vec![link.clone()]);
+        assert_eq!(*reported.lock().unwrap(), vec![link.url.clone()]);
+        assert_eq!(monitor.broken(), vec![&link]);
+
+        // A link still broken is not reported again.
+        advance(60);
+        assert!(monitor.poll().is_empty());
+        assert_eq!(server.hits(), 3);
+        assert_eq!(reported.lock().unwrap().len(), 1);
+
+        gone.store(false, Ordering::SeqCst);
+        advance(60);
+        let monitor = monitor.start().stop();
+        assert_eq!(server.hits(), 4);
+        assert!(monitor.broken().is_empty());
+
+        us.shutdown();
+        let mut monitor = monitor;
+        advance(60);
+        assert!(monitor.poll().is_empty());
+        assert_eq!(server.hits(), 4);
+    }
+
+    #[test]
+    fn shorten_batch_parallel() {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (current, highest) = (in_flight.clone(), peak.clone());
+        let server = MockServer::start(move |req| {
+            let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+            highest.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            current.fetch_sub(1, Ordering::SeqCst);
+
+            let target = req.body.rsplit("\"target\": \"").next().unwrap();
+            let page = target.trim_end_matches("\"}").rsplit('/').next().unwrap();
+            short(&format!("http://kutt.it/{}", page))
+        })
+        .unwrap();
+        let urls: Vec<String> = (0..20)
+            .map(|i| format!("http://example.com/{}", i))
+            .collect();
+
+        let results = client::shorten_batch_parallel(
+            &urls,
+            &reqwest::blocking::Client::new(),
+            &mock_provider(server.address()),
+            4,
+        );
+
+        let expected: Vec<_> = (0..20)
+            .map(|i| Ok(format!("http://kutt.it/{}", i)))
+            .collect();
+        assert_eq!(results, expected);
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(client::shorten_batch_parallel(
+            &[] as &[&str],
+            &reqwest::blocking::Client::new(),
+            &Provider::IsGd,
+            4
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn parser_override() {
+        let server = MockServer::with_response(MockResponse::new(
+            200,
+            "<a id=\"link\" href=\"https://is.gd/abc\">",
+        ))
+        .unwrap();
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .build()
+            .unwrap();
+        assert_eq!(
+            us.generate("http://example.com", &Provider::IsGd),
+            Err(providers::ProviderError::Deserialize)
+        );
+
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .parser(Provider::IsGd, |res| {
+                let link = res.split("href=\"").nth(1)?;
+                link.split('"').next().map(str::to_owned)
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            us.generate("http://example.com", &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+
+        let us = server
+            .attach(client::UrlShortener::builder(), Provider::IsGd)
+            .parser(Provider::IsGd, |_| None)
+            .build()
+            .unwrap();
+        assert_eq!(
+            us.generate("http://example.com", &Provider::IsGd),
+            Err(providers::ProviderError::Deserialize)
+        );
+    }
+
+    #[test]
+    fn generate_race_top() {
+        let released = Arc::new(AtomicBool::new(false));
+        let release = released.clone();
+        let slow = MockServer::start(move |_| {
+            while !release.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            MockResponse::plain("https://is.gd/abc")
+        })
+        .unwrap();
+        let fast = MockServer::with_response(MockResponse::plain("https://v.gd/abc")).unwrap();
+        let third =
+            MockServer::with_response(MockResponse::json("url", "https://bam.bz/abc")).unwrap();
+        assert_eq!(
+            &providers::PROVIDERS[..3],
+            &[Provider::IsGd, Provider::VGd, Provider::BamBz]
+        );
+        let builder = slow.attach(client::UrlShortener::builder(), Provider::IsGd);
+        let builder = fast.attach(builder, Provider::VGd);
+        let us = third.attach(builder, Provider::BamBz).build().unwrap();
+
+        // The slow provider answers only once the race is over.
+        let res = us.generate_race_top("http://example.com", 2);
+        released.store(true, Ordering::SeqCst);
+
+        assert_eq!(res, Ok((Provider::VGd, "https://v.gd/abc".to_owned())));
+        assert_eq!(fast.hits(), 1);
+        assert_eq!(third.hits(), 0);
+        assert_eq!(
+            us.generate_race("http://example.com", &[]),
+            Err(providers::ProviderError::NoProvidersAvailable)
+        );
+    }
+
+    #[test]
+    fn histogram_buckets() {
+        for value in (0..100_000).chain(vec![u64::MAX / 3, u64::MAX]) {
+            let bucket = client::Histogram::bucket(value);
+            let middle = client::Histogram::value(bucket) as f64;
+            assert!((middle - value as f64).abs() <= value as f64 / 16.0 + 0.5);
+        }
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let mut histogram = client::Histogram::default();
+        for ms in 1..=1000 {
+            histogram.record(Duration::from_millis(ms));
+        }
+
+        let stats = histogram.stats();
+        assert_eq!(stats.count, 1000);
+        for &(measured, expected) in &[(stats.p50, 500.0), (stats.p95, 950.0), (stats.p99, 990.0)] {
+            let measured = measured.as_secs_f64() * 1000.0;
+            assert!(
+                (measured - expected).abs() <= expected * 0.07,
+                "{} ms",
+                measured
+            );
+        }
+
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::new().unwrap();
+        assert!(us.latency_stats().is_empty());
+        us.generate("http://example.com", &provider).unwrap();
+        assert_eq!(us.latency_stats()[&provider].count, 1);
+    }
+
+    #[test]
+    fn shorten_or_original() {
+        let failing = MockServer::with_response(MockResponse::new(500, "")).unwrap();
+        let working = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let http = reqwest::blocking::Client::new();
+        let url = "http://example.com";
+
+        let order = [
+            mock_provider(failing.address()),
+            mock_provider(&dead_address()),
+        ];
+        assert_eq!(
+            client::shorten_or_original(url, &http, &order),
+            (None, url.to_owned())
+        );
+        assert_eq!(
+            client::shorten_or_original(url, &http, &[]),
+            (None, url.to_owned())
+        );
+
+        let order = [
+            mock_provider(failing.address()),
+            mock_provider(working.address()),
+        ];
+        assert_eq!(
+            client::shorten_or_original(url, &http, &order),
+            (Some(order[1].clone()), "http://kutt.it/abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn shorten_secure() {
+        // Every request goes through the proxy, which refuses them all.
+        let proxy = MockServer::with_response(MockResponse::new(502, "")).unwrap();
+        let http = reqwest::blocking::Client::builder()
+            .proxy(reqwest::Proxy::all(proxy.address()).unwrap())
+            .build()
+            .unwrap();
+
+        assert!(client::shorten_secure("http://example.com", &http).is_err());
+
+        let requests = proxy.requests();
+        let https = providers::https_providers();
+        assert!(!https.is_empty());
+        assert_eq!(requests.len(), https.len());
+        for (req, provider) in requests.iter().zip(&https) {
+            assert_eq!(req.method, "CONNECT");
+            let host = url::Url::parse(&providers::request("", provider).url).unwrap();
+            assert_eq!(
+                req.path,
+                format!("{}:443", host.host_str().unwrap()),
+                "{:?}",
+                provider
+            );
+        }
+    }
+
+    #[test]
+    fn shorten_into() {
+        let server = MockServer::start(|req| {
+            let target = req.body.rsplit("\"target\": \"").next().unwrap();
+            let page = target.trim_end_matches("\"}").rsplit('/').next().unwrap();
+            short(&format!("http://kutt.it/{}", page))
+        })
+        .unwrap();
+        let provider = mock_provider(server.address());
+        let http = reqwest::blocking::Client::new();
+        let mut buf = String::with_capacity(64);
+
+        client::shorten_into("http://example.com/a", &mut buf, &http, &provider).unwrap();
+        buf.push(' ');
+        client::shorten_into("http://example.com/b", &mut buf, &http, &provider).unwrap();
+        assert_eq!(buf, "http://kutt.it/a http://kutt.it/b");
+
+        // The body is read into the buffer, which then keeps its capacity.
+        buf.clear();
+        client::shorten_into("http://example.com/c", &mut buf, &http, &provider).unwrap();
+        assert_eq!(buf, "http://kutt.it/c");
+        let capacity = buf.capacity();
+        buf.clear();
+        client::shorten_into("http://example.com/c", &mut buf, &http, &provider).unwrap();
+        assert_eq!(buf.capacity(), capacity);
+
+        let dead = mock_provider(&dead_address());
+        assert!(client::shorten_into("http://example.com/d", &mut buf, &http, &dead).is_err());
+        assert_eq!(buf, "http://kutt.it/c");
+
+        let server = MockServer::with_response(MockResponse::plain("Error: nope")).unwrap();
+        let provider = mock_provider(server.address());
+        assert!(client::shorten_into("http://example.com/e", &mut buf, &http, &provider).is_err());
+        assert_eq!(buf, "http://kutt.it/c");
+    }
+
+    #[test]
+    fn resolver() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let port = server.address().rsplit(':').next().unwrap();
+        let resolved = Arc::new(Mutex::new(Vec::new()));
+        let seen = resolved.clone();
+        let us = client::UrlShortener::builder()
+            .endpoint(Provider::IsGd, &format!("http://is-gd.test:{}", port))
+            .resolver(move |host| {
+                seen.lock().unwrap().push(host.to_owned());
+                match host {
+                    "is-gd.test" => vec!["127.0.0.1".parse().unwrap()],
+                    _ => Vec::new(),
+                }
+            })
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::IsGd);
+
+        assert_eq!(res, Ok("https://is.gd/abc".to_owned()));
+        assert_eq!(server.hits(), 1);
+        assert_eq!(
+            server.requests()[0].header("Host"),
+            Some(&*format!("is-gd.test:{}", port))
+        );
+        assert_eq!(*resolved.lock().unwrap(), vec!["is-gd.test".to_owned()]);
+    }
+
+    #[test]
+    fn resolver_per_request() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let port = server.address().rsplit(':').next().unwrap();
+        let provider = mock_provider(&format!("http://kutt.test:{}", port));
+        // Nothing listens on 127.0.0.2, unlike on 127.0.0.1.
+        let answer = Arc::new(Mutex::new("127.0.0.2"));
+        let current = answer.clone();
+        let us = client::UrlShortener::builder()
+            .resolver(move |host| match host {
+                "kutt.test" => vec![current.lock().unwrap().parse().unwrap()],
+                _ => Vec::new(),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            us.generate("http://example.com/a", &provider),
+            Err(providers::ProviderError::Connection)
+        );
+        assert_eq!(server.hits(), 0);
+
+        *answer.lock().unwrap() = "127.0.0.1";
+        assert_eq!(
+            us.generate("http://example.com/b", &provider),
+            Ok("http://kutt.it/abc".to_owned())
+        );
+        assert_eq!(
+            server.requests()[0].header("Host"),
+            Some(&*format!("kutt.test:{}", port))
+        );
+        assert_eq!(
+            us.resolving.as_ref().unwrap().clients.lock().unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn generate_weighted() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .disable(&[Provider::IsGd])
+            .build()
+            .unwrap();
+
+        let weights = [(Provider::IsGd, 100), (provider.clone(), 1)];
+        for _ in 0..5 {
+            let res = us.generate_weighted("http://example.com", &weights);
+            assert_eq!(res, Ok((provider.clone(), "http://kutt.it/abc".to_owned())));
+        }
+        assert_eq!(
+            us.generate_weighted("http://example.com", &weights[..1]),
+            Err(providers::ProviderError::NoProvidersAvailable)
+        );
+    }
+
+    #[test]
+    fn seeded_selection() {
+        let servers: Vec<_> = (0..3)
+            .map(|_| MockServer::with_response(short("http://kutt.it/abc")).unwrap())
+            .collect();
+        let weights: Vec<_> = servers
+            .iter()
+            .map(|server| (mock_provider(server.address()), 1))
+            .collect();
+        let picks = |us: &client::UrlShortener| -> Vec<Provider> {
+            (0..20)
+                .map(|_| {
+                    us.generate_weighted("http://example.com", &weights)
+                        .unwrap()
+                        .0
+                })
+                .collect()
+        };
+        let seeded = |seed| client::UrlShortener::builder().seed(seed).build().unwrap();
+
+        let sequence = picks(&seeded(7));
+        assert_eq!(sequence, picks(&seeded(7)));
+        assert_ne!(sequence, picks(&seeded(8)));
+        assert!(weights
+            .iter()
+            .all(|(provider, _)| sequence.contains(provider)));
+
+        let (base, max) = (Duration::from_millis(10), Duration::from_secs(1));
+        assert_eq!(seeded(7).jitter(base, max), seeded(7).jitter(base, max));
+    }
+
+    #[test]
+    fn rank_by_latency() {
+        let servers: Vec<_> = (0..3)
+            .map(|i| MockServer::with_response(short(&format!("http://kutt.it/{}", i))).unwrap())
+            .collect();
+        let providers: Vec<_> = servers
+            .iter()
+            .map(|server| mock_provider(server.address()))
+            .collect();
+        let failed = mock_provider(&dead_address());
+        let unprobed = mock_provider("127.0.0.1:1");
+        let mut all = providers.clone();
+        all.insert(0, failed.clone());
+        all.insert(0, unprobed.clone());
+        let us = client::UrlShortener::new().unwrap();
+
+        assert_eq!(us.rank_by_latency(&all), all);
+        assert!(us.probe_latencies(&all[1..2])[0].1.is_err());
+        {
+            let mut probed = us.probed.lock().unwrap();
+            for (provider, millis) in providers.iter().zip(&[300, 5, 100]) {
+                probed.insert(provider.clone(), Some(Duration::from_millis(*millis)));
+            }
+        }
+        assert_eq!(
+            us.rank_by_latency(&all),
+            vec![
+                providers[1].clone(),
+                providers[2].clone(),
+                providers[0].clone(),
+                unprobed.clone(),
+                failed.clone()
+            ]
+        );
+
+        // The fastest provider is attempted first, and only it is.
+        let res = us.generate_nearest("http://example.com", &all[2..]);
+        assert_eq!(
+            res,
+            Ok((providers[1].clone(), "http://kutt.it/1".to_owned()))
+        );
+        assert_eq!(
+            servers.iter().map(MockServer::hits).collect::<Vec<_>>(),
+            vec![0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn short_url_original() {
+        let server = MockServer::with_response(short("http://kutt.it/abc")).unwrap();
+        let provider = mock_provider(server.address());
+        let us = client::UrlShortener::builder()
+            .normalize_urls(true)
+            .build()
+            .unwrap();
+        let url = "HTTP://Example.com:80/a/../long?q=1#top";
+
+        let short_url = us.generate_detailed(url, &provider).unwrap();
+
+        assert_eq!(short_url.original, url);
+        assert_eq!(short_url.original_len(), url.len());
     }
 }
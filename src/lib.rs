@@ -37,12 +37,18 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
+pub mod alias;
+pub mod backoff;
 /// A urlshortener http client for performing requests.
 #[cfg(feature = "client")]
 pub mod client;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
 pub mod providers;
 /// A request builders for sending via http client.
 pub mod request;
+pub mod rng;
+pub mod target;
 
 /// A prelude module with main useful stuff.
 pub mod prelude {
@@ -0,0 +1,395 @@
+//! A local HTTP server imitating the providers, for testing the code which
+//! generates short URLs without reaching the real services.
+//!
+//! # Example
+//!
+//! ```rust
+//! use urlshortener::{client::UrlShortener, mock::{MockResponse, MockServer}, providers::Provider};
+//!
+//! let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+//! let us = server
+//!     .attach(UrlShortener::builder(), Provider::IsGd)
+//!     .build()
+//!     .unwrap();
+//!
+//! let short_url = us.generate("https://rust-lang.org", &Provider::IsGd);
+//! assert_eq!(short_url, Ok("https://is.gd/abc".to_owned()));
+//! assert_eq!(server.hits(), 1);
+//! ```
+
+use crate::client::UrlShortenerBuilder;
+use crate::providers::Provider;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request received by the mock server.
+#[derive(Debug, Clone, Default)]
+pub struct MockRequest {
+    /// The HTTP method.
+    pub method: String,
+    /// The path, including the query.
+    pub path: String,
+    /// The request headers, with lowercased names.
+    pub headers: Vec<(String, String)>,
+    /// The request body.
+    pub body: String,
+}
+
+impl MockRequest {
+    /// Returns the value of the header with the given name, if any.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn read<R: Read>(stream: R) -> io::Result<MockRequest> {
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        let mut parts = line.split_whitespace();
+        let mut req = MockRequest {
+            method: parts.next().unwrap_or_default().to_owned(),
+            path: parts.next().unwrap_or_default().to_owned(),
+            ..MockRequest::default()
+        };
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                req.headers
+                    .push((name.trim().to_lowercase(), value.trim().to_owned()));
+            }
+        }
+
+        let length = req
+            .header("content-length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+        req.body = String::from_utf8_lossy(&body).into_owned();
+
+        Ok(req)
+    }
+}
+
+/// A response sent by the mock server.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    chunked: bool,
+}
+
+impl MockResponse {
+    /// Creates a response with the given status code and body.
+    pub fn new<B: Into<Vec<u8>>>(status: u16, body: B) -> MockResponse {
+        MockResponse {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            chunked: false,
+        }
+    }
+
+    /// A successful response with the short URL as a plain text, like the
+    /// one of `is.gd`.
+    pub fn plain(short_url: &str) -> MockResponse {
+        MockResponse::new(200, short_url)
+    }
+
+    /// A successful response with the short URL as a field of a JSON
+    /// object, like the one of `bam.bz`.
+    pub fn json(field: &str, short_url: &str) -> MockResponse {
+        MockResponse::new(200, format!(r#"{{"{}": "{}"}}"#, field, short_url))
+            .header("Content-Type", "application/json")
+    }
+
+    /// A successful response with the short URL inside an XML tag, like the
+    /// one of `tny.im`.
+    pub fn xml(tag: &str, short_url: &str) -> MockResponse {
+        MockResponse::new(
+            200,
+            format!("<result><{0}>{1}</{0}></result>", tag, short_url),
+        )
+        .header("Content-Type", "text/xml")
+    }
+
+    /// A redirect to the given location.
+    pub fn redirect(location: &str) -> MockResponse {
+        MockResponse::new(302, "").header("Location", location)
+    }
+
+    /// Adds a header to the response.
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sends the body with the chunked transfer encoding, in small chunks.
+    pub fn chunked(mut self) -> MockResponse {
+        self.chunked = true;
+        self
+    }
+
+    fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        write!(w, "HTTP/1.1 {} Mock\r\nConnection: close\r\n", self.status)?;
+        for (name, value) in &self.headers {
+            write!(w, "{}: {}\r\n", name, value)?;
+        }
+
+        if self.chunked {
+            w.write_all(b"Transfer-Encoding: chunked\r\n\r\n")?;
+            for chunk in self.body.chunks(8) {
+                write!(w, "{:x}\r\n", chunk.len())?;
+                w.write_all(chunk)?;
+                w.write_all(b"\r\n")?;
+            }
+            w.write_all(b"0\r\n\r\n")
+        } else {
+            write!(w, "Content-Length: {}\r\n\r\n", self.body.len())?;
+            w.write_all(&self.body)
+        }
+    }
+}
+
+/// A local HTTP server answering the requests with a handler.
+///
+/// The server stops when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    address: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockServer {
+    /// Starts the server answering every request with the response returned
+    /// by `handler`. The handler may be called from several threads at once.
+    pub fn start<F>(handler: F) -> io::Result<MockServer>
+    where
+        F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let handler = Arc::new(handler);
+
+        let (recorded, stop) = (requests.clone(), shutdown.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let (handler, recorded) = (handler.clone(), recorded.clone());
+
+                thread::spawn(move || {
+                    let req = match stream.try_clone().and_then(MockRequest::read) {
+                        Ok(req) => req,
+                        Err(_) => return,
+                    };
+                    recorded.lock().unwrap().push(req.clone());
+                    let _ = handler(&req).write(stream);
+                });
+            }
+        });
+
+        Ok(MockServer {
+            address,
+            requests,
+            shutdown,
+        })
+    }
+
+    /// Starts the server answering every request with the same response.
+    pub fn with_response(res: MockResponse) -> io::Result<MockServer> {
+        MockServer::start(move |_| res.clone())
+    }
+
+    /// Returns the base address of the server, like `http://127.0.0.1:1234`.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Returns all the requests received so far.
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Returns the number of requests received so far.
+    pub fn hits(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// Points the provider endpoint of the `UrlShortener` being built at
+    /// this server.
+    pub fn attach(&self, builder: UrlShortenerBuilder, provider: Provider) -> UrlShortenerBuilder {
+        builder.endpoint(provider, &self.address)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wakes the listener up so that it notices the shutdown.
+        let _ = TcpStream::connect(self.address.trim_start_matches("http://"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::UrlShortener;
+
+    #[test]
+    fn plain_provider() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::IsGd)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::IsGd);
+
+        assert_eq!(res, Ok("https://is.gd/abc".to_owned()));
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(
+            requests[0].path,
+            "/create.php?format=simple&url=http%3A%2F%2Fexample.com"
+        );
+    }
+
+    #[test]
+    fn json_provider() {
+        let server =
+            MockServer::with_response(MockResponse::json("url", "https://bam.bz/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::BamBz)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::BamBz);
+
+        assert_eq!(res, Ok("https://bam.bz/abc".to_owned()));
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/api/short");
+        assert_eq!(
+            requests[0].header("Content-Type"),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(requests[0].body, "target=http%3A%2F%2Fexample.com");
+    }
+
+    #[test]
+    fn space_encoding() {
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::IsGd)
+            .build()
+            .unwrap();
+
+        assert!(us
+            .generate("http://example.com/a b", &Provider::IsGd)
+            .is_ok());
+        assert_eq!(
+            server.requests()[0].path,
+            "/create.php?format=simple&url=http%3A%2F%2Fexample.com%2Fa%20b"
+        );
+
+        let server =
+            MockServer::with_response(MockResponse::json("url", "https://bam.bz/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::BamBz)
+            .build()
+            .unwrap();
+
+        assert!(us
+            .generate("http://example.com/a b", &Provider::BamBz)
+            .is_ok());
+        assert_eq!(
+            server.requests()[0].body,
+            "target=http%3A%2F%2Fexample.com%2Fa+b"
+        );
+    }
+
+    #[test]
+    fn json_array_provider() {
+        let server = MockServer::with_response(
+            MockResponse::new(
+                200,
+                r#"[{"error": "quota"}, {"url": "https://bam.bz/abc"}, {"url": "https://bam.bz/def"}]"#,
+            )
+            .header("Content-Type", "application/json"),
+        )
+        .unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::BamBz)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::BamBz);
+
+        assert_eq!(res, Ok("https://bam.bz/abc".to_owned()));
+    }
+
+    #[test]
+    fn xml_provider() {
+        let server =
+            MockServer::with_response(MockResponse::xml("shorturl", "http://tny.im/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::TnyIm)
+            .build()
+            .unwrap();
+
+        let res = us.generate("http://example.com", &Provider::TnyIm);
+
+        assert_eq!(res, Ok("http://tny.im/abc".to_owned()));
+    }
+
+    #[test]
+    fn referer_provider() {
+        let server =
+            MockServer::with_response(MockResponse::plain(Provider::TinyUrl.example_response()))
+                .unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::TinyUrl)
+            .build()
+            .unwrap();
+
+        assert!(us
+            .generate("http://example.com", &Provider::TinyUrl)
+            .is_ok());
+        assert_eq!(
+            server.requests()[0].header("Referer"),
+            Some("https://tinyurl.com/")
+        );
+
+        let server = MockServer::with_response(MockResponse::plain("https://is.gd/abc")).unwrap();
+        let us = server
+            .attach(UrlShortener::builder(), Provider::IsGd)
+            .build()
+            .unwrap();
+
+        assert!(us.generate("http://example.com", &Provider::IsGd).is_ok());
+        assert_eq!(server.requests()[0].header("Referer"), None);
+    }
+}
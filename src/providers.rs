@@ -1,7 +1,12 @@
 //! Library service providers implementation.
 
 use crate::request as req;
-use reqwest::header::HeaderMap;
+use crate::rng::{Rng, XorShift};
+use crate::target;
+use reqwest::header::{HeaderMap, HeaderValue, REFERER};
+use std::collections::HashSet;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::form_urlencoded;
 
 /// A user agent for faking weird services.
@@ -9,12 +14,73 @@ const FAKE_USER_AGENT: &str =
     "Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:58.0) Gecko/20100101 Firefox/58.0";
 
 /// Describes the provider error.
+///
+/// New variants may be added in minor releases, so a `match` on it must
+/// have a wildcard arm.
+#[non_exhaustive]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ProviderError {
     /// Means there was a connection error. Usually when making a request.
     Connection,
     /// Means we were not able to deserialize the answer.
     Deserialize,
+    /// Means the string does not name a provider which can be constructed
+    /// without parameters.
+    UnknownProvider,
+    /// Means the generated short URL does not lead to the original URL.
+    VerificationFailed,
+    /// Means the provider response exceeds the allowed size.
+    ResponseTooLarge,
+    /// Means the provider does not support the requested feature.
+    Unsupported,
+    /// Means the provider domain could not be resolved, which usually
+    /// means the service is gone.
+    Dns,
+    /// Means the provider answered with an unsuccessful HTTP status code.
+    Status(u16),
+    /// Means the domain of the long URL is not allowed by the domain filter.
+    BlockedDomain,
+    /// Means the long URL points to a private, loopback, link-local or reserved
+    /// address.
+    PrivateAddressBlocked,
+    /// Means the provider has been disabled for the `UrlShortener`.
+    ProviderDisabled,
+    /// Means the TLS connection to the provider could not be established.
+    Tls,
+    /// Means the connection to the provider could not be established in time.
+    ConnectTimeout,
+    /// Means the provider has not answered in time after the connection was
+    /// established.
+    ReadTimeout,
+    /// Means the long URL contains control characters, like a newline.
+    InvalidInput,
+    /// Means the custom alias is not accepted by the provider, because of its
+    /// length or of its characters.
+    InvalidAlias,
+    /// Means the operation has been cancelled.
+    Cancelled,
+    /// Means the long URL is longer than the provider accepts.
+    InputTooLong,
+    /// Means no provider was left to attempt, for example because all of them
+    /// were filtered out or disabled.
+    NoProvidersAvailable,
+    /// Means the provider refuses the long URL because it is already short.
+    TargetTooShort,
+    /// Means the provider refuses the scheme of the long URL, taking only HTTP
+    /// and HTTPS ones for example.
+    UnsupportedScheme,
+}
+
+impl ProviderError {
+    /// Returns whether the error is likely to go away on its own, so that
+    /// retrying the same provider later makes sense.
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            Self::Connection | Self::ReadTimeout => true,
+            Self::Status(code) => code == 429 || code >= 500,
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for ProviderError {
@@ -28,12 +94,152 @@ impl std::fmt::Display for ProviderError {
                 f,
                 "Couldn't deserialize the shortened URL from the response."
             ),
+            Self::UnknownProvider => write!(f, "There is no such provider."),
+            Self::VerificationFailed => {
+                write!(f, "The short URL does not redirect to the original URL.")
+            }
+            Self::ResponseTooLarge => write!(f, "The provider response is too large."),
+            Self::Unsupported => write!(f, "The provider does not support this feature."),
+            Self::Dns => write!(f, "The provider domain could not be resolved."),
+            Self::Status(code) => write!(f, "The provider answered with the status {}.", code),
+            Self::BlockedDomain => write!(f, "The domain of the URL is not allowed."),
+            Self::PrivateAddressBlocked => write!(f, "The URL points to a private address."),
+            Self::ProviderDisabled => write!(f, "The provider is disabled."),
+            Self::Tls => write!(f, "The TLS connection to the provider failed."),
+            Self::ConnectTimeout => write!(f, "The connection to the provider has timed out."),
+            Self::ReadTimeout => write!(f, "The provider has not answered in time."),
+            Self::InvalidInput => write!(f, "The URL contains control characters."),
+            Self::InvalidAlias => write!(f, "The alias is not accepted by the provider."),
+            Self::Cancelled => write!(f, "The operation has been cancelled."),
+            Self::InputTooLong => write!(f, "The URL is too long for the provider."),
+            Self::NoProvidersAvailable => write!(f, "No provider is available for the URL."),
+            Self::TargetTooShort => write!(f, "The URL is already short."),
+            Self::UnsupportedScheme => {
+                write!(f, "The URL scheme is not supported by the provider.")
+            }
         }
     }
 }
 
 impl std::error::Error for ProviderError {}
 
+/// An error of [`providers_from_config`] or [`ProviderBuilder::build`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ConfigError {
+    /// Means the entry does not name a provider (see the `FromStr`
+    /// implementation of `Provider`).
+    UnknownProvider(String),
+    /// Means the line is not a list of providers.
+    InvalidSyntax(String),
+    /// Means a parameter the provider requires has not been given.
+    MissingField(&'static str),
+    /// Means a parameter is malformed, or is not one of the provider.
+    InvalidField(&'static str),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::UnknownProvider(ref entry) => write!(f, "Unknown provider: {:?}.", entry),
+            Self::InvalidSyntax(ref line) => write!(f, "Invalid provider list: {:?}.", line),
+            Self::MissingField(field) => write!(f, "Missing provider parameter: {}.", field),
+            Self::InvalidField(field) => write!(f, "Invalid provider parameter: {}.", field),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Describes what a provider short link leads to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ProviderKind {
+    /// The short link redirects directly to the original URL.
+    Direct,
+    /// The short link shows a preview page instead of redirecting.
+    PreviewPage,
+    /// The short link may show ads before redirecting.
+    AdSupported,
+}
+
+/// The format of the provider response the short URL has been read from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ResponseFormat {
+    /// A field of a JSON object.
+    Json,
+    /// An XML element.
+    Xml,
+    /// The whole body, as plain text.
+    PlainText,
+    /// An attribute scraped from an HTML page.
+    Html,
+    /// The `Location` header of a redirect.
+    Location,
+    /// A custom parser set with `UrlShortenerBuilder::parser`.
+    Custom,
+}
+
+/// The quality tier of a provider, following the groups of [`PROVIDERS`].
+/// Tiers are ordered from the best to the worst.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum ProviderTier {
+    /// A reliable provider.
+    Good,
+    /// A provider which has long response times sometimes.
+    Slow,
+    /// A provider which is discouraged from use, due to problems such as
+    /// rate limitations.
+    Discouraged,
+    /// A provider which shows previews or ads instead of direct links.
+    Preview,
+}
+
+/// A documented limit of the number of requests to a provider.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct RateLimit {
+    /// The number of requests allowed in the period.
+    pub requests: u32,
+    /// The period the requests are counted over.
+    pub per: Duration,
+}
+
+/// The statistics of a short URL, returned by the providers which keep
+/// them (see [`request_url_stats`]).
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct UrlStats {
+    /// The long URL the short URL leads to.
+    pub long_url: String,
+    /// The number of times the short URL has been followed.
+    pub clicks: u64,
+}
+
+/// Describes the custom aliases a provider accepts.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AliasRules {
+    /// The minimum length of an alias.
+    pub min_length: usize,
+    /// The maximum length of an alias.
+    pub max_length: usize,
+    /// The characters allowed besides the ASCII letters and digits.
+    pub symbols: &'static str,
+}
+
+impl AliasRules {
+    /// Checks the alias against the rules, returning
+    /// `ProviderError::InvalidAlias` if it violates them.
+    pub fn check(&self, alias: &str) -> Result<(), ProviderError> {
+        let length = alias.chars().count();
+        let allowed = alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || self.symbols.contains(c));
+
+        if allowed && (self.min_length..=self.max_length).contains(&length) {
+            Ok(())
+        } else {
+            Err(ProviderError::InvalidAlias)
+        }
+    }
+}
+
 /// A slice of all `Provider` variants which do not require authentication.
 /// This list is in order of provider quality.
 ///
@@ -94,16 +300,7 @@ macro_rules! parse_xml_tag {
 macro_rules! parse_json_tag {
     ($fname:ident, $tag:expr, $prefix:expr) => {
         fn $fname(res: &str) -> Option<String> {
-            res.to_owned()
-                .split(&format!("\"{}\"", $tag))
-                .nth(1)
-                .unwrap_or("")
-                .split(",")
-                .next()
-                .unwrap_or("")
-                .split("\"")
-                .nth(1)
-                .map(|v| format!("{}{}", $prefix, v.replace("\\", "")))
+            json_string_field(res, $tag).map(|v| format!("{}{}", $prefix, percent_decode_url(&v)))
         }
     };
 }
@@ -119,7 +316,7 @@ macro_rules! parse_noop {
 macro_rules! request {
     ($name:ident, $method:expr, $req_url:expr) => {
         fn $name(url: &str) -> req::Request {
-            let url = form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>();
+            let url = target::query_encode(url);
             req::Request {
                 url: format!($req_url, url),
                 body: None,
@@ -131,35 +328,198 @@ macro_rules! request {
         }
     };
 
-    (B, $name:ident, $method:expr, $req_url:expr, $body:expr) => {
+    ($name:ident, $method:expr, $req_url:expr, $body:expr, $content_type:expr) => {
         fn $name(url: &str) -> req::Request {
+            let url = target::form_encode(url);
             req::Request {
                 url: $req_url.to_owned(),
                 body: Some(format!($body, url)),
-                content_type: None,
+                content_type: Some($content_type),
                 user_agent: None,
                 headers: None,
                 method: $method,
             }
         }
     };
+}
 
-    ($name:ident, $method:expr, $req_url:expr, $body:expr, $content_type:expr) => {
-        fn $name(url: &str) -> req::Request {
-            req::Request {
-                url: $req_url.to_owned(),
-                body: Some(format!($body, url)),
-                content_type: Some($content_type),
-                user_agent: None,
-                headers: None,
-                method: $method,
-            }
+/// Escapes a string so it can be embedded into a JSON string literal.
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
         }
+    }
+    escaped
+}
+
+/// Returns the JSON text wrapped in a JSONP callback, like
+/// `callback({"short": "..."});`, or the text as is if it is not wrapped.
+/// The `/**/` prefix some servers put before the callback is skipped.
+fn strip_jsonp(res: &str) -> &str {
+    let trimmed = res.trim();
+    let trimmed = trimmed.strip_prefix("/**/").unwrap_or(trimmed).trim_start();
+    let open = match trimmed.find('(') {
+        Some(open) => open,
+        None => return res,
     };
+    let callback = trimmed[..open].trim_end();
+    let is_callback = callback.starts_with(|c: char| c.is_ascii_alphabetic() || "_$".contains(c))
+        && callback
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_$.".contains(c));
+    let inner = trimmed[open + 1..]
+        .trim_end()
+        .trim_end_matches(';')
+        .trim_end()
+        .strip_suffix(')');
+
+    match inner {
+        Some(inner) if is_callback => inner.trim(),
+        _ => res,
+    }
+}
+
+/// Returns the unescaped value of the first string field named `field` of the
+/// JSON text, which may be wrapped in a JSONP callback.
+fn json_string_field(res: &str, field: &str) -> Option<String> {
+    let res = strip_jsonp(res);
+    let key = format!("\"{}\"", field);
+    let rest = &res[res.find(&key)? + key.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let mut chars = rest.strip_prefix('"')?.chars();
+
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let mut code = hex4(&mut chars)?;
+                    if (0xd800..0xdc00).contains(&code) {
+                        if chars.next()? != '\\' || chars.next()? != 'u' {
+                            return None;
+                        }
+                        let low = hex4(&mut chars)?;
+                        code =
+                            0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)? & 0x3ff);
+                    }
+                    value.push(std::char::from_u32(code)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+/// Returns the raw value of the first non-string field named `field` of the
+/// JSON text, like `true` or `42`, which may be wrapped in a JSONP callback.
+fn json_raw_field<'a>(res: &'a str, field: &str) -> Option<&'a str> {
+    let res = strip_jsonp(res);
+    let key = format!("\"{}\"", field);
+    let rest = &res[res.find(&key)? + key.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?;
+    let end = rest.find([',', '}']).unwrap_or(rest.len());
+
+    Some(rest[..end].trim())
+}
+
+/// Returns the top-level elements of the JSON array, which may be wrapped in
+/// a JSONP callback, or `None` if the text is not an array.
+fn json_array_elements(res: &str) -> Option<Vec<&str>> {
+    let inner = strip_jsonp(res)
+        .trim()
+        .strip_prefix('[')?
+        .strip_suffix(']')?;
+
+    let (mut elements, mut start, mut depth) = (Vec::new(), 0, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, c) in inner.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                elements.push(inner[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    elements.push(inner[start..].trim());
+    elements.retain(|element| !element.is_empty());
+
+    Some(elements)
+}
+
+/// Reads four hexadecimal digits of a JSON `\u` escape.
+fn hex4(chars: &mut std::str::Chars<'_>) -> Option<u32> {
+    let digits: String = chars.take(4).collect();
+    if digits.len() != 4 {
+        return None;
+    }
+    u32::from_str_radix(&digits, 16).ok()
+}
+
+/// Decodes a short URL returned entirely percent-encoded, like
+/// `http%3A%2F%2Fexample.com%2Fabc`, leaving any other value as is.
+fn percent_decode_url(value: &str) -> String {
+    if !value.contains("://") {
+        if let Ok(decoded) = percent_encoding::percent_decode_str(value).decode_utf8() {
+            if decoded.contains("://") {
+                return decoded.into_owned();
+            }
+        }
+    }
+    value.to_owned()
+}
+
+/// Adds a string field to the JSON object of the request body.
+fn json_insert(req: &mut req::Request, field: &str, value: &str) {
+    if let Some(body) = req.body.as_mut() {
+        if body.ends_with('}') {
+            body.pop();
+            body.push_str(&format!(r#", "{}": "{}"}}"#, field, json_escape(value)));
+        }
+    }
+}
+
+/// Builds a `POST` request sending the url as the `field` of a JSON object.
+fn json_request(address: String, field: &str, url: &str) -> req::Request {
+    req::Request {
+        url: address,
+        body: Some(format!(r#"{{"{}": "{}"}}"#, field, json_escape(url))),
+        content_type: Some(req::ContentType::Json),
+        user_agent: None,
+        headers: None,
+        method: req::Method::Post,
+    }
 }
 
 /// Used to specify which provider to use to generate a short URL.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Provider {
     /// <http://abv8.me> provider
     ///
@@ -251,6 +611,50 @@ pub enum Provider {
 }
 
 impl Provider {
+    /// Every `Provider` variant in declaration order.
+    ///
+    /// Unlike [`PROVIDERS`], this list is not ordered by quality and also
+    /// contains the providers which require authentication. Their
+    /// credentials are left empty, so they are only useful for inspecting the
+    /// provider metadata and must be filled in before generating short URLs.
+    pub const ALL: &'static [Provider] = &[
+        Provider::Abv8,
+        Provider::BamBz,
+        Provider::BitLy {
+            token: String::new(),
+        },
+        Provider::BitUrl,
+        Provider::Bmeo,
+        Provider::FifoCc,
+        Provider::GooGl {
+            api_key: String::new(),
+        },
+        Provider::Kutt {
+            api_key: String::new(),
+            host: None,
+        },
+        Provider::HecSu,
+        Provider::HmmRs,
+        Provider::IsGd,
+        Provider::NowLinks,
+        Provider::PhxCoIn,
+        Provider::PsbeCo,
+        Provider::SCoop,
+        Provider::Rlu,
+        Provider::SirBz,
+        Provider::TinyUrl,
+        Provider::TinyPh,
+        Provider::TnyIm,
+        Provider::UrlShortenerIo,
+        Provider::VGd,
+    ];
+
+    /// Returns every `Provider` variant in declaration order, see
+    /// [`Provider::ALL`].
+    pub fn all() -> &'static [Provider] {
+        Self::ALL
+    }
+
     /// Converts the Provider variant into its domain name equivilant
     pub fn to_name(&self) -> &str {
         match *self {
@@ -281,6 +685,439 @@ impl Provider {
             Provider::VGd => "v.gd",
         }
     }
+
+    /// Returns the length of the provider domain name, which is a lower
+    /// bound of the length of its short links.
+    pub fn domain_length(&self) -> usize {
+        self.to_name().len()
+    }
+
+    /// Returns what the provider short links lead to.
+    pub fn kind(&self) -> ProviderKind {
+        match *self {
+            Provider::NowLinks => ProviderKind::PreviewPage,
+            Provider::PhxCoIn => ProviderKind::AdSupported,
+            _ => ProviderKind::Direct,
+        }
+    }
+
+    /// Returns the format of the responses the provider parser reads (see
+    /// [`parse`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use urlshortener::providers::{Provider, ResponseFormat};
+    ///
+    /// assert_eq!(Provider::IsGd.response_format(), ResponseFormat::PlainText);
+    /// assert_eq!(Provider::TnyIm.response_format(), ResponseFormat::Xml);
+    /// ```
+    pub fn response_format(&self) -> ResponseFormat {
+        match *self {
+            Provider::BamBz
+            | Provider::BitUrl
+            | Provider::Bmeo
+            | Provider::FifoCc
+            | Provider::GooGl { .. }
+            | Provider::HmmRs
+            | Provider::Kutt { .. }
+            | Provider::SirBz
+            | Provider::TinyPh => ResponseFormat::Json,
+            Provider::HecSu | Provider::PsbeCo | Provider::TnyIm => ResponseFormat::Xml,
+            Provider::TinyUrl => ResponseFormat::Html,
+            Provider::Abv8
+            | Provider::BitLy { .. }
+            | Provider::IsGd
+            | Provider::NowLinks
+            | Provider::PhxCoIn
+            | Provider::SCoop
+            | Provider::Rlu
+            | Provider::UrlShortenerIo
+            | Provider::VGd => ResponseFormat::PlainText,
+        }
+    }
+
+    /// Returns a representative sample of the response body of the provider,
+    /// in the shape its parser expects (see [`parse`]).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use urlshortener::providers::{parse, Provider};
+    ///
+    /// let res = Provider::TnyIm.example_response();
+    /// assert_eq!(parse(res, &Provider::TnyIm), Ok("http://tny.im/abc".to_owned()));
+    /// ```
+    pub fn example_response(&self) -> &'static str {
+        match *self {
+            Provider::Abv8 => "http://abv8.me/abc",
+            Provider::BamBz => r#"{"url": "https://bam.bz/abc"}"#,
+            Provider::BitLy { .. } => "http://bit.ly/abc",
+            Provider::BitUrl => r#"{"short": "https://biturl.top/abc"}"#,
+            Provider::Bmeo => r#"{"short": "http://bmeo.org/abc"}"#,
+            Provider::FifoCc => r#"{"shortner": "abc", "status": "ok"}"#,
+            Provider::GooGl { .. } => {
+                r#"{"kind": "urlshortener#url", "id": "https://goo.gl/abc", "longUrl": "https://rust-lang.org/"}"#
+            }
+            Provider::HmmRs => r#"{"shortUrl": "http://hmm.rs/abc"}"#,
+            Provider::HecSu => "<result><short>https://hec.su/abc</short></result>",
+            Provider::IsGd => "https://is.gd/abc",
+            Provider::Kutt { .. } => {
+                r#"{"id": "abc", "target": "https://rust-lang.org/", "shortUrl": "https://kutt.it/abc"}"#
+            }
+            Provider::NowLinks => "http://nowlinks.net/abc",
+            Provider::PhxCoIn => "http://phx.co.in/abc",
+            Provider::PsbeCo => "<Result><ShortUrl>http://psbe.co/abc</ShortUrl></Result>",
+            Provider::SCoop => "http://s.coop/abc",
+            Provider::SirBz => r#"{"short_link": "http://sirbz.com/abc"}"#,
+            Provider::Rlu => "http://rlu.ru/abc",
+            Provider::TinyUrl => {
+                r#"<button data-clipboard-text="https://tinyurl.com/abc">Copy</button>"#
+            }
+            Provider::TinyPh => r#"{"hash": "abc"}"#,
+            Provider::TnyIm => "<result><shorturl>http://tny.im/abc</shorturl></result>",
+            Provider::UrlShortenerIo => "http://url-shortener.io/abc",
+            Provider::VGd => "https://v.gd/abc",
+        }
+    }
+
+    /// Returns the address of the page of the provider showing where the
+    /// short URL leads to before redirecting, if the provider has one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use urlshortener::providers::Provider;
+    ///
+    /// assert_eq!(
+    ///     Provider::IsGd.preview_url("https://is.gd/abc"),
+    ///     Some("https://is.gd/abc-".to_owned())
+    /// );
+    /// assert_eq!(Provider::BamBz.preview_url("https://bam.bz/abc"), None);
+    /// ```
+    pub fn preview_url(&self, short_url: &str) -> Option<String> {
+        let short_url = short_url.trim_end_matches('/');
+        match *self {
+            Provider::IsGd | Provider::VGd => Some(format!("{}-", short_url)),
+            Provider::BitLy { .. } => Some(format!("{}+", short_url)),
+            Provider::TinyUrl => {
+                let mut parsed = url::Url::parse(short_url).ok()?;
+                let host = format!("preview.{}", parsed.host_str()?);
+                parsed.set_host(Some(&host)).ok()?;
+                Some(parsed.into())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the address of the page of the provider showing the
+    /// statistics of the short URL, if the provider has one.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use urlshortener::providers::Provider;
+    ///
+    /// assert_eq!(
+    ///     Provider::IsGd.stats_url("https://is.gd/abc"),
+    ///     Some("https://is.gd/stats.php?url=abc".to_owned())
+    /// );
+    /// assert_eq!(Provider::BamBz.stats_url("https://bam.bz/abc"), None);
+    /// ```
+    pub fn stats_url(&self, short_url: &str) -> Option<String> {
+        match *self {
+            Provider::IsGd | Provider::VGd => {
+                let code = short_code(short_url, self)?;
+                Some(format!("https://{}/stats.php?url={}", self.to_name(), code))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the `Referer` header the provider expects on its requests, if
+    /// any. Some scrape-based providers refuse the requests which do not
+    /// seem to come from their own creation page.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use urlshortener::providers::Provider;
+    ///
+    /// assert_eq!(Provider::TinyUrl.referer(), Some("https://tinyurl.com/"));
+    /// assert_eq!(Provider::IsGd.referer(), None);
+    /// ```
+    pub fn referer(&self) -> Option<&'static str> {
+        match *self {
+            Provider::TinyUrl => Some("https://tinyurl.com/"),
+            _ => None,
+        }
+    }
+
+    /// Returns the rules of the custom aliases of the provider, or `None` if
+    /// it does not support custom aliases.
+    pub fn alias_rules(&self) -> Option<AliasRules> {
+        match *self {
+            Provider::IsGd | Provider::VGd => Some(AliasRules {
+                min_length: 5,
+                max_length: 30,
+                symbols: "_",
+            }),
+            Provider::Kutt { .. } => Some(AliasRules {
+                min_length: 1,
+                max_length: 64,
+                symbols: "_-",
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the short URLs of the provider lead to the long URLs
+    /// with their query strings intact. No built-in provider is known to
+    /// strip or mangle them; a provider found doing so may be flagged with
+    /// `UrlShortenerBuilder::strips_query`.
+    pub fn preserves_query(&self) -> bool {
+        true
+    }
+
+    /// Returns whether the requests to the provider are sent over HTTPS.
+    pub fn is_https(&self) -> bool {
+        match *self {
+            Provider::BamBz
+            | Provider::BitLy { .. }
+            | Provider::BitUrl
+            | Provider::FifoCc
+            | Provider::GooGl { .. }
+            | Provider::HecSu
+            | Provider::IsGd => true,
+            Provider::Kutt { ref host, .. } => host
+                .as_ref()
+                .map(|host| host.starts_with("https://"))
+                .unwrap_or(true),
+            _ => false,
+        }
+    }
+
+    /// Returns the maximum length of the long URLs the provider accepts, in
+    /// bytes, if it is known.
+    pub fn max_input_length(&self) -> Option<usize> {
+        match *self {
+            Provider::IsGd | Provider::VGd => Some(5000),
+            Provider::BitLy { .. } => Some(2048),
+            Provider::Kutt { .. } => Some(2040),
+            _ => None,
+        }
+    }
+
+    /// Returns the documented rate limit of the provider, if any.
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        let (requests, minutes) = match *self {
+            Provider::SirBz => (250, 15),
+            Provider::Rlu => (100, 60),
+            Provider::HecSu => (3000, 24 * 60),
+            Provider::Abv8 => (20, 3),
+            _ => return None,
+        };
+
+        Some(RateLimit {
+            requests,
+            per: Duration::from_secs(minutes * 60),
+        })
+    }
+
+    /// Returns the default timeout of the requests to the provider, which
+    /// accounts for how fast it usually answers.
+    pub fn default_timeout(&self) -> Duration {
+        match *self {
+            Provider::IsGd | Provider::VGd => Duration::from_secs(2),
+            // Has long response sometimes.
+            Provider::TnyIm => Duration::from_secs(10),
+            _ => Duration::from_secs(3),
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = ProviderError;
+
+    /// Parses a provider from its domain name, as returned by
+    /// [`Provider::to_name`]. Only the providers which do not require
+    /// authentication can be parsed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_lowercase().as_str() {
+            "abv8.me" => Provider::Abv8,
+            "bam.bz" => Provider::BamBz,
+            "biturl.top" => Provider::BitUrl,
+            "bmeo.org" => Provider::Bmeo,
+            "fifo.cc" => Provider::FifoCc,
+            "hmm.rs" => Provider::HmmRs,
+            "hec.su" => Provider::HecSu,
+            "is.gd" => Provider::IsGd,
+            "nowlinks.net" => Provider::NowLinks,
+            "phx.co.in" => Provider::PhxCoIn,
+            "psbe.co" => Provider::PsbeCo,
+            "s.coop" => Provider::SCoop,
+            "sirbz.com" => Provider::SirBz,
+            "rlu.ru" => Provider::Rlu,
+            "tinyurl.com" => Provider::TinyUrl,
+            "tiny.ph" => Provider::TinyPh,
+            "tny.im" => Provider::TnyIm,
+            "url-shortener.io" => Provider::UrlShortenerIo,
+            "v.gd" => Provider::VGd,
+            _ => return Err(ProviderError::UnknownProvider),
+        })
+    }
+}
+
+impl std::convert::TryFrom<&str> for Provider {
+    type Error = ProviderError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl AsRef<str> for Provider {
+    fn as_ref(&self) -> &str {
+        self.to_name()
+    }
+}
+
+/// The providers built by a [`ProviderBuilder`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Parameterized {
+    BitLy,
+    GooGl,
+    Kutt,
+}
+
+/// A builder of the providers which take parameters, like credentials or
+/// the host of a self-hosted instance. The parameters are validated when
+/// the provider is built.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{ConfigError, Provider, ProviderBuilder};
+///
+/// let kutt = ProviderBuilder::kutt()
+///     .api_key("MY_API_KEY")
+///     .host("https://kutt.example.com/")
+///     .build();
+/// assert_eq!(
+///     kutt,
+///     Ok(Provider::Kutt {
+///         api_key: "MY_API_KEY".to_owned(),
+///         host: Some("https://kutt.example.com".to_owned()),
+///     })
+/// );
+///
+/// assert_eq!(
+///     ProviderBuilder::bitly().build(),
+///     Err(ConfigError::MissingField("token"))
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct ProviderBuilder {
+    provider: Parameterized,
+    credential: Option<String>,
+    host: Option<String>,
+}
+
+impl ProviderBuilder {
+    fn new(provider: Parameterized) -> ProviderBuilder {
+        ProviderBuilder {
+            provider,
+            credential: None,
+            host: None,
+        }
+    }
+
+    /// Starts building a [`Provider::BitLy`], which requires a token.
+    pub fn bitly() -> ProviderBuilder {
+        ProviderBuilder::new(Parameterized::BitLy)
+    }
+
+    /// Starts building a [`Provider::GooGl`], which requires an api key.
+    pub fn googl() -> ProviderBuilder {
+        ProviderBuilder::new(Parameterized::GooGl)
+    }
+
+    /// Starts building a [`Provider::Kutt`], which requires an api key and
+    /// may be given the host of a self-hosted instance.
+    pub fn kutt() -> ProviderBuilder {
+        ProviderBuilder::new(Parameterized::Kutt)
+    }
+
+    /// Sets the token of the provider.
+    pub fn token<S: Into<String>>(mut self, token: S) -> Self {
+        self.credential = Some(token.into());
+        self
+    }
+
+    /// Sets the api key of the provider, the same as
+    /// [`ProviderBuilder::token`].
+    pub fn api_key<S: Into<String>>(self, api_key: S) -> Self {
+        self.token(api_key)
+    }
+
+    /// Sets the host of a self-hosted instance, like
+    /// `https://kutt.example.com`.
+    pub fn host<S: Into<String>>(mut self, host: S) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Builds the provider.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::MissingField` if the token or the api key is
+    /// missing or blank, and `ConfigError::InvalidField` if the host is not
+    /// an HTTP(S) address without a path, or is given to a provider which
+    /// cannot be self-hosted.
+    pub fn build(self) -> Result<Provider, ConfigError> {
+        let field = match self.provider {
+            Parameterized::BitLy => "token",
+            Parameterized::GooGl | Parameterized::Kutt => "api_key",
+        };
+        let credential = match self.credential {
+            Some(credential) if !credential.trim().is_empty() => credential.trim().to_owned(),
+            _ => return Err(ConfigError::MissingField(field)),
+        };
+        let host = match self.host {
+            Some(host) if self.provider == Parameterized::Kutt => Some(validate_host(&host)?),
+            Some(_) => return Err(ConfigError::InvalidField("host")),
+            None => None,
+        };
+
+        Ok(match self.provider {
+            Parameterized::BitLy => Provider::BitLy { token: credential },
+            Parameterized::GooGl => Provider::GooGl {
+                api_key: credential,
+            },
+            Parameterized::Kutt => Provider::Kutt {
+                api_key: credential,
+                host,
+            },
+        })
+    }
+}
+
+/// Checks that the host is an HTTP(S) address without a path, and returns
+/// it without the trailing slash.
+fn validate_host(host: &str) -> Result<String, ConfigError> {
+    let host = host.trim().trim_end_matches('/');
+    let parsed = url::Url::parse(host).map_err(|_| ConfigError::InvalidField("host"))?;
+    let valid = matches!(parsed.scheme(), "http" | "https")
+        && parsed.has_host()
+        && parsed.path() == "/"
+        && parsed.query().is_none()
+        && parsed.fragment().is_none();
+    if !valid {
+        return Err(ConfigError::InvalidField("host"));
+    }
+
+    Ok(host.to_owned())
 }
 
 parse_noop!(abv8_parse);
@@ -297,7 +1134,7 @@ request!(
 
 parse_noop!(bitly_parse);
 fn bitly_req(url: &str, key: &str) -> req::Request {
-    let encoded_url = form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>();
+    let encoded_url = target::query_encode(url);
     let address = format!(
         "https://api-ssl.bitly.com/v3/shorten?access_token={}&longUrl={}&format=txt",
         key, encoded_url
@@ -325,25 +1162,18 @@ request!(
 
 parse_json_tag!(googl_parse, "id", "");
 fn googl_req(url: &str, key: &str) -> req::Request {
-    req::Request {
-        url: format!("https://www.googleapis.com/urlshortener/v1/url?key={}", key),
-        body: Some(format!(r#"{{"longUrl": "{}"}}"#, url)),
-        content_type: Some(req::ContentType::Json),
-        user_agent: None,
-        headers: None,
-        method: req::Method::Post,
-    }
+    json_request(
+        format!("https://www.googleapis.com/urlshortener/v1/url?key={}", key),
+        "longUrl",
+        url,
+    )
 }
 
 parse_json_tag!(hmmrs_parse, "shortUrl", "");
 fn hmmrs_req(url: &str) -> req::Request {
     req::Request {
-        url: "http:/hmm.rs/x/shorten".to_owned(),
-        body: Some(format!(r#"{{"url": "{}"}}"#, url)),
-        content_type: Some(req::ContentType::Json),
         user_agent: Some(req::UserAgent(FAKE_USER_AGENT.to_owned())),
-        headers: None,
-        method: req::Method::Post,
+        ..json_request("http:/hmm.rs/x/shorten".to_owned(), "url", url)
     }
 }
 
@@ -367,12 +1197,12 @@ fn kutt_req(url: &str, api_key: &str, host: Option<&str>) -> req::Request {
     headers.insert("X-API-Key", api_key.parse().unwrap());
 
     req::Request {
-        url: format!("{}/api/url/submit", host.unwrap_or("https://kutt.it")),
-        body: Some(format!(r#"{{"target": "{}"}}"#, url)),
-        content_type: Some(req::ContentType::Json),
-        user_agent: None,
         headers: Some(headers),
-        method: req::Method::Post,
+        ..json_request(
+            format!("{}/api/url/submit", host.unwrap_or("https://kutt.it")),
+            "target",
+            url,
+        )
     }
 }
 
@@ -475,34 +1305,729 @@ request!(
     req::ContentType::FormUrlEncoded
 );
 
-/// Parses the response from a successful request to a provider into the
-/// URL-shortened string.
-pub fn parse(res: &str, provider: &Provider) -> Result<String, ProviderError> {
-    match *provider {
-        Provider::Abv8 => abv8_parse(res),
-        Provider::BamBz => bambz_parse(res),
-        Provider::BitLy { .. } => bitly_parse(res),
-        Provider::BitUrl => biturl_parse(res),
-        Provider::Bmeo => bmeo_parse(res),
-        Provider::FifoCc => fifocc_parse(res),
-        Provider::GooGl { .. } => googl_parse(res),
-        Provider::HmmRs => hmmrs_parse(res),
-        Provider::HecSu => hecsu_parse(res),
-        Provider::IsGd => isgd_parse(res),
-        Provider::Kutt { .. } => kutt_parse(res),
-        Provider::NowLinks => nowlinks_parse(res),
-        Provider::PhxCoIn => phxcoin_parse(res),
-        Provider::PsbeCo => psbeco_parse(res),
-        Provider::SCoop => scoop_parse(res),
-        Provider::SirBz => sirbz_parse(res),
-        Provider::Rlu => rlu_parse(res),
-        Provider::TinyUrl => tinyurl_parse(res),
-        Provider::TinyPh => tinyph_parse(res),
-        Provider::TnyIm => tnyim_parse(res),
-        Provider::UrlShortenerIo => urlshortenerio_parse(res),
-        Provider::VGd => vgd_parse(res),
-    }
-    .ok_or(ProviderError::Deserialize)
+/// Returns the code of the short URL, like `abc` for `https://is.gd/abc`, if
+/// it can be cleanly isolated: the host must be the provider domain, and the
+/// code the only path segment.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{short_code, Provider};
+///
+/// assert_eq!(short_code("https://is.gd/abc", &Provider::IsGd).as_deref(), Some("abc"));
+/// assert_eq!(short_code("https://v.gd/abc", &Provider::IsGd), None);
+/// ```
+pub fn short_code(short_url: &str, provider: &Provider) -> Option<String> {
+    let parsed = url::Url::parse(short_url).ok()?;
+    let host = target::target_host(short_url)?;
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let own_domain = match provider {
+        Provider::BitLy { .. } => matches!(host, "bit.ly" | "j.mp" | "bitly.com"),
+        _ => host == provider.to_name(),
+    };
+    if !own_domain || parsed.query().is_some() || parsed.fragment().is_some() {
+        return None;
+    }
+
+    let mut segments = parsed.path_segments()?.filter(|s| !s.is_empty());
+    match (segments.next(), segments.next()) {
+        (Some(code), None) => Some(code.to_owned()),
+        _ => None,
+    }
+}
+
+/// Returns the provider which has generated the short URL, judging by its
+/// host. The case and a `www.` prefix of the host are ignored. The
+/// credentials of the returned provider are empty.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{provider_for_short_url, Provider};
+///
+/// assert_eq!(provider_for_short_url("https://is.gd/abc"), Some(Provider::IsGd));
+/// assert_eq!(provider_for_short_url("https://example.com/abc"), None);
+/// ```
+pub fn provider_for_short_url(short_url: &str) -> Option<Provider> {
+    // Unlike a long URL, a short URL without a scheme is not taken.
+    url::Url::parse(short_url).ok()?;
+    let host = target::target_host(short_url)?;
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    match host {
+        "bit.ly" | "j.mp" => Provider::ALL
+            .iter()
+            .find(|p| matches!(p, Provider::BitLy { .. }))
+            .cloned(),
+        _ => Provider::ALL.iter().find(|p| p.to_name() == host).cloned(),
+    }
+}
+
+/// Returns the quality tier of the provider. The providers which require
+/// authentication are in the `Good` tier.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{tier, Provider, ProviderTier};
+///
+/// assert_eq!(tier(&Provider::IsGd), ProviderTier::Good);
+/// assert_eq!(tier(&Provider::Rlu), ProviderTier::Discouraged);
+/// ```
+pub fn tier(provider: &Provider) -> ProviderTier {
+    match *provider {
+        Provider::TnyIm => ProviderTier::Slow,
+        Provider::SirBz
+        | Provider::Rlu
+        | Provider::HecSu
+        | Provider::Abv8
+        | Provider::TinyUrl
+        | Provider::PsbeCo => ProviderTier::Discouraged,
+        Provider::NowLinks | Provider::PhxCoIn => ProviderTier::Preview,
+        _ => ProviderTier::Good,
+    }
+}
+
+/// Reads an ordered list of providers, named by their domains (see
+/// [`Provider::to_name`]), from a configuration holding only that list, in
+/// a plain format close to JSON and TOML arrays. The names may be written
+/// one per line or separated by commas, either bare or double-quoted, and
+/// the whole list may be enclosed in brackets like a JSON array, in which
+/// case they must be quoted. A `#` outside of the quotes starts a comment.
+///
+/// Nothing else is accepted: this is not a TOML or JSON parser, so keys,
+/// table headers and objects are refused.
+///
+/// # Errors
+///
+/// Returns `ConfigError::UnknownProvider` with the offending name if one of
+/// them does not name a provider, and `ConfigError::InvalidSyntax` with the
+/// offending entry if it is not a name or the list is not closed.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{providers_from_config, Provider};
+///
+/// let config = r#"["v.gd", "is.gd"]  # The fallback order."#;
+/// assert_eq!(
+///     providers_from_config(config),
+///     Ok(vec![Provider::VGd, Provider::IsGd])
+/// );
+/// ```
+pub fn providers_from_config(config: &str) -> Result<Vec<Provider>, ConfigError> {
+    let mut entries = String::new();
+    for line in config.lines() {
+        entries.push_str(strip_comment(line));
+        entries.push('\n');
+    }
+
+    let entries = entries.trim();
+    let (entries, bracketed) = match entries.strip_prefix('[') {
+        Some(items) => (
+            items
+                .strip_suffix(']')
+                .ok_or_else(|| ConfigError::InvalidSyntax(entries.to_owned()))?,
+            true,
+        ),
+        None => (entries, false),
+    };
+
+    entries
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let name = match entry.strip_prefix('"').and_then(|e| e.strip_suffix('"')) {
+                Some(name) => name,
+                None if !bracketed => entry,
+                None => return Err(ConfigError::InvalidSyntax(entry.to_owned())),
+            };
+            if name.is_empty() || name.contains(|c: char| "\"'[]{}=:".contains(c)) {
+                return Err(ConfigError::InvalidSyntax(entry.to_owned()));
+            }
+            name.parse()
+                .map_err(|_| ConfigError::UnknownProvider(name.to_owned()))
+        })
+        .collect()
+}
+
+/// Cuts the comment, started by a `#` outside of double quotes, off the line.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Picks a provider at random, each with a probability proportional to its
+/// weight, to spread the load over several providers. Returns `None` if no
+/// provider has a positive weight.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{weighted_random, Provider};
+///
+/// let weights = [(Provider::IsGd, 3), (Provider::VGd, 1), (Provider::BamBz, 0)];
+/// let provider = weighted_random(&weights).unwrap();
+/// assert!(provider == Provider::IsGd || provider == Provider::VGd);
+/// ```
+pub fn weighted_random(weights: &[(Provider, u32)]) -> Option<Provider> {
+    weighted_random_with(weights, XorShift::new())
+}
+
+/// Picks a provider like [`weighted_random`], drawing from the given source
+/// of uniformly distributed random numbers. A seeded source makes the picks
+/// reproducible.
+pub fn weighted_random_with<R: Rng>(weights: &[(Provider, u32)], mut rng: R) -> Option<Provider> {
+    let total: u64 = weights.iter().map(|&(_, weight)| u64::from(weight)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut draw = rng.next_u64() % total;
+    for (provider, weight) in weights {
+        let weight = u64::from(*weight);
+        if draw < weight {
+            return Some(provider.clone());
+        }
+        draw -= weight;
+    }
+
+    None
+}
+
+/// Checks the invariants of the provider tables, returning every violation
+/// found: each variant appears once in [`Provider::ALL`] with a non-empty,
+/// unique domain name, builds a request to an HTTP(S) address, parses its
+/// [`Provider::example_response`] and is parsed back from its name unless
+/// it requires authentication, and each entry of [`PROVIDERS`] appears once.
+///
+/// This catches the wiring mistakes made when adding a provider, and is run
+/// by the unit tests of the crate.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::verify_providers;
+///
+/// assert_eq!(verify_providers(), Ok(()));
+/// ```
+pub fn verify_providers() -> Result<(), Vec<String>> {
+    let mut violations = Vec::new();
+    let mut variants = HashSet::new();
+    let mut names = HashSet::new();
+
+    for provider in Provider::ALL {
+        let name = provider.to_name();
+        if !variants.insert(mem::discriminant(provider)) {
+            violations.push(format!("{:?} appears twice in Provider::ALL", provider));
+        }
+        if name.is_empty() || !name.contains('.') || name.contains(char::is_whitespace) {
+            violations.push(format!("{:?} has an invalid name {:?}", provider, name));
+        } else if !names.insert(name) {
+            violations.push(format!("{} is the name of several providers", name));
+        }
+
+        let address = request("https://example.com/", provider).url;
+        let scheme_ok = match url::Url::parse(&address) {
+            Ok(url) => url.scheme() == "http" || url.scheme() == "https",
+            Err(_) => false,
+        };
+        if !scheme_ok {
+            violations.push(format!(
+                "{} requests an invalid address {:?}",
+                name, address
+            ));
+        }
+        if let Err(e) = parse(provider.example_response(), provider) {
+            violations.push(format!(
+                "{} does not parse its example response: {}",
+                name, e
+            ));
+        }
+        match name.parse::<Provider>() {
+            Ok(ref parsed) if parsed != provider => {
+                violations.push(format!("{} is parsed as {:?}", name, parsed));
+            }
+            Err(_) if !requires_auth(provider) => {
+                violations.push(format!("{} is not parsed from its name", name));
+            }
+            _ => {}
+        }
+    }
+
+    let mut listed = HashSet::new();
+    for provider in PROVIDERS {
+        if !listed.insert(provider) {
+            violations.push(format!("{:?} appears twice in PROVIDERS", provider));
+        }
+        if !variants.contains(&mem::discriminant(provider)) {
+            violations.push(format!("{:?} is missing from Provider::ALL", provider));
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Returns whether the provider carries credentials.
+fn requires_auth(provider: &Provider) -> bool {
+    matches!(
+        *provider,
+        Provider::BitLy { .. } | Provider::GooGl { .. } | Provider::Kutt { .. }
+    )
+}
+
+/// Returns the providers supported by this build, one per variant, in
+/// declaration order. Every provider is currently compiled in, as none of
+/// them is behind a cargo feature, so this is [`Provider::ALL`]; the
+/// providers requiring authentication have empty credentials.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{compiled_providers, Provider};
+///
+/// assert!(compiled_providers().contains(&Provider::IsGd));
+/// ```
+pub fn compiled_providers() -> Vec<Provider> {
+    Provider::ALL.to_vec()
+}
+
+/// Returns the providers of [`PROVIDERS`] which are requested over HTTPS
+/// (see [`Provider::is_https`]), in the same order.
+pub fn https_providers() -> Vec<Provider> {
+    PROVIDERS.iter().filter(|p| p.is_https()).cloned().collect()
+}
+
+/// Returns the providers of [`PROVIDERS`] without any documented rate limit
+/// (see [`Provider::rate_limit`]), in the same order. This is the set to use
+/// for batch jobs.
+pub fn unlimited_providers() -> Vec<Provider> {
+    PROVIDERS
+        .iter()
+        .filter(|p| p.rate_limit().is_none())
+        .cloned()
+        .collect()
+}
+
+/// Returns the providers ordered by the length of their domain names, so that
+/// the ones likely to give the briefest links come first. The providers with
+/// domains of the same length keep their relative order.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{by_domain_length, Provider, PROVIDERS};
+///
+/// let providers = by_domain_length(PROVIDERS);
+/// assert_eq!(providers[..2], [Provider::VGd, Provider::IsGd]);
+/// ```
+pub fn by_domain_length(providers: &[Provider]) -> Vec<Provider> {
+    let mut providers = providers.to_vec();
+    providers.sort_by_key(Provider::domain_length);
+    providers
+}
+
+/// Summarizes the failures of the providers of a fallback run, like the
+/// errors returned by `UrlShortener::generate_robust`, in one line naming
+/// each provider and the reason of its failure.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{explain, Provider, ProviderError};
+///
+/// let errors = [
+///     (Provider::IsGd, ProviderError::Status(429)),
+///     (Provider::VGd, ProviderError::ReadTimeout),
+/// ];
+/// assert_eq!(
+///     explain(&errors),
+///     "is.gd: The provider answered with the status 429; \
+///      v.gd: The provider has not answered in time"
+/// );
+/// ```
+pub fn explain(errors: &[(Provider, ProviderError)]) -> String {
+    if errors.is_empty() {
+        return "No provider has been attempted".to_owned();
+    }
+
+    errors
+        .iter()
+        .map(|(provider, error)| {
+            let reason = error.to_string();
+            format!("{}: {}", provider.to_name(), reason.trim_end_matches('.'))
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Parses the response from a successful request to a provider into the
+/// URL-shortened string.
+///
+/// Returns `ProviderError::Deserialize` if no short URL could be found,
+/// including when the provider has answered with an error message or an HTML
+/// page instead. When the response is a known message of a provider refusing
+/// the long URL, the matching error is returned instead, like
+/// `ProviderError::TargetTooShort` for a URL which is already short. A
+/// leading byte order mark is ignored, and a short URL returned without a
+/// scheme, like `//is.gd/abc` or `/abc`, is completed into an absolute URL
+/// with the scheme and the domain of the provider. Any value which is not
+/// then an absolute HTTP or HTTPS URL with a host is not a short URL.
+///
+/// When a JSON provider answers with an array of link objects, the link of
+/// the first element having one is returned (see [`parse_links`] for all of
+/// them).
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{parse, Provider, ProviderError};
+///
+/// let res = "Error: This service does not accept short URLs";
+/// assert_eq!(parse(res, &Provider::SirBz), Err(ProviderError::TargetTooShort));
+///
+/// let res = r#"[{"url": "https://bam.bz/abc"}, {"url": "https://bam.bz/def"}]"#;
+/// assert_eq!(parse(res, &Provider::BamBz), Ok("https://bam.bz/abc".to_owned()));
+/// ```
+pub fn parse(res: &str, provider: &Provider) -> Result<String, ProviderError> {
+    let res = res.strip_prefix('\u{feff}').unwrap_or(res);
+    if let Some(elements) = json_elements(res, provider) {
+        return elements
+            .into_iter()
+            .find_map(|element| parse(element, provider).ok())
+            .ok_or_else(|| rejection(res).unwrap_or(ProviderError::Deserialize));
+    }
+
+    match *provider {
+        Provider::Abv8 => abv8_parse(res),
+        Provider::BamBz => bambz_parse(res),
+        Provider::BitLy { .. } => bitly_parse(res),
+        Provider::BitUrl => biturl_parse(res),
+        Provider::Bmeo => bmeo_parse(res),
+        Provider::FifoCc => fifocc_parse(res),
+        Provider::GooGl { .. } => googl_parse(res),
+        Provider::HmmRs => hmmrs_parse(res),
+        Provider::HecSu => hecsu_parse(res),
+        Provider::IsGd => isgd_parse(res),
+        Provider::Kutt { .. } => kutt_parse(res),
+        Provider::NowLinks => nowlinks_parse(res),
+        Provider::PhxCoIn => phxcoin_parse(res),
+        Provider::PsbeCo => psbeco_parse(res),
+        Provider::SCoop => scoop_parse(res),
+        Provider::SirBz => sirbz_parse(res),
+        Provider::Rlu => rlu_parse(res),
+        Provider::TinyUrl => tinyurl_parse(res),
+        Provider::TinyPh => tinyph_parse(res),
+        Provider::TnyIm => tnyim_parse(res),
+        Provider::UrlShortenerIo => urlshortenerio_parse(res),
+        Provider::VGd => vgd_parse(res),
+    }
+    .map(|short_url| short_url.trim().to_owned())
+    .filter(|short_url| looks_like_short_url(short_url))
+    .map(|short_url| absolute_short_url(short_url, provider))
+    .filter(|short_url| is_web_url(short_url))
+    .ok_or_else(|| rejection(res).unwrap_or(ProviderError::Deserialize))
+}
+
+/// Parses the response from a successful request to a provider into all the
+/// short URLs it holds: the link of every element, in order, when a JSON
+/// provider answers with an array of link objects, like a batch endpoint
+/// does, or the single link of any other response (see [`parse`]).
+///
+/// # Errors
+///
+/// Returns the error of the first element without a link, so that the links
+/// always match the elements, and `ProviderError::Deserialize` for an empty
+/// array.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{parse_links, Provider};
+///
+/// let res = r#"[{"url": "https://bam.bz/abc"}, {"url": "https://bam.bz/def"}]"#;
+/// assert_eq!(
+///     parse_links(res, &Provider::BamBz),
+///     Ok(vec!["https://bam.bz/abc".to_owned(), "https://bam.bz/def".to_owned()])
+/// );
+/// ```
+pub fn parse_links(res: &str, provider: &Provider) -> Result<Vec<String>, ProviderError> {
+    let res = res.strip_prefix('\u{feff}').unwrap_or(res);
+    match json_elements(res, provider) {
+        Some(elements) if elements.is_empty() => Err(ProviderError::Deserialize),
+        Some(elements) => elements
+            .into_iter()
+            .map(|element| parse(element, provider))
+            .collect(),
+        None => parse(res, provider).map(|short_url| vec![short_url]),
+    }
+}
+
+/// Returns the elements of the response if the provider answers in JSON and
+/// the response is an array.
+fn json_elements<'a>(res: &'a str, provider: &Provider) -> Option<Vec<&'a str>> {
+    if provider.response_format() == ResponseFormat::Json {
+        json_array_elements(res)
+    } else {
+        None
+    }
+}
+
+/// Completes a short URL returned without a scheme (`is.gd/abc`), relative
+/// to the protocol (`//is.gd/abc`) or relative to the host (`/abc`), with
+/// the scheme of the provider requests and its domain. Any other value is
+/// returned as is.
+fn absolute_short_url(short_url: String, provider: &Provider) -> String {
+    let scheme = if provider.is_https() { "https" } else { "http" };
+    if short_url.starts_with("//") {
+        return format!("{}:{}", scheme, short_url);
+    }
+    if short_url.starts_with('/') {
+        return format!("{}://{}{}", scheme, provider.to_name(), short_url);
+    }
+
+    let host = short_url.split(['/', '?', '#']).next().unwrap_or_default();
+    let bare_host = !short_url.contains("://") && host.contains('.') && !host.contains('@');
+    if bare_host {
+        target::default_scheme(&short_url, scheme).into_owned()
+    } else {
+        short_url
+    }
+}
+
+/// Returns whether the short URL is an absolute HTTP or HTTPS URL with a
+/// host, so that it can be used as is.
+fn is_web_url(short_url: &str) -> bool {
+    match url::Url::parse(short_url) {
+        Ok(parsed) => matches!(parsed.scheme(), "http" | "https") && parsed.host().is_some(),
+        Err(_) => false,
+    }
+}
+
+/// The known messages of the providers refusing a long URL, in lowercase,
+/// along with the matching errors.
+const REJECTIONS: &[(&str, ProviderError)] = &[
+    // sirbz.com
+    ("does not accept short urls", ProviderError::TargetTooShort),
+    ("already shortened", ProviderError::TargetTooShort),
+    ("already a short", ProviderError::TargetTooShort),
+    ("only http", ProviderError::UnsupportedScheme),
+    ("unsupported protocol", ProviderError::UnsupportedScheme),
+    ("too long", ProviderError::InputTooLong),
+    // is.gd and v.gd
+    ("please enter a valid url", ProviderError::InvalidInput),
+];
+
+/// Returns the error matching the message of a provider refusing the long
+/// URL, if the response is one of the known messages (see `REJECTIONS`).
+fn rejection(res: &str) -> Option<ProviderError> {
+    let res = res.to_lowercase();
+    REJECTIONS
+        .iter()
+        .find(|(message, _)| res.contains(message))
+        .map(|&(_, error)| error)
+}
+
+/// Returns whether the provider has created a new short link, or returned
+/// the existing one of the same URL, if its response says so.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{link_created, Provider};
+///
+/// let kutt = Provider::Kutt { api_key: "key".to_owned(), host: None };
+/// let res = r#"{"shortUrl": "https://kutt.it/abc", "reuse": true}"#;
+/// assert_eq!(link_created(res, &kutt), Some(false));
+/// assert_eq!(link_created("https://is.gd/abc", &Provider::IsGd), None);
+/// ```
+pub fn link_created(res: &str, provider: &Provider) -> Option<bool> {
+    match *provider {
+        Provider::BitLy { .. } => match json_raw_field(res, "new_hash")? {
+            "1" => Some(true),
+            "0" => Some(false),
+            _ => None,
+        },
+        Provider::Kutt { .. } => match json_raw_field(res, "reuse")? {
+            "true" => Some(false),
+            "false" => Some(true),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns when the provider has created the short link, if its response
+/// says so: the `createdAt` field of `kutt.it`, or the `date` of the YOURLS
+/// responses of `tny.im`, which carries no time zone and is taken as UTC.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use urlshortener::providers::{created_at, Provider};
+///
+/// let kutt = Provider::Kutt { api_key: "key".to_owned(), host: None };
+/// let res = r#"{"shortUrl": "https://kutt.it/abc", "createdAt": "2021-03-04T05:06:07.500Z"}"#;
+/// assert_eq!(
+///     created_at(res, &kutt),
+///     Some(UNIX_EPOCH + Duration::from_millis(1_614_834_367_500))
+/// );
+/// assert_eq!(created_at("https://is.gd/abc", &Provider::IsGd), None);
+/// ```
+pub fn created_at(res: &str, provider: &Provider) -> Option<SystemTime> {
+    let timestamp = match *provider {
+        Provider::Kutt { .. } => json_string_field(res, "createdAt")?,
+        Provider::TnyIm => tnyim_date(res)?,
+        _ => return None,
+    };
+
+    parse_timestamp(&timestamp)
+}
+
+parse_xml_tag!(tnyim_date, "date");
+
+/// Parses a timestamp like `2021-03-04 05:06:07`, `2021-03-04T05:06:07.5Z`
+/// or `2021-03-04T05:06:07+02:00`. A timestamp without offset is taken as
+/// UTC.
+fn parse_timestamp(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    let separators = bytes.len() >= 19
+        && bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && (bytes[10] == b'T' || bytes[10] == b' ')
+        && bytes[13] == b':'
+        && bytes[16] == b':';
+    if !separators {
+        return None;
+    }
+    let number = |start: usize, end: usize| -> Option<i64> {
+        let digits = value.get(start..end)?;
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    if second > 60 {
+        return None;
+    }
+
+    let mut rest = &value[19..];
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        let digits = format!("{:0<9}", &fraction[..len.min(9)]);
+        nanos = digits.parse().ok()?;
+        rest = &fraction[len..];
+    }
+    let offset = match rest {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = rest[1..].replace(':', "");
+            if offset.len() != 4 || !offset.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let (hours, minutes): (i64, i64) =
+                (offset[..2].parse().ok()?, offset[2..].parse().ok()?);
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::new(seconds as u64, nanos))
+}
+
+/// Returns the number of days between 1970-01-01 and the date of the
+/// proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the value of the `<input>` field of an HTML form with the given
+/// name, like the CSRF token a create page expects back, if there is one.
+/// The value is returned as written, without decoding the HTML entities.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::form_input;
+///
+/// let page = r#"<form><input type="hidden" name="csrf" value="t0k3n"></form>"#;
+/// assert_eq!(form_input(page, "csrf"), Some("t0k3n".to_owned()));
+/// assert_eq!(form_input(page, "nonce"), None);
+/// ```
+pub fn form_input(html: &str, name: &str) -> Option<String> {
+    html.split("<input")
+        .skip(1)
+        .map(|tag| tag.split('>').next().unwrap_or(tag))
+        .find(|tag| html_attribute(tag, "name") == Some(name))
+        .and_then(|tag| html_attribute(tag, "value"))
+        .map(str::to_owned)
+}
+
+/// Returns the value of the quoted attribute of an HTML tag.
+fn html_attribute<'a>(tag: &'a str, attribute: &str) -> Option<&'a str> {
+    for quote in &['"', '\''] {
+        let key = format!("{}={}", attribute, quote);
+        let mut rest = tag;
+        while let Some(start) = rest.find(&key) {
+            let preceded_by_space = rest[..start].ends_with(char::is_whitespace);
+            let value = &rest[start + key.len()..];
+            if preceded_by_space {
+                return value.split(*quote).next();
+            }
+            rest = value;
+        }
+    }
+    None
+}
+
+/// Returns whether the parsed value may be a short URL, rejecting the empty
+/// ones, the markup and the sentences, typically from error pages returned
+/// with a successful status, as well as the values with replacement
+/// characters, left by a body which is not valid UTF-8.
+fn looks_like_short_url(value: &str) -> bool {
+    !value.is_empty()
+        && !value.contains(|c: char| {
+            c == '<' || c == '>' || c == char::REPLACEMENT_CHARACTER || c.is_whitespace()
+        })
 }
 
 /// Performs a request to the short link provider.
@@ -523,7 +2048,7 @@ pub fn parse(res: &str, provider: &Provider) -> Result<String, ProviderError> {
 /// }
 /// ```
 pub fn request(url: &str, provider: &Provider) -> req::Request {
-    match *provider {
+    let mut req = match *provider {
         Provider::Abv8 => abv8_req(url),
         Provider::BamBz => bambz_req(url),
         Provider::BitLy { ref token } => bitly_req(url, token),
@@ -549,5 +2074,1166 @@ pub fn request(url: &str, provider: &Provider) -> req::Request {
         Provider::TnyIm => tnyim_req(url),
         Provider::UrlShortenerIo => urlshortenerio_req(url),
         Provider::VGd => vgd_req(url),
+    };
+
+    if let Some(referer) = provider.referer() {
+        req.headers
+            .get_or_insert_with(HeaderMap::new)
+            .insert(REFERER, HeaderValue::from_static(referer));
+    }
+    req
+}
+
+/// Builds a request to the short link provider for a short URL which expires
+/// at the given time.
+///
+/// # Errors
+///
+/// Returns `ProviderError::Unsupported` if the provider does not support link
+/// expiration.
+pub fn request_with_expiry(
+    url: &str,
+    expires_at: SystemTime,
+    provider: &Provider,
+) -> Result<req::Request, ProviderError> {
+    match *provider {
+        Provider::Kutt { .. } => {
+            let seconds = expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+                .as_secs();
+            // Kutt counts the expiration in minutes, at least one.
+            let minutes = (seconds.saturating_add(59) / 60).max(1);
+            let mut req = request(url, provider);
+            json_insert(&mut req, "expire_in", &format!("{} minutes", minutes));
+            Ok(req)
+        }
+        _ => Err(ProviderError::Unsupported),
+    }
+}
+
+/// Builds a request to the short link provider for a short URL with the
+/// given custom alias, which is validated beforehand (see
+/// [`Provider::alias_rules`]).
+///
+/// # Errors
+///
+/// Returns `ProviderError::Unsupported` if the provider does not support
+/// custom aliases, and `ProviderError::InvalidAlias` if it does not accept
+/// this alias.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{request_with_alias, Provider, ProviderError};
+///
+/// let req = request_with_alias("https://rust-lang.org", "rust_lang", &Provider::IsGd).unwrap();
+/// assert!(req.url.ends_with("&shorturl=rust_lang"));
+///
+/// let res = request_with_alias("https://rust-lang.org", "rust-lang", &Provider::IsGd);
+/// assert_eq!(res.err(), Some(ProviderError::InvalidAlias));
+/// ```
+pub fn request_with_alias(
+    url: &str,
+    alias: &str,
+    provider: &Provider,
+) -> Result<req::Request, ProviderError> {
+    provider
+        .alias_rules()
+        .ok_or(ProviderError::Unsupported)?
+        .check(alias)?;
+
+    match *provider {
+        Provider::Kutt { .. } => {
+            let mut req = request(url, provider);
+            json_insert(&mut req, "customurl", alias);
+            Ok(req)
+        }
+        _ => Ok(request_with_params(url, provider, &[("shorturl", alias)])),
+    }
+}
+
+/// Builds a request to the short link provider with extra parameters, which
+/// are appended to the query of `GET` requests and merged into the body of
+/// `POST` ones.
+///
+/// This allows using provider features which are not supported by this
+/// library yet. The parameters are encoded but not validated in any way.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{self, Provider};
+///
+/// let req = providers::request_with_params(
+///     "https://google.com",
+///     &Provider::IsGd,
+///     &[("logstats", "1")],
+/// );
+/// assert!(req.url.ends_with("&logstats=1"));
+/// ```
+pub fn request_with_params<K: AsRef<str>, V: AsRef<str>>(
+    url: &str,
+    provider: &Provider,
+    params: &[(K, V)],
+) -> req::Request {
+    let mut req = request(url, provider);
+    if params.is_empty() {
+        return req;
+    }
+
+    match (req.method, req.content_type) {
+        (req::Method::Post, Some(req::ContentType::Json)) => {
+            for (name, value) in params {
+                json_insert(&mut req, name.as_ref(), value.as_ref());
+            }
+        }
+        (req::Method::Post, _) => {
+            let encoded = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(params)
+                .finish();
+            let body = req.body.get_or_insert_with(String::new);
+            if !body.is_empty() {
+                body.push('&');
+            }
+            body.push_str(&encoded);
+        }
+        (req::Method::Get, _) => {
+            for (name, value) in params {
+                req.url.push(if req.url.contains('?') { '&' } else { '?' });
+                req.url.push_str(&target::query_encode(name.as_ref()));
+                req.url.push('=');
+                req.url.push_str(&target::query_encode(value.as_ref()));
+            }
+        }
+    }
+
+    req
+}
+
+/// Returns the address of the YOURLS API of the provider, if it runs
+/// YOURLS.
+fn yourls_api(provider: &Provider) -> Option<&'static str> {
+    match *provider {
+        Provider::TnyIm => Some("http://tny.im/yourls-api.php"),
+        _ => None,
+    }
+}
+
+/// Builds a YOURLS API request of the action about the short URL.
+fn yourls_req(api: &str, action: &str, short_url: &str) -> req::Request {
+    req::Request {
+        url: format!(
+            "{}?action={}&shorturl={}&format=json",
+            api,
+            action,
+            target::query_encode(short_url)
+        ),
+        body: None,
+        content_type: None,
+        user_agent: None,
+        headers: None,
+        method: req::Method::Get,
+    }
+}
+
+/// Checks the status code of a YOURLS API response, which reports the
+/// unknown short URLs with a `404`.
+fn yourls_check(res: &str) -> Result<(), ProviderError> {
+    match json_raw_field(res, "statusCode").map(|code| code.trim_matches('"').parse::<u16>()) {
+        Some(Ok(code)) if code != 200 => Err(ProviderError::Status(code)),
+        _ => Ok(()),
+    }
+}
+
+/// Builds a request to the provider for the long URL a short URL leads to.
+///
+/// # Errors
+///
+/// Returns `ProviderError::Unsupported` if the provider cannot expand its
+/// short URLs. Only the providers running YOURLS, like `tny.im`, can.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{self, Provider};
+///
+/// let req = providers::request_expand("http://tny.im/abc", &Provider::TnyIm).unwrap();
+/// assert_eq!(
+///     req.url,
+///     "http://tny.im/yourls-api.php?action=expand&shorturl=http%3A%2F%2Ftny.im%2Fabc&format=json"
+/// );
+/// ```
+pub fn request_expand(short_url: &str, provider: &Provider) -> Result<req::Request, ProviderError> {
+    let api = yourls_api(provider).ok_or(ProviderError::Unsupported)?;
+    Ok(yourls_req(api, "expand", short_url))
+}
+
+/// Parses the long URL out of the response to a [`request_expand`]
+/// request.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{parse_expand, Provider};
+///
+/// let res = r#"{"keyword": "abc", "shorturl": "http://tny.im/abc", "longurl": "https://example.com/", "statusCode": 200}"#;
+/// assert_eq!(parse_expand(res, &Provider::TnyIm), Ok("https://example.com/".to_owned()));
+/// ```
+pub fn parse_expand(res: &str, provider: &Provider) -> Result<String, ProviderError> {
+    yourls_api(provider).ok_or(ProviderError::Unsupported)?;
+    yourls_check(res)?;
+    json_string_field(res, "longurl")
+        .filter(|url| looks_like_short_url(url))
+        .ok_or(ProviderError::Deserialize)
+}
+
+/// Builds a request to the provider for the statistics of a short URL.
+///
+/// # Errors
+///
+/// Returns `ProviderError::Unsupported` if the provider does not report the
+/// statistics of its short URLs. Only the providers running YOURLS, like
+/// `tny.im`, do.
+pub fn request_url_stats(
+    short_url: &str,
+    provider: &Provider,
+) -> Result<req::Request, ProviderError> {
+    let api = yourls_api(provider).ok_or(ProviderError::Unsupported)?;
+    Ok(yourls_req(api, "url-stats", short_url))
+}
+
+/// Parses the statistics out of the response to a [`request_url_stats`]
+/// request.
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::providers::{parse_url_stats, Provider, UrlStats};
+///
+/// let res = r#"{"statusCode": 200, "message": "success", "link": {"shorturl": "http://tny.im/abc", "url": "https://example.com/", "clicks": "12"}}"#;
+/// assert_eq!(
+///     parse_url_stats(res, &Provider::TnyIm),
+///     Ok(UrlStats { long_url: "https://example.com/".to_owned(), clicks: 12 })
+/// );
+/// ```
+pub fn parse_url_stats(res: &str, provider: &Provider) -> Result<UrlStats, ProviderError> {
+    yourls_api(provider).ok_or(ProviderError::Unsupported)?;
+    yourls_check(res)?;
+    let long_url = json_string_field(res, "url")
+        .filter(|url| looks_like_short_url(url))
+        .ok_or(ProviderError::Deserialize)?;
+    // YOURLS sends the clicks as a string, but a number is accepted too.
+    let clicks = json_string_field(res, "clicks")
+        .as_deref()
+        .or_else(|| json_raw_field(res, "clicks"))
+        .and_then(|clicks| clicks.parse().ok())
+        .ok_or(ProviderError::Deserialize)?;
+
+    Ok(UrlStats { long_url, clicks })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn from_str() {
+        assert_eq!("is.gd".parse::<Provider>(), Ok(Provider::IsGd));
+        assert_eq!(Provider::try_from("is.gd"), "is.gd".parse::<Provider>());
+        assert_eq!(Provider::try_from("v.gd"), "v.gd".parse::<Provider>());
+        assert_eq!(
+            "goo.gl".parse::<Provider>().unwrap_err(),
+            ProviderError::UnknownProvider
+        );
+
+        for provider in PROVIDERS {
+            assert_eq!(
+                Provider::try_from(provider.to_name()).as_ref(),
+                Ok(provider)
+            );
+        }
+    }
+
+    #[test]
+    fn all() {
+        // Exhaustive, so that a new variant can't be forgotten in `ALL`.
+        fn index(provider: &Provider) -> usize {
+            match *provider {
+                Provider::Abv8 => 0,
+                Provider::BamBz => 1,
+                Provider::BitLy { .. } => 2,
+                Provider::BitUrl => 3,
+                Provider::Bmeo => 4,
+                Provider::FifoCc => 5,
+                Provider::GooGl { .. } => 6,
+                Provider::Kutt { .. } => 7,
+                Provider::HecSu => 8,
+                Provider::HmmRs => 9,
+                Provider::IsGd => 10,
+                Provider::NowLinks => 11,
+                Provider::PhxCoIn => 12,
+                Provider::PsbeCo => 13,
+                Provider::SCoop => 14,
+                Provider::Rlu => 15,
+                Provider::SirBz => 16,
+                Provider::TinyUrl => 17,
+                Provider::TinyPh => 18,
+                Provider::TnyIm => 19,
+                Provider::UrlShortenerIo => 20,
+                Provider::VGd => 21,
+            }
+        }
+
+        let indices: Vec<usize> = Provider::all().iter().map(index).collect();
+        assert_eq!(indices, (0..22).collect::<Vec<_>>());
+
+        let unique: std::collections::HashSet<_> = Provider::all().iter().collect();
+        assert_eq!(unique.len(), Provider::all().len());
+    }
+
+    #[test]
+    fn verify_providers() {
+        if let Err(violations) = super::verify_providers() {
+            panic!("invalid provider tables:\n{}", violations.join("\n"));
+        }
+        assert!(requires_auth(&Provider::ALL[2]));
+        assert!(!requires_auth(&Provider::IsGd));
+    }
+
+    #[test]
+    fn response_formats() {
+        for provider in Provider::all() {
+            let res = provider.example_response();
+            let format = if res.starts_with('{') {
+                ResponseFormat::Json
+            } else if res.starts_with("<button") {
+                ResponseFormat::Html
+            } else if res.starts_with('<') {
+                ResponseFormat::Xml
+            } else {
+                ResponseFormat::PlainText
+            };
+            assert_eq!(provider.response_format(), format, "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn compiled_providers() {
+        let compiled = super::compiled_providers();
+        assert_eq!(compiled, Provider::all());
+        for provider in PROVIDERS {
+            assert!(compiled.contains(provider), "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn domain_length() {
+        assert_eq!(Provider::VGd.domain_length(), 4);
+        assert_eq!(Provider::UrlShortenerIo.domain_length(), 16);
+
+        let ordered = by_domain_length(&[
+            Provider::UrlShortenerIo,
+            Provider::TinyUrl,
+            Provider::VGd,
+            Provider::IsGd,
+        ]);
+        assert_eq!(
+            ordered,
+            [
+                Provider::VGd,
+                Provider::IsGd,
+                Provider::TinyUrl,
+                Provider::UrlShortenerIo
+            ]
+        );
+    }
+
+    #[test]
+    fn unlimited_providers() {
+        let unlimited = super::unlimited_providers();
+
+        for limited in &[
+            Provider::SirBz,
+            Provider::Rlu,
+            Provider::HecSu,
+            Provider::Abv8,
+        ] {
+            assert!(limited.rate_limit().is_some());
+            assert!(!unlimited.contains(limited));
+        }
+        assert_eq!(unlimited.len(), PROVIDERS.len() - 4);
+        assert_eq!(unlimited[..2], [Provider::IsGd, Provider::VGd]);
+    }
+
+    #[test]
+    fn provider_for_short_url() {
+        use super::provider_for_short_url;
+
+        assert_eq!(
+            provider_for_short_url("https://is.gd/abc"),
+            Some(Provider::IsGd)
+        );
+        assert_eq!(
+            provider_for_short_url("http://WWW.V.gd/abc"),
+            Some(Provider::VGd)
+        );
+        assert_eq!(
+            provider_for_short_url("https://kutt.it/abc"),
+            Some(Provider::Kutt {
+                api_key: String::new(),
+                host: None
+            })
+        );
+        assert!(matches!(
+            provider_for_short_url("https://bit.ly/abc"),
+            Some(Provider::BitLy { .. })
+        ));
+        assert_eq!(provider_for_short_url("https://example.com/abc"), None);
+        assert_eq!(provider_for_short_url("is.gd/abc"), None);
+    }
+
+    #[test]
+    fn example_responses() {
+        for provider in Provider::all() {
+            let short_url = parse(provider.example_response(), provider).unwrap();
+            let parsed = url::Url::parse(&short_url).unwrap();
+            assert_eq!(parsed.path(), "/abc", "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn provider_builder() {
+        assert_eq!(
+            ProviderBuilder::bitly().token("abc").build(),
+            Ok(Provider::BitLy {
+                token: "abc".to_owned()
+            })
+        );
+        assert_eq!(
+            ProviderBuilder::googl().api_key(" key ").build(),
+            Ok(Provider::GooGl {
+                api_key: "key".to_owned()
+            })
+        );
+        assert_eq!(
+            ProviderBuilder::kutt().api_key("key").build(),
+            Ok(Provider::Kutt {
+                api_key: "key".to_owned(),
+                host: None
+            })
+        );
+        assert_eq!(
+            ProviderBuilder::kutt()
+                .api_key("key")
+                .host("http://127.0.0.1:8080/")
+                .build(),
+            Ok(Provider::Kutt {
+                api_key: "key".to_owned(),
+                host: Some("http://127.0.0.1:8080".to_owned())
+            })
+        );
+
+        assert_eq!(
+            ProviderBuilder::bitly().build(),
+            Err(ConfigError::MissingField("token"))
+        );
+        assert_eq!(
+            ProviderBuilder::googl().api_key("  ").build(),
+            Err(ConfigError::MissingField("api_key"))
+        );
+        assert_eq!(
+            ProviderBuilder::kutt().host("https://kutt.it").build(),
+            Err(ConfigError::MissingField("api_key"))
+        );
+        for host in &[
+            "kutt.it",
+            "ftp://kutt.it",
+            "https://kutt.it/api",
+            "https://kutt.it?a=b",
+        ] {
+            assert_eq!(
+                ProviderBuilder::kutt().api_key("key").host(*host).build(),
+                Err(ConfigError::InvalidField("host")),
+                "{}",
+                host
+            );
+        }
+        assert_eq!(
+            ProviderBuilder::bitly()
+                .token("abc")
+                .host("https://bit.ly")
+                .build(),
+            Err(ConfigError::InvalidField("host"))
+        );
+    }
+
+    #[test]
+    fn explain() {
+        let errors = [
+            (Provider::IsGd, ProviderError::Status(429)),
+            (Provider::VGd, ProviderError::ConnectTimeout),
+            (Provider::TinyPh, ProviderError::Deserialize),
+        ];
+
+        let report = super::explain(&errors);
+
+        assert_eq!(report.matches("; ").count(), 2);
+        for (provider, error) in &errors {
+            let reason = error.to_string();
+            let entry = format!("{}: {}", provider.to_name(), reason.trim_end_matches('.'));
+            assert!(report.contains(&entry), "{}", report);
+        }
+        assert!(!report.contains('\n'));
+        assert_eq!(super::explain(&[]), "No provider has been attempted");
+    }
+
+    #[test]
+    fn yourls_responses() {
+        let expanded = r#"{"keyword":"abc","shorturl":"http:\/\/tny.im\/abc","longurl":"https:\/\/example.com\/","title":"Example","message":"success","statusCode":200}"#;
+        assert_eq!(
+            parse_expand(expanded, &Provider::TnyIm),
+            Ok("https://example.com/".to_owned())
+        );
+        assert_eq!(
+            parse_expand(expanded, &Provider::IsGd),
+            Err(ProviderError::Unsupported)
+        );
+        assert_eq!(
+            parse_expand(
+                r#"{"statusCode":404,"message":"Error: short URL not found"}"#,
+                &Provider::TnyIm
+            ),
+            Err(ProviderError::Status(404))
+        );
+
+        let stats = r#"{"statusCode":200,"message":"success","link":{"shorturl":"http:\/\/tny.im\/abc","url":"https:\/\/example.com\/","clicks":7}}"#;
+        assert_eq!(
+            parse_url_stats(stats, &Provider::TnyIm),
+            Ok(UrlStats {
+                long_url: "https://example.com/".to_owned(),
+                clicks: 7
+            })
+        );
+        assert_eq!(
+            parse_url_stats(r#"{"statusCode":200,"link":{}}"#, &Provider::TnyIm),
+            Err(ProviderError::Deserialize)
+        );
+        assert_eq!(
+            request_url_stats("http://tny.im/abc", &Provider::TnyIm).unwrap().url,
+            "http://tny.im/yourls-api.php?action=url-stats&shorturl=http%3A%2F%2Ftny.im%2Fabc&format=json"
+        );
+        assert!(request_expand("https://is.gd/abc", &Provider::IsGd).is_err());
+    }
+
+    #[test]
+    fn providers_from_config() {
+        use super::{providers_from_config, ConfigError};
+
+        let expected = vec![Provider::TinyPh, Provider::IsGd, Provider::VGd];
+        for list in &[
+            r#"["tiny.ph", "is.gd", "v.gd"]"#,
+            "[\n  \"tiny.ph\",\n  \"is.gd\", # the best one\n  \"v.gd\",\n]",
+            "tiny.ph\nis.gd\n\nv.gd\n",
+            "# Providers\ntiny.ph, \"is.gd\" # the best one\nv.gd",
+        ] {
+            assert_eq!(
+                providers_from_config(list),
+                Ok(expected.clone()),
+                "{}",
+                list
+            );
+        }
+
+        assert_eq!(
+            providers_from_config(r#"["is.gd", "goo.gle"]"#),
+            Err(ConfigError::UnknownProvider("goo.gle".to_owned()))
+        );
+        assert_eq!(
+            providers_from_config(r#"["is.gd#v.gd"]"#),
+            Err(ConfigError::UnknownProvider("is.gd#v.gd".to_owned()))
+        );
+        for (list, entry) in &[
+            ("providers = [\"is.gd\"]", "providers = [\"is.gd\"]"),
+            ("[providers]\nis.gd", "[providers]\nis.gd"),
+            ("[providers]", "providers"),
+            ("[[\"is.gd\"]]", "[\"is.gd\"]"),
+            ("{\"order\": \"is.gd\"}", "{\"order\": \"is.gd\"}"),
+            ("[\"is.gd\"", "[\"is.gd\""),
+            ("[\"\"]", "\"\""),
+        ] {
+            assert_eq!(
+                providers_from_config(list),
+                Err(ConfigError::InvalidSyntax((*entry).to_owned())),
+                "{}",
+                list
+            );
+        }
+        assert_eq!(providers_from_config("[]"), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn weighted_random() {
+        use super::weighted_random_with;
+
+        let weights = [
+            (Provider::IsGd, 1),
+            (Provider::VGd, 3),
+            (Provider::BamBz, 0),
+            (Provider::TinyPh, 6),
+        ];
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut rng = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut counts = [0u32; 4];
+        for _ in 0..10_000 {
+            let provider = weighted_random_with(&weights, &mut rng).unwrap();
+            counts[weights.iter().position(|(p, _)| *p == provider).unwrap()] += 1;
+        }
+
+        assert_eq!(counts[2], 0);
+        for (&count, &(_, weight)) in counts.iter().zip(&weights) {
+            let expected = 1000 * weight;
+            assert!(
+                count.max(expected) - count.min(expected) <= 200,
+                "{:?}",
+                counts
+            );
+        }
+        assert_eq!(weighted_random_with(&[(Provider::IsGd, 0)], rng), None);
+        assert_eq!(super::weighted_random(&[]), None);
+    }
+
+    #[test]
+    fn rejections() {
+        for (res, provider, error) in &[
+            (
+                r#"{"error": "This service does not accept short URLs"}"#,
+                Provider::SirBz,
+                ProviderError::TargetTooShort,
+            ),
+            (
+                "Error: Sorry, that URL is already shortened",
+                Provider::IsGd,
+                ProviderError::TargetTooShort,
+            ),
+            (
+                "Error: Please enter a valid URL to shorten",
+                Provider::VGd,
+                ProviderError::InvalidInput,
+            ),
+            (
+                "Error: Only HTTP and HTTPS links are allowed",
+                Provider::Rlu,
+                ProviderError::UnsupportedScheme,
+            ),
+            (
+                "Error: Sorry, the URL you entered is too long",
+                Provider::IsGd,
+                ProviderError::InputTooLong,
+            ),
+        ] {
+            assert_eq!(parse(res, provider), Err(*error), "{}", res);
+        }
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let kutt = Provider::Kutt {
+            api_key: String::new(),
+            host: None,
+        };
+        assert_eq!(
+            parse("\u{feff}{\"shortUrl\": \"https://kutt.it/abc\"}", &kutt),
+            Ok("https://kutt.it/abc".to_owned())
+        );
+        assert_eq!(
+            parse("\u{feff}https://is.gd/abc", &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+
+        let invalid = String::from_utf8_lossy(b"https://is.gd/ab\xff");
+        assert_eq!(
+            parse(&invalid, &Provider::IsGd),
+            Err(ProviderError::Deserialize)
+        );
+    }
+
+    #[test]
+    fn json_escapes() {
+        let res = r#"{"hash": "a\/b", "short": "http:\/\/tiny.ph\/abc"}"#;
+        assert_eq!(
+            json_string_field(res, "short").as_deref(),
+            Some("http://tiny.ph/abc")
+        );
+        assert_eq!(
+            parse(res, &Provider::TinyPh),
+            Ok("http://tiny.ph/a/b".to_owned())
+        );
+        assert_eq!(
+            parse(
+                r#"{"short":"http:\/\/bmeo.org\/\u00e9t\u00e9"}"#,
+                &Provider::Bmeo
+            ),
+            Ok("http://bmeo.org/été".to_owned())
+        );
+        assert_eq!(
+            parse(r#"{"url": "http:\/\/bam.bz\/a,b\"c\""}"#, &Provider::BamBz),
+            Ok(r#"http://bam.bz/a,b"c""#.to_owned())
+        );
+        assert_eq!(
+            json_string_field(r#"{"s": "\ud83e\udd80"}"#, "s").as_deref(),
+            Some("🦀")
+        );
+        assert_eq!(json_string_field(r#"{"short": 1}"#, "short"), None);
+        assert_eq!(json_string_field(r#"{"short": "abc"#, "short"), None);
+    }
+
+    #[test]
+    fn link_arrays() {
+        let res = r#"[{"url": "https://bam.bz/abc"}, {"url": "https://bam.bz/def"}]"#;
+        assert_eq!(
+            parse(res, &Provider::BamBz),
+            Ok("https://bam.bz/abc".to_owned())
+        );
+        assert_eq!(
+            parse_links(res, &Provider::BamBz),
+            Ok(vec![
+                "https://bam.bz/abc".to_owned(),
+                "https://bam.bz/def".to_owned()
+            ])
+        );
+
+        // The first element having a link is picked.
+        let res =
+            r#" [{"error": "[bad, url]"}, {"url": "https://bam.bz/def", "tags": ["a", "b"]}] "#;
+        assert_eq!(
+            parse(res, &Provider::BamBz),
+            Ok("https://bam.bz/def".to_owned())
+        );
+        assert_eq!(
+            parse_links(res, &Provider::BamBz),
+            Err(ProviderError::Deserialize)
+        );
+
+        assert_eq!(
+            json_array_elements(r#"cb([{"a": "x\",]"}, 2, [3, 4]]);"#),
+            Some(vec![r#"{"a": "x\",]"}"#, "2", "[3, 4]"])
+        );
+        assert_eq!(json_array_elements("[ ]"), Some(vec![]));
+        assert_eq!(json_array_elements(r#"{"url": "[]"}"#), None);
+        assert_eq!(
+            parse("[]", &Provider::BamBz),
+            Err(ProviderError::Deserialize)
+        );
+        assert_eq!(
+            parse_links("[]", &Provider::BamBz),
+            Err(ProviderError::Deserialize)
+        );
+        assert_eq!(
+            parse_links(r#"{"url": "https://bam.bz/abc"}"#, &Provider::BamBz),
+            Ok(vec!["https://bam.bz/abc".to_owned()])
+        );
+
+        // Only the JSON providers read arrays.
+        let res = r#"["https://is.gd/abc", "https://is.gd/def"]"#;
+        assert_eq!(
+            parse_links(res, &Provider::IsGd),
+            Err(ProviderError::Deserialize)
+        );
+    }
+
+    #[test]
+    fn jsonp() {
+        for provider in Provider::all() {
+            let res = provider.example_response();
+            if !res.starts_with('{') {
+                continue;
+            }
+            let wrapped = format!("callback({});", res);
+            assert_eq!(parse(&wrapped, provider), parse(res, provider));
+        }
+
+        let res = r#"/**/ jQuery_123.cb ( {"short": "http://bmeo.org/abc"} ) ;"#;
+        assert_eq!(strip_jsonp(res), r#"{"short": "http://bmeo.org/abc"}"#);
+        assert_eq!(
+            parse(res, &Provider::Bmeo),
+            Ok("http://bmeo.org/abc".to_owned())
+        );
+        assert_eq!(
+            super::link_created(r#"cb({"reuse": false})"#, &Provider::ALL[7]),
+            Some(true)
+        );
+        for res in &[
+            "https://is.gd/abc",
+            "{\"a\": \"(b)\"}",
+            "bad name({})",
+            "cb({}",
+        ] {
+            assert_eq!(strip_jsonp(res), *res);
+        }
+    }
+
+    #[test]
+    fn relative_short_urls() {
+        for res in &["//is.gd/abc", "is.gd/abc", "/abc", "https://is.gd/abc"] {
+            assert_eq!(
+                parse(res, &Provider::IsGd),
+                Ok("https://is.gd/abc".to_owned())
+            );
+        }
+        assert_eq!(
+            parse(r#"{"short": "bmeo.org/abc?x=1"}"#, &Provider::Bmeo),
+            Ok("http://bmeo.org/abc?x=1".to_owned())
+        );
+        assert_eq!(
+            parse("//s.coop/abc", &Provider::SCoop),
+            Ok("http://s.coop/abc".to_owned())
+        );
+        let kutt = Provider::Kutt {
+            api_key: String::new(),
+            host: Some("http://127.0.0.1:8080".to_owned()),
+        };
+        assert_eq!(
+            parse(r#"{"shortUrl": "/abc"}"#, &kutt),
+            Ok("http://127.0.0.1:8080/abc".to_owned())
+        );
+        for res in &[
+            "mailto:a@b.org",
+            "abc",
+            "RATE_LIMITED",
+            "ftp://is.gd/abc",
+            "javascript:alert(1)",
+            "http://",
+        ] {
+            assert_eq!(
+                parse(res, &Provider::IsGd),
+                Err(ProviderError::Deserialize),
+                "{}",
+                res
+            );
+        }
+    }
+
+    #[test]
+    fn created_at() {
+        let at = |seconds: u64, millis: u64| {
+            Some(UNIX_EPOCH + Duration::from_secs(seconds) + Duration::from_millis(millis))
+        };
+
+        assert_eq!(parse_timestamp("1970-01-01T00:00:00Z"), at(0, 0));
+        assert_eq!(parse_timestamp("2000-02-29 12:00:00"), at(951_825_600, 0));
+        assert_eq!(
+            parse_timestamp("2021-03-04T07:06:07.25+02:00"),
+            at(1_614_834_367, 250)
+        );
+        assert_eq!(
+            parse_timestamp("2021-03-04T00:06:07-0500"),
+            at(1_614_834_367, 0)
+        );
+        for invalid in &[
+            "",
+            "2021-13-04 05:06:07",
+            "2021-03-04",
+            "2021-03-04T05:06:07 UTC",
+            "1969-12-31T23:59:59Z",
+            "2021-03-04T05:06:07.Z",
+        ] {
+            assert_eq!(parse_timestamp(invalid), None, "{}", invalid);
+        }
+
+        let res = "<result><url><keyword>abc</keyword><date>2021-03-04 05:06:07</date>\
+                   </url><shorturl>http://tny.im/abc</shorturl></result>";
+        assert_eq!(
+            super::created_at(res, &Provider::TnyIm),
+            at(1_614_834_367, 0)
+        );
+        assert_eq!(
+            super::created_at(Provider::TnyIm.example_response(), &Provider::TnyIm),
+            None
+        );
+    }
+
+    #[test]
+    fn form_input() {
+        let page = r#"<form method="post">
+            <input type="text" name="url">
+            <input type='hidden' data-name="csrf" name='csrf' value='a"b'>
+            <input type="hidden" name="nonce" value="n0nce"/>
+        </form>"#;
+        assert_eq!(super::form_input(page, "csrf"), Some("a\"b".to_owned()));
+        assert_eq!(super::form_input(page, "nonce"), Some("n0nce".to_owned()));
+        assert_eq!(super::form_input(page, "url"), None);
+        assert_eq!(super::form_input(page, "name"), None);
+    }
+
+    #[test]
+    fn percent_encoded() {
+        assert_eq!(
+            parse(r#"{"url": "https%3A%2F%2Fbam.bz%2Fabc"}"#, &Provider::BamBz),
+            Ok("https://bam.bz/abc".to_owned())
+        );
+        assert_eq!(
+            parse(r#"{"url": "https://bam.bz/a%20b"}"#, &Provider::BamBz),
+            Ok("https://bam.bz/a%20b".to_owned())
+        );
+    }
+
+    #[test]
+    fn preview_url() {
+        assert_eq!(
+            Provider::IsGd.preview_url("https://is.gd/abc"),
+            Some("https://is.gd/abc-".to_owned())
+        );
+        assert_eq!(
+            Provider::VGd.preview_url("https://v.gd/abc/"),
+            Some("https://v.gd/abc-".to_owned())
+        );
+        assert_eq!(
+            Provider::TinyUrl.preview_url("https://tinyurl.com/abc"),
+            Some("https://preview.tinyurl.com/abc".to_owned())
+        );
+        assert_eq!(Provider::Rlu.preview_url("http://rlu.ru/abc"), None);
+    }
+
+    #[test]
+    fn alias() {
+        let req = request_with_alias("http://example.com", "Valid_alias1", &Provider::VGd).unwrap();
+        assert_eq!(
+            req.url,
+            "http://v.gd/create.php?format=simple&url=http%3A%2F%2Fexample.com&shorturl=Valid_alias1"
+        );
+        let kutt = Provider::Kutt {
+            api_key: String::new(),
+            host: None,
+        };
+        let req = request_with_alias("http://example.com", "my-link", &kutt).unwrap();
+        assert!(req.body.unwrap().ends_with(r#", "customurl": "my-link"}"#));
+
+        for alias in &["bad alias", "bad-alias", "ünïcode", "abcd", &"a".repeat(31)] {
+            let res = request_with_alias("http://example.com", alias, &Provider::IsGd);
+            assert_eq!(res.err(), Some(ProviderError::InvalidAlias), "{}", alias);
+        }
+        let res = request_with_alias("http://example.com", &"a".repeat(30), &Provider::IsGd);
+        assert!(res.is_ok());
+        let res = request_with_alias("http://example.com", "alias", &Provider::BamBz);
+        assert_eq!(res.err(), Some(ProviderError::Unsupported));
+    }
+
+    #[test]
+    fn error_pages() {
+        let page = "<!DOCTYPE html>\n<html><head><title>Error</title></head>\n\
+                    <body><h1>Something went wrong</h1></body></html>";
+        for provider in &[
+            Provider::IsGd,
+            Provider::TnyIm,
+            Provider::BamBz,
+            Provider::TinyUrl,
+        ] {
+            assert_eq!(
+                parse(page, provider),
+                Err(ProviderError::Deserialize),
+                "{:?}",
+                provider
+            );
+        }
+        assert_eq!(
+            parse(
+                "Error: Please enter a valid URL to shorten",
+                &Provider::IsGd
+            ),
+            Err(ProviderError::InvalidInput)
+        );
+        assert_eq!(parse("", &Provider::VGd), Err(ProviderError::Deserialize));
+        assert_eq!(
+            parse("https://is.gd/abc\n", &Provider::IsGd),
+            Ok("https://is.gd/abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn link_created() {
+        let kutt = Provider::Kutt {
+            api_key: String::new(),
+            host: None,
+        };
+        let bitly = Provider::BitLy {
+            token: String::new(),
+        };
+
+        let reused = r#"{"shortUrl": "https://kutt.it/abc", "reuse": true }"#;
+        assert_eq!(super::link_created(reused, &kutt), Some(false));
+        let created = r#"{"reuse":false,"shortUrl": "https://kutt.it/abc"}"#;
+        assert_eq!(super::link_created(created, &kutt), Some(true));
+        assert_eq!(super::link_created(r#"{"shortUrl": "x"}"#, &kutt), None);
+        let existing = r#"{"data": {"url": "http://bit.ly/abc", "new_hash": 0}}"#;
+        assert_eq!(super::link_created(existing, &bitly), Some(false));
+        assert_eq!(
+            super::link_created("https://is.gd/abc", &Provider::IsGd),
+            None
+        );
+    }
+
+    #[test]
+    fn https_providers() {
+        let https = super::https_providers();
+
+        assert_eq!(https[0], Provider::IsGd);
+        assert!(https.contains(&Provider::BamBz));
+        assert!(!https.contains(&Provider::VGd));
+        assert!(!https.contains(&Provider::Rlu));
+        assert!(https.iter().all(Provider::is_https));
+    }
+
+    #[test]
+    fn is_https_matches_requests() {
+        let kutt = |host: Option<&str>| Provider::Kutt {
+            api_key: "key".to_owned(),
+            host: host.map(ToOwned::to_owned),
+        };
+        let parameterized = [
+            Provider::BitLy {
+                token: "token".to_owned(),
+            },
+            Provider::GooGl {
+                api_key: "key".to_owned(),
+            },
+            kutt(None),
+            kutt(Some("https://kutt.example.com")),
+            kutt(Some("http://kutt.example.com")),
+        ];
+
+        for provider in PROVIDERS.iter().chain(&parameterized) {
+            let url = request("", provider).url;
+            assert_eq!(provider.is_https(), url.starts_with("https://"), "{}", url);
+        }
+    }
+
+    #[test]
+    fn tiers_follow_providers_order() {
+        let tiers: Vec<_> = PROVIDERS.iter().map(super::tier).collect();
+        let mut sorted = tiers.clone();
+        sorted.sort();
+        assert_eq!(tiers, sorted);
+    }
+
+    #[test]
+    fn short_code() {
+        use super::short_code;
+
+        assert_eq!(
+            short_code("https://is.gd/abc", &Provider::IsGd).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            short_code("https://www.is.gd/abc/", &Provider::IsGd).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(short_code("https://is.gd/", &Provider::IsGd), None);
+        assert_eq!(short_code("https://is.gd/a/b", &Provider::IsGd), None);
+        assert_eq!(short_code("https://is.gd/abc?x=1", &Provider::IsGd), None);
+        assert_eq!(short_code("https://example.com/abc", &Provider::IsGd), None);
+    }
+
+    #[test]
+    fn form_body() {
+        for provider in Provider::all() {
+            let req = request("http://example.com/?a=1&b=2", provider);
+            if req.body.is_none() || matches!(req.content_type, Some(req::ContentType::Json)) {
+                continue;
+            }
+
+            assert!(
+                matches!(req.content_type, Some(req::ContentType::FormUrlEncoded)),
+                "{:?}",
+                provider
+            );
+            let body = req.body.unwrap();
+            let pairs: Vec<_> = form_urlencoded::parse(body.as_bytes()).collect();
+            assert_eq!(pairs.len(), 1, "{:?}", provider);
+            assert_eq!(pairs[0].1, "http://example.com/?a=1&b=2");
+        }
+    }
+
+    #[test]
+    fn json_body() {
+        let kutt = Provider::Kutt {
+            api_key: "key".to_owned(),
+            host: None,
+        };
+        let kutt_req = request("http://example.com/?q=\"a\"\\b\n", &kutt);
+
+        assert!(matches!(
+            kutt_req.content_type,
+            Some(req::ContentType::Json)
+        ));
+        assert_eq!(
+            kutt_req.body.as_deref(),
+            Some(r#"{"target": "http://example.com/?q=\"a\"\\b\n"}"#)
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let kutt = Provider::Kutt {
+            api_key: "key".to_owned(),
+            host: None,
+        };
+        let expires_at = SystemTime::now() + Duration::from_secs(60 * 60);
+
+        let kutt_req = request_with_expiry("http://example.com", expires_at, &kutt).unwrap();
+        assert_eq!(
+            kutt_req.body.as_deref(),
+            Some(r#"{"target": "http://example.com", "expire_in": "60 minutes"}"#)
+        );
+
+        let res = request_with_expiry("http://example.com", expires_at, &Provider::IsGd);
+        assert_eq!(res.unwrap_err(), ProviderError::Unsupported);
+    }
+
+    #[test]
+    fn extra_params() {
+        let isgd_req =
+            request_with_params("http://example.com", &Provider::IsGd, &[("a b", "c&d")]);
+        assert_eq!(
+            isgd_req.url,
+            "https://is.gd/create.php?format=simple&url=http%3A%2F%2Fexample.com&a%20b=c%26d"
+        );
+
+        let bambz_req =
+            request_with_params("http://example.com", &Provider::BamBz, &[("a", "b c")]);
+        assert_eq!(
+            bambz_req.body.as_deref(),
+            Some("target=http%3A%2F%2Fexample.com&a=b+c")
+        );
+
+        let kutt = Provider::Kutt {
+            api_key: "key".to_owned(),
+            host: None,
+        };
+        let kutt_req = request_with_params("http://example.com", &kutt, &[("reuse", "true")]);
+        assert_eq!(
+            kutt_req.body.as_deref(),
+            Some(r#"{"target": "http://example.com", "reuse": "true"}"#)
+        );
+    }
+
+    #[test]
+    fn as_ref() {
+        assert_eq!(Provider::IsGd.as_ref(), "is.gd");
+        assert_eq!(
+            Provider::Kutt {
+                api_key: "key".to_owned(),
+                host: Some("https://example.com".to_owned()),
+            }
+            .as_ref(),
+            "example.com"
+        );
     }
 }
@@ -1,6 +1,6 @@
 #[cfg(feature = "client")]
 use reqwest::{
-    blocking::{Client, Response},
+    blocking::{Client, RequestBuilder, Response},
     header::{self, HeaderMap},
 };
 
@@ -50,6 +50,11 @@ pub struct Request {
 impl Request {
     /// Sends the request and returns the response.
     pub fn execute(&self, client: &Client) -> Result<Response, reqwest::Error> {
+        self.build(client).send()
+    }
+
+    /// Prepares the request for sending without sending it.
+    pub fn build(&self, client: &Client) -> RequestBuilder {
         let mut builder = match self.method {
             Method::Get => client.get(&self.url),
             Method::Post => client.post(&self.url),
@@ -76,6 +81,6 @@ impl Request {
             builder = builder.body(body);
         }
 
-        builder.send()
+        builder
     }
 }
@@ -0,0 +1,99 @@
+//! Sources of the random numbers behind the random picks of providers (see
+//! [`crate::providers::weighted_random_with`]) and the random aliases.
+//!
+//! The random features draw from an [`Rng`], which is [`XorShift`] seeded
+//! at random unless another one is given. A seeded source makes them
+//! reproducible, for example in tests.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A source of uniformly distributed random numbers.
+pub trait Rng {
+    /// Returns the next random number.
+    fn next_u64(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> Rng for F {
+    fn next_u64(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// A fast, non-cryptographic pseudo-random generator (xorshift64*).
+///
+/// # Example
+///
+/// ```rust
+/// use urlshortener::rng::{Rng, XorShift};
+///
+/// let (mut a, mut b) = (XorShift::with_seed(42), XorShift::with_seed(42));
+/// assert_eq!(a.next_u64(), b.next_u64());
+/// ```
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Creates a generator from a random seed.
+    pub fn new() -> XorShift {
+        XorShift::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    /// Creates a generator from the given seed. The same seed always gives
+    /// the same numbers.
+    pub fn with_seed(seed: u64) -> XorShift {
+        XorShift {
+            // The xorshift state must not be zero.
+            state: seed | 1,
+        }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> Self {
+        XorShift::new()
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers<R: Rng>(mut rng: R) -> Vec<u64> {
+        (0..8).map(|_| rng.next_u64()).collect()
+    }
+
+    #[test]
+    fn seeded() {
+        assert_eq!(
+            numbers(XorShift::with_seed(7)),
+            numbers(XorShift::with_seed(7))
+        );
+        assert_ne!(
+            numbers(XorShift::with_seed(7)),
+            numbers(XorShift::with_seed(8))
+        );
+        assert!(numbers(XorShift::with_seed(0)).iter().all(|&n| n != 0));
+    }
+
+    #[test]
+    fn closure() {
+        let mut n = 0;
+        let counter = || {
+            n += 1;
+            n
+        };
+        assert_eq!(numbers(counter), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}